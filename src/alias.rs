@@ -0,0 +1,64 @@
+extern crate std;
+
+use std::io::Write;
+use exec;
+use error::Result;
+use state::ShellState;
+
+// Quotes a value so that it reads back as the same word
+pub fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+impl ShellState {
+    // alias [name[=value] ...]
+    pub fn alias(&mut self, args: &[String]) -> Result<i32> {
+        if args.is_empty() {
+            let mut names: Vec<&String> = self.aliases.keys().collect();
+            names.sort();
+            for name in names {
+                writeln!(
+                    exec::output(),
+                    "alias {}={}",
+                    name,
+                    quote(&self.aliases[name])
+                )?;
+            }
+            return Ok(0);
+        }
+        let mut status = 0;
+        for arg in args {
+            match arg.split_once('=') {
+                Some((name, value)) => {
+                    self.aliases.insert(name.to_owned(), value.to_owned());
+                }
+                None => {
+                    match self.aliases.get(arg) {
+                        Some(value) => writeln!(exec::output(), "alias {}={}", arg, quote(value))?,
+                        None => {
+                            self.report(&format!("alias: {}: not found", arg));
+                            status = 1;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(status)
+    }
+
+    // unalias -a | name ...
+    pub fn unalias(&mut self, args: &[String]) -> Result<i32> {
+        if args.first().is_some_and(|a| a == "-a") {
+            self.aliases.clear();
+            return Ok(0);
+        }
+        let mut status = 0;
+        for arg in args {
+            if self.aliases.remove(arg).is_none() {
+                self.report(&format!("unalias: {}: not found", arg));
+                status = 1;
+            }
+        }
+        Ok(status)
+    }
+}
@@ -0,0 +1,433 @@
+extern crate std;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+use exec;
+use error;
+use error::ShellError;
+use parser;
+use state::ShellState;
+
+// A variable holding several values, by index or, once declared with -A, by key. Indexed
+// arrays can have gaps, as unset leaves them.
+#[derive(Clone, Debug)]
+pub enum Array {
+    Indexed(BTreeMap<usize, String>),
+    Associative(BTreeMap<String, String>),
+}
+
+impl Array {
+    pub fn from_values(values: Vec<String>) -> Array {
+        Array::Indexed(values.into_iter().enumerate().collect())
+    }
+
+    pub fn values(&self) -> Vec<String> {
+        match *self {
+            Array::Indexed(ref elements) => elements.values().cloned().collect(),
+            Array::Associative(ref elements) => elements.values().cloned().collect(),
+        }
+    }
+
+    // The indices or keys that have values, for ${!name[@]}
+    pub fn keys(&self) -> Vec<String> {
+        match *self {
+            Array::Indexed(ref elements) => elements.keys().map(|i| i.to_string()).collect(),
+            Array::Associative(ref elements) => elements.keys().cloned().collect(),
+        }
+    }
+
+    // What $name gives for an array
+    pub fn first(&self) -> Option<String> {
+        match *self {
+            Array::Indexed(ref elements) => elements.get(&0).cloned(),
+            Array::Associative(ref elements) => elements.get("0").cloned(),
+        }
+    }
+
+    // One past the highest index, where += carries on from
+    fn next_index(&self) -> usize {
+        match *self {
+            Array::Indexed(ref elements) => elements.keys().next_back().map_or(0, |i| i + 1),
+            Array::Associative(_) => 0,
+        }
+    }
+}
+
+// The value of an assignment: a word, or the elements of name=(...), which may set their own
+// index or key with [subscript]=value
+pub enum Value {
+    Scalar(String),
+    List(Vec<(Option<String>, String)>),
+}
+
+// name=value, name+=value, name[subscript]=value or name=(...), with everything expanded
+pub struct Assignment {
+    pub name: String,
+    pub subscript: Option<String>,
+    pub append: bool,
+    pub value: Value,
+}
+
+impl Assignment {
+    // The name and value that a command's environment gets, where only strings can go
+    pub fn pair(&self) -> (String, String) {
+        let value = match self.value {
+            Value::Scalar(ref value) => value.clone(),
+            Value::List(ref elements) => {
+                elements.iter().map(|e| e.1.as_str()).collect::<Vec<_>>().join(" ")
+            }
+        };
+        (self.name.clone(), value)
+    }
+}
+
+impl fmt::Display for Assignment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(ref subscript) = self.subscript {
+            write!(f, "[{}]", subscript)?;
+        }
+        write!(f, "{}=", if self.append { "+" } else { "" })?;
+        match self.value {
+            Value::Scalar(ref value) => write!(f, "{}", value),
+            Value::List(ref elements) => {
+                let elements: Vec<String> = elements.iter()
+                    .map(|(key, value)| match *key {
+                        Some(ref key) => format!("[{}]={}", key, value),
+                        None => value.clone(),
+                    })
+                    .collect();
+                write!(f, "({})", elements.join(" "))
+            }
+        }
+    }
+}
+
+// Splits an assignment word into its name, subscript and whether it appends, and its value as
+// written
+pub fn split_assignment(word: &str) -> Option<(&str, Option<&str>, bool, &str)> {
+    let equals = parser::assignment(word)?;
+    let (target, value) = (&word[..equals], &word[equals + 1..]);
+    let (target, append) = match target.strip_suffix('+') {
+        Some(target) => (target, true),
+        None => (target, false),
+    };
+    match target.find('[') {
+        Some(open) => Some((&target[..open], Some(&target[open + 1..target.len() - 1]), append, value)),
+        None => Some((target, None, append, value)),
+    }
+}
+
+// Quotes a value in double quotes, as declare -p prints them
+fn double_quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        if "\"\\$`".contains(c) {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl ShellState {
+    // Expands an assignment word. The elements of name=(...) are split and globbed like the
+    // arguments of a command, the other parts are expanded as single words.
+    pub fn expand_assignment(&mut self, word: &str) -> Option<Assignment> {
+        let (name, subscript, append, value) = split_assignment(word)?;
+        let subscript = subscript.map(|s| self.expand_word(s));
+        let value = match value.strip_prefix('(').and_then(|v| v.strip_suffix(')')) {
+            Some(list) if subscript.is_none() => {
+                let mut elements = Vec::new();
+                let tokens = parser::tokenize(list).unwrap_or_default();
+                for token in tokens.iter().filter(|t| t.kind == parser::TokenKind::Word) {
+                    let word = token.text.as_str();
+                    match word.strip_prefix('[').and_then(|w| w.split_once("]=")) {
+                        Some((key, value)) => {
+                            let key = self.expand_word(key);
+                            elements.push((Some(key), self.expand_word(value)));
+                        }
+                        None => {
+                            let values = self.expand_words(&[word.to_owned()]);
+                            elements.extend(values.into_iter().map(|v| (None, v)));
+                        }
+                    }
+                }
+                Value::List(elements)
+            }
+            _ => Value::Scalar(self.expand_word(value)),
+        };
+        Some(Assignment {
+            name: name.to_owned(),
+            subscript,
+            append,
+            value,
+        })
+    }
+
+    pub fn assign(&mut self, assignment: Assignment) -> error::Result<()> {
+        let Assignment {
+            name,
+            subscript,
+            append,
+            value,
+        } = assignment;
+        match (value, subscript) {
+            (Value::List(elements), _) => {
+                let mut array = match self.arrays.remove(&name) {
+                    // Replacing an associative array keeps it associative
+                    Some(Array::Associative(_)) if !append => Array::Associative(BTreeMap::new()),
+                    Some(array) if append => array,
+                    _ => {
+                        // A string being appended to becomes the first element
+                        let first = self.variable(&name).filter(|_| append);
+                        Array::from_values(first.into_iter().collect())
+                    }
+                };
+                self.variables.remove(&name);
+                let mut next = array.next_index();
+                let mut result = Ok(());
+                for (key, value) in elements {
+                    match array {
+                        Array::Indexed(ref mut map) => {
+                            if let Some(key) = key {
+                                match index(&key, next) {
+                                    Ok(i) => next = i,
+                                    Err(error) => {
+                                        result = Err(error);
+                                        break;
+                                    }
+                                }
+                            }
+                            map.insert(next, value);
+                            next += 1;
+                        }
+                        Array::Associative(ref mut map) => {
+                            match key {
+                                Some(key) => {
+                                    map.insert(key, value);
+                                }
+                                None => {
+                                    result = Err(ShellError::Message(format!(
+                                        "{}: {}: must use subscript when assigning associative array",
+                                        name,
+                                        value
+                                    )));
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                }
+                self.arrays.insert(name, array);
+                return result;
+            }
+            (Value::Scalar(value), Some(key)) => {
+                if !self.arrays.contains_key(&name) {
+                    let first = self.variable(&name);
+                    self.arrays.insert(name.clone(), Array::from_values(first.into_iter().collect()));
+                    self.variables.remove(&name);
+                }
+                let array = self.arrays.get_mut(&name).unwrap();
+                match *array {
+                    Array::Indexed(ref mut map) => {
+                        let i = index(&key, map.keys().next_back().map_or(0, |i| i + 1))?;
+                        let element = map.entry(i).or_default();
+                        set_value(element, value, append);
+                    }
+                    Array::Associative(ref mut map) => {
+                        set_value(map.entry(key).or_default(), value, append);
+                    }
+                }
+            }
+            (Value::Scalar(value), None) => {
+                match self.arrays.get_mut(&name) {
+                    // Assigning to an array without a subscript sets its first element
+                    Some(&mut Array::Indexed(ref mut map)) => {
+                        set_value(map.entry(0).or_default(), value, append)
+                    }
+                    Some(&mut Array::Associative(ref mut map)) => {
+                        set_value(map.entry("0".to_owned()).or_default(), value, append)
+                    }
+                    None => {
+                        let value = match self.variable(&name) {
+                            Some(old) if append => old + &value,
+                            _ => value,
+                        };
+                        self.variables.insert(&name, &value);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // The elements of an array that a subscript picks: all of them for @ and *, or the one at
+    // an index or key. Negative indices count back from the end. A plain variable is an array
+    // of one.
+    pub fn elements(&mut self, name: &str, subscript: &str) -> Option<Vec<String>> {
+        let array = match self.arrays.get(name) {
+            Some(array) => array.clone(),
+            None => Array::from_values(vec![self.parameter(name)?]),
+        };
+        if subscript == "@" || subscript == "*" {
+            return Some(array.values());
+        }
+        let key = self.expand_word(subscript);
+        let element = match array {
+            Array::Indexed(ref map) => {
+                let i = index(&key, map.keys().next_back().map_or(0, |i| i + 1)).ok()?;
+                map.get(&i).cloned()
+            }
+            Array::Associative(ref map) => map.get(&key).cloned(),
+        };
+        element.map(|element| vec![element])
+    }
+
+    // The indices or keys of an array, for ${!name[@]}
+    pub fn array_keys(&self, name: &str) -> Vec<String> {
+        match self.arrays.get(name) {
+            Some(array) => array.keys(),
+            None if self.parameter(name).is_some() => vec!["0".to_owned()],
+            None => Vec::new(),
+        }
+    }
+
+    // Removes one element, for unset name[subscript]
+    pub fn unset_element(&mut self, name: &str, subscript: &str) -> error::Result<()> {
+        match self.arrays.get_mut(name) {
+            Some(&mut Array::Indexed(ref mut map)) => {
+                let i = index(subscript, map.keys().next_back().map_or(0, |i| i + 1))?;
+                map.remove(&i);
+            }
+            Some(&mut Array::Associative(ref mut map)) => {
+                map.remove(subscript);
+            }
+            None if subscript == "0" => self.variables.remove(name),
+            None => {}
+        }
+        Ok(())
+    }
+
+    // declare [-aAp] [name[=value] ...], also called typeset. The assignments among the
+    // arguments were expanded as assignments, and come separately.
+    pub fn declare(&mut self, args: &[String], assignments: Vec<Assignment>) -> error::Result<i32> {
+        let mut kind = None;
+        let mut print = false;
+        let mut names = Vec::new();
+        for arg in args {
+            match arg.strip_prefix('-').filter(|_| names.is_empty()) {
+                Some(flags) => {
+                    for flag in flags.chars() {
+                        match flag {
+                            'a' | 'A' => kind = Some(flag),
+                            'p' => print = true,
+                            // Every variable is in the environment already
+                            'x' | 'g' => {}
+                            _ => {
+                                self.report(&format!("declare: -{}: invalid option", flag));
+                                return Ok(2);
+                            }
+                        }
+                    }
+                }
+                None => names.push(arg.clone()),
+            }
+        }
+        if print || (args.is_empty() && assignments.is_empty()) {
+            return self.print_declarations(&names);
+        }
+
+        let mut status = 0;
+        let declared = names.iter().cloned().chain(assignments.iter().map(|a| a.name.clone()));
+        for name in declared.collect::<Vec<_>>() {
+            if !parser::is_name(&name) {
+                self.report(&format!("declare: `{}': not a valid identifier", name));
+                status = 1;
+                continue;
+            }
+            let existing = self.arrays.get(&name).map(|a| matches!(*a, Array::Associative(_)));
+            match (kind, existing) {
+                (Some('A'), Some(false)) | (Some('a'), Some(true)) => {
+                    self.report(&format!("declare: {}: cannot convert between array kinds", name));
+                    status = 1;
+                }
+                (Some('A'), None) => {
+                    self.variables.remove(&name);
+                    self.arrays.insert(name, Array::Associative(BTreeMap::new()));
+                }
+                (Some('a'), None) => {
+                    let first = self.variable(&name);
+                    self.variables.remove(&name);
+                    self.arrays.insert(name, Array::from_values(first.into_iter().collect()));
+                }
+                _ => {}
+            }
+        }
+        for assignment in assignments {
+            if let Err(error) = self.assign(assignment) {
+                self.report(&format!("declare: {}", error));
+                status = 1;
+            }
+        }
+        Ok(status)
+    }
+
+    // declare -p prints declarations that recreate the variables
+    fn print_declarations(&self, names: &[String]) -> error::Result<i32> {
+        let mut all: Vec<String> = self.variables.names();
+        all.extend(self.arrays.keys().cloned());
+        all.sort();
+        let names = if names.is_empty() { &all[..] } else { names };
+        let mut status = 0;
+        for name in names {
+            match self.arrays.get(name) {
+                Some(Array::Indexed(map)) => {
+                    let elements: Vec<String> = map.iter()
+                        .map(|(i, v)| format!("[{}]={}", i, double_quote(v)))
+                        .collect();
+                    writeln!(
+                        exec::output(),
+                        "declare -a {}=({})",
+                        name,
+                        elements.join(" ")
+                    )?;
+                }
+                Some(Array::Associative(map)) => {
+                    let elements: Vec<String> = map.iter()
+                        .map(|(k, v)| format!("[{}]={}", double_quote(k), double_quote(v)))
+                        .collect();
+                    writeln!(exec::output(), "declare -A {}=({})", name, elements.join(" "))?;
+                }
+                None => {
+                    match self.variable(name) {
+                        Some(value) => writeln!(exec::output(), "declare -x {}={}", name, double_quote(&value))?,
+                        None => {
+                            self.report(&format!("declare: {}: not found", name));
+                            status = 1;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(status)
+    }
+}
+
+fn set_value(element: &mut String, value: String, append: bool) {
+    if append {
+        element.push_str(&value);
+    } else {
+        *element = value;
+    }
+}
+
+// An index into an indexed array, where negative ones count back from `end`
+fn index(key: &str, end: usize) -> error::Result<usize> {
+    let bad = || ShellError::Message(format!("{}: bad array subscript", key));
+    let i: i64 = key.trim().parse().map_err(|_| bad())?;
+    let i = if i < 0 { end as i64 + i } else { i };
+    if i < 0 { Err(bad()) } else { Ok(i as usize) }
+}
@@ -11,8 +11,9 @@ where
     T: std::clone::Clone,
 {
     pub fn new(size: usize) -> Self {
+        // One slot always stays empty so that head == tail means the buffer is empty
         CircularBuffer {
-            buffer: vec![Default::default(); size],
+            buffer: vec![Default::default(); size + 1],
             head: 0,
             tail: 0,
         }
@@ -20,26 +21,36 @@ where
 
     pub fn push(&mut self, entry: T) {
         self.buffer[self.tail] = entry;
-        self.tail += 1;
-        if self.tail > self.buffer.len() {
-            self.tail = 0;
-        }
+        self.tail = (self.tail + 1) % self.buffer.len();
         if self.head == self.tail {
-            self.head += 1;
-            if self.head > self.buffer.len() {
-                self.head = 0;
-            }
+            self.head = (self.head + 1) % self.buffer.len();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        (self.tail + self.buffer.len() - self.head) % self.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head == self.tail
+    }
+
+    // Entries are indexed from the oldest (0) to the newest (len - 1)
+    pub fn get(&self, index: usize) -> Option<&T> {
+        if index >= self.len() {
+            return None;
         }
+        Some(&self.buffer[(self.head + index) % self.buffer.len()])
     }
 
-    pub fn iter(&self) -> CircularBufferIter<T> {
+    pub fn iter(&self) -> CircularBufferIter<'_, T> {
         CircularBufferIter {
             buffer: self,
             position: self.head,
         }
     }
 
-    pub fn iter_rev(&self) -> CircularBufferIterRev<T> {
+    pub fn iter_rev(&self) -> CircularBufferIterRev<'_, T> {
         CircularBufferIterRev {
             buffer: self,
             position: self.tail,
@@ -47,11 +58,24 @@ where
         }
     }
 
+    // Keeps only the entries for which `keep` returns true, in their original order
+    pub fn retain<F>(&mut self, mut keep: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let kept: Vec<T> = self.iter().filter(|e| keep(e)).cloned().collect();
+        self.head = 0;
+        self.tail = 0;
+        for entry in kept {
+            self.push(entry);
+        }
+    }
+
     pub fn tail(&self) -> Option<&T> {
         if self.head == self.tail {
             return None;
         }
-        Some(&self.buffer[self.tail - 1])
+        Some(&self.buffer[(self.tail + self.buffer.len() - 1) % self.buffer.len()])
     }
 
     pub fn head(&self) -> Option<&T> {
@@ -74,10 +98,7 @@ impl<'a, T: Default + 'a> Iterator for CircularBufferIter<'a, T> {
         if self.position == self.buffer.tail {
             None
         } else {
-            self.position += 1;
-            if self.position > self.buffer.buffer.len() {
-                self.position = 0;
-            }
+            self.position = (self.position + 1) % self.buffer.buffer.len();
             Some(&self.buffer.buffer[oldpos])
         }
     }
@@ -92,20 +113,17 @@ pub struct CircularBufferIterRev<'a, T: 'a> {
 impl<'a, T: Default + 'a> Iterator for CircularBufferIterRev<'a, T> {
     type Item = &'a T;
     fn next(&mut self) -> Option<&'a T> {
-        if self.exhausted {
-            return None;
-        }
-        if self.position == self.buffer.head {
+        if self.exhausted || self.position == self.buffer.head {
             self.exhausted = true;
+            return None;
         }
 
-        // Decrement the location
-        let oldpos = self.position;
+        // Decrement the location, the tail itself is always an empty slot
         if self.position > 0 {
             self.position -= 1;
         } else {
             self.position = self.buffer.buffer.len() - 1
         }
-        Some(&self.buffer.buffer[oldpos])
+        Some(&self.buffer.buffer[self.position])
     }
 }
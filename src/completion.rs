@@ -0,0 +1,165 @@
+extern crate std;
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use alias::quote;
+use exec;
+use error::Result;
+use parser;
+use parser::TokenKind;
+use run_command::BUILTINS;
+use state::ShellState;
+
+// Where the arguments of a command are completed from, set up with the complete builtin
+#[derive(Clone, Default)]
+pub struct Completion {
+    words: Vec<String>,
+    files: bool,
+    directories: bool,
+    commands: bool,
+}
+
+impl Completion {
+    // The options that would set this completion up again
+    fn options(&self) -> String {
+        let mut options = String::new();
+        for &(set, flag) in &[(self.files, "-f"), (self.directories, "-d"), (self.commands, "-c")] {
+            if set {
+                options.push_str(flag);
+                options.push(' ');
+            }
+        }
+        if !self.words.is_empty() {
+            options.push_str(&format!("-W {} ", quote(&self.words.join(" "))));
+        }
+        options
+    }
+}
+
+impl ShellState {
+    // complete [-f] [-d] [-c] [-W words] name ..., complete -r name ..., or complete to list
+    pub fn complete(&mut self, args: &[String]) -> Result<i32> {
+        if args.is_empty() {
+            let mut names: Vec<&String> = self.completions.keys().collect();
+            names.sort();
+            for name in names {
+                writeln!(
+                    exec::output(),
+                    "complete {}{}",
+                    self.completions[name].options(),
+                    name
+                )?;
+            }
+            return Ok(0);
+        }
+        let mut completion = Completion::default();
+        let mut remove = false;
+        let mut i = 0;
+        while i < args.len() && args[i].starts_with('-') {
+            match args[i].as_str() {
+                "-f" => completion.files = true,
+                "-d" => completion.directories = true,
+                "-c" => completion.commands = true,
+                "-r" => remove = true,
+                "-W" => {
+                    i += 1;
+                    match args.get(i) {
+                        Some(words) => {
+                            completion.words.extend(words.split_whitespace().map(str::to_owned))
+                        }
+                        None => {
+                            self.report("complete: -W: option requires an argument");
+                            return Ok(2);
+                        }
+                    }
+                }
+                option => {
+                    self.report(&format!("complete: {}: invalid option", option));
+                    return Ok(2);
+                }
+            }
+            i += 1;
+        }
+        if i == args.len() {
+            self.report("complete: usage: complete [-f] [-d] [-c] [-W words] [-r] name ...");
+            return Ok(2);
+        }
+        for name in &args[i..] {
+            if remove {
+                self.completions.remove(name);
+            } else {
+                self.completions.insert(name.clone(), completion.clone());
+            }
+        }
+        Ok(0)
+    }
+
+    // The completion set up for the command the end of the input is an argument of
+    pub fn completion_for(&self, input: &str) -> Option<&Completion> {
+        let (tokens, _) = parser::tokenize_partial(input);
+        let mut command: Option<&str> = None;
+        for token in &tokens {
+            match token.kind {
+                TokenKind::Word if command.is_none() && parser::assignment(&token.text).is_none() => {
+                    command = Some(&token.text)
+                }
+                TokenKind::Word | TokenKind::Comment | TokenKind::HereDocument => {}
+                TokenKind::Operator | TokenKind::Newline => command = None,
+            }
+        }
+        // Only once the command name itself has been typed out
+        if !input.ends_with(|c: char| c.is_whitespace()) &&
+            tokens.last().is_some_and(|t| Some(t.text.as_str()) == command)
+        {
+            return None;
+        }
+        self.completions.get(command?)
+    }
+
+    // The first candidate of a completion that starts with `word`
+    pub fn complete_word(&self, completion: &Completion, word: &str) -> Option<String> {
+        if let Some(found) = completion.words.iter().find(|w| w.starts_with(word) && *w != word) {
+            return Some(found.clone());
+        }
+        if completion.commands {
+            let mut names: Vec<String> = BUILTINS.iter()
+                .map(|b| b.to_string())
+                .chain(self.functions.keys().cloned())
+                .chain(self.aliases.keys().cloned())
+                .collect();
+            for dir in self.variable("PATH").unwrap_or_default().split(':') {
+                if let Ok(entries) = fs::read_dir(dir) {
+                    names.extend(entries.filter_map(|e| e.ok()?.file_name().into_string().ok()));
+                }
+            }
+            names.sort();
+            if let Some(found) = names.into_iter().find(|n| n.starts_with(word) && n != word) {
+                return Some(found);
+            }
+        }
+        if completion.files || completion.directories {
+            return self.find_match_path(word, completion.directories && !completion.files);
+        }
+        None
+    }
+
+    // A file in the working directory, or in the directory `pattern` names, that starts with
+    // what comes after its last slash
+    fn find_match_path(&self, pattern: &str, directories: bool) -> Option<String> {
+        let (dir, prefix) = match pattern.rfind('/') {
+            Some(i) => pattern.split_at(i + 1),
+            None => ("", pattern),
+        };
+        let cwd = self.variable("PWD").unwrap_or_default();
+        let mut names: Vec<String> = fs::read_dir(Path::new(&cwd).join(dir))
+            .ok()?
+            .filter_map(|e| e.ok())
+            .filter(|e| !directories || e.path().is_dir())
+            .filter_map(|e| e.file_name().into_string().ok())
+            .filter(|n| n.starts_with(prefix) && n != prefix && (prefix.starts_with('.') || !n.starts_with('.')))
+            .collect();
+        names.sort();
+        names.into_iter().next().map(|name| format!("{}{}", dir, name))
+    }
+}
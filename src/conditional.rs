@@ -0,0 +1,421 @@
+extern crate std;
+extern crate libc;
+extern crate glob;
+
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use self::glob::Pattern;
+use arrays::Array;
+use error;
+use exec;
+use state::ShellState;
+
+const UNARY: &[&str] = &[
+    "-a", "-b", "-c", "-d", "-e", "-f", "-g", "-h", "-k", "-L", "-n", "-O", "-G", "-p", "-r",
+    "-s", "-S", "-t", "-u", "-w", "-x", "-z",
+];
+
+// How deeply ( ) and ! can nest, short of the end of the stack
+const MAX_NESTING: usize = 1000;
+
+const BINARY: &[&str] = &[
+    "=", "==", "!=", "<", ">", "=~", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "-nt", "-ot", "-ef",
+];
+
+// Evaluates the expressions of test, [ and [[. test gets its words already expanded, while [[
+// gets them as written and expands them as it goes, so that && and || skip what they don't
+// need and the right side of == is a pattern.
+struct Evaluator<'a> {
+    shell: &'a mut ShellState,
+    words: &'a [String],
+    position: usize,
+    // Whether this is [[, which doesn't have -a and -o for and and or
+    double: bool,
+    // Set while parsing what && and || don't need, which isn't expanded
+    skipping: bool,
+    // How many ( and ! the word being parsed is inside
+    depth: usize,
+}
+
+// Syntax errors give test's status 2, after a message
+type Result<T> = std::result::Result<T, String>;
+
+impl<'a> Evaluator<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.words.get(self.position).map(|w| w.as_str())
+    }
+
+    fn next(&mut self) -> Result<&'a str> {
+        let word = self.peek().ok_or_else(|| "argument expected".to_owned())?;
+        self.position += 1;
+        Ok(word)
+    }
+
+    fn eat(&mut self, word: &str) -> bool {
+        let found = self.peek() == Some(word);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn is_and(&self) -> bool {
+        self.peek() == Some(if self.double { "&&" } else { "-a" })
+    }
+
+    fn is_or(&self) -> bool {
+        self.peek() == Some(if self.double { "||" } else { "-o" })
+    }
+
+    fn or(&mut self) -> Result<bool> {
+        let mut result = self.and()?;
+        while self.is_or() {
+            self.position += 1;
+            let right = self.short_circuit(result, Evaluator::and)?;
+            result = result || right;
+        }
+        Ok(result)
+    }
+
+    fn and(&mut self) -> Result<bool> {
+        let mut result = self.not()?;
+        while self.is_and() {
+            self.position += 1;
+            let right = self.short_circuit(!result, Evaluator::not)?;
+            result = result && right;
+        }
+        Ok(result)
+    }
+
+    // Parses the next operand of && or ||, which is only evaluated when the answer isn't
+    // `decided` already
+    fn short_circuit(&mut self, decided: bool, parse: fn(&mut Self) -> Result<bool>) -> Result<bool> {
+        let skipping = self.skipping;
+        self.skipping = skipping || decided;
+        let result = parse(self);
+        self.skipping = skipping;
+        result
+    }
+
+    // Parses something inside a ( or !, one level deeper
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<bool>) -> Result<bool> {
+        if self.depth >= MAX_NESTING || exec::stack_low() {
+            return Err("expression nested too deeply".to_owned());
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn not(&mut self) -> Result<bool> {
+        if self.eat("!") {
+            return self.nested(Evaluator::not).map(|result| !result);
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<bool> {
+        let word = self.next()?;
+        if word == "(" {
+            let result = self.nested(Evaluator::or)?;
+            if !self.eat(")") {
+                return Err("expected `)'".to_owned());
+            }
+            return Ok(result);
+        }
+        // A binary operator after this word makes it the left operand, even if it looks like
+        // a unary operator
+        if let Some(operator) = self.peek().filter(|o| self.is_binary(o)) {
+            self.position += 1;
+            let right = self.next()?;
+            return self.binary(word, operator, right);
+        }
+        if UNARY.contains(&word) {
+            if let Some(operand) = self.peek() {
+                self.position += 1;
+                let operand = self.expand(operand);
+                return self.unary(word, &operand);
+            }
+        }
+        Ok(!self.expand(word).is_empty())
+    }
+
+    fn is_binary(&self, word: &str) -> bool {
+        BINARY.contains(&word) && (self.double || word != "=~")
+    }
+
+    fn expand(&mut self, word: &str) -> String {
+        if self.skipping {
+            String::new()
+        } else if self.double {
+            self.shell.expand_word(word)
+        } else {
+            word.to_owned()
+        }
+    }
+
+    fn unary(&mut self, operator: &str, operand: &str) -> Result<bool> {
+        if self.skipping {
+            return Ok(false);
+        }
+        let path = self.shell.resolve_path(operand);
+        let metadata = || fs::metadata(&path).ok();
+        Ok(match operator {
+            "-n" => !operand.is_empty(),
+            "-z" => operand.is_empty(),
+            "-a" | "-e" => metadata().is_some(),
+            "-f" => metadata().is_some_and(|m| m.is_file()),
+            "-d" => metadata().is_some_and(|m| m.is_dir()),
+            "-b" => metadata().is_some_and(|m| m.file_type().is_block_device()),
+            "-c" => metadata().is_some_and(|m| m.file_type().is_char_device()),
+            "-p" => metadata().is_some_and(|m| m.file_type().is_fifo()),
+            "-S" => metadata().is_some_and(|m| m.file_type().is_socket()),
+            "-h" | "-L" => fs::symlink_metadata(&path).is_ok_and(|m| m.file_type().is_symlink()),
+            "-s" => metadata().is_some_and(|m| m.len() > 0),
+            "-g" => metadata().is_some_and(|m| m.permissions().mode() & 0o2000 != 0),
+            "-u" => metadata().is_some_and(|m| m.permissions().mode() & 0o4000 != 0),
+            "-k" => metadata().is_some_and(|m| m.permissions().mode() & 0o1000 != 0),
+            "-O" => metadata().is_some_and(|m| m.uid() == unsafe { libc::geteuid() }),
+            "-G" => metadata().is_some_and(|m| m.gid() == unsafe { libc::getegid() }),
+            "-r" => access(&path, libc::R_OK),
+            "-w" => access(&path, libc::W_OK),
+            "-x" => access(&path, libc::X_OK),
+            "-t" => {
+                let fd = integer(operand)?;
+                unsafe { libc::isatty(fd as i32) == 1 }
+            }
+            _ => return Err(format!("{}: unary operator expected", operator)),
+        })
+    }
+
+    fn binary(&mut self, left: &str, operator: &str, right: &str) -> Result<bool> {
+        if self.skipping {
+            return Ok(false);
+        }
+        let left = self.expand(left);
+        if self.double && matches!(operator, "=" | "==" | "!=") {
+            // The right side of [['s == is a pattern, with its quoted parts matched literally
+            let pattern = self.shell.expand_pattern(right);
+            let matched = Pattern::new(&pattern).map_or(left == pattern, |p| p.matches(&left));
+            return Ok(matched == (operator != "!="));
+        }
+        if operator == "=~" {
+            let pattern = self.shell.expand_word(right);
+            return self.shell.regex_match(&left, &pattern);
+        }
+        let right = self.expand(right);
+        let modified = |path: &str| {
+            fs::metadata(self.shell.resolve_path(path)).and_then(|m| m.modified()).ok()
+        };
+        Ok(match operator {
+            "=" | "==" => left == right,
+            "!=" => left != right,
+            "<" => left < right,
+            ">" => left > right,
+            "-eq" => integer(&left)? == integer(&right)?,
+            "-ne" => integer(&left)? != integer(&right)?,
+            "-lt" => integer(&left)? < integer(&right)?,
+            "-le" => integer(&left)? <= integer(&right)?,
+            "-gt" => integer(&left)? > integer(&right)?,
+            "-ge" => integer(&left)? >= integer(&right)?,
+            // A file that doesn't exist is older than one that does
+            "-nt" => modified(&left) > modified(&right),
+            "-ot" => modified(&left) < modified(&right),
+            "-ef" => {
+                let id = |path: &str| {
+                    fs::metadata(self.shell.resolve_path(path)).ok().map(|m| (m.dev(), m.ino()))
+                };
+                id(&left).is_some() && id(&left) == id(&right)
+            }
+            _ => return Err(format!("{}: binary operator expected", operator)),
+        })
+    }
+}
+
+fn integer(word: &str) -> Result<i64> {
+    word.trim().parse().map_err(|_| format!("{}: integer expression expected", word))
+}
+
+// How many groups a regular expression has: the ( that aren't escaped or in brackets
+fn group_count(pattern: &str) -> usize {
+    let mut count = 0;
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '[' => {
+                // A ] right after the [ or [^ is part of the set
+                let mut first = true;
+                for c in chars.by_ref() {
+                    if c == ']' && !first {
+                        break;
+                    }
+                    first = c == '^' && first;
+                }
+            }
+            '(' => count += 1,
+            _ => {}
+        }
+    }
+    count
+}
+
+fn access(path: &Path, mode: libc::c_int) -> bool {
+    match CString::new(path.to_string_lossy().as_bytes()) {
+        Ok(path) => unsafe { libc::access(path.as_ptr(), mode) == 0 },
+        Err(_) => false,
+    }
+}
+
+impl ShellState {
+    // test expression, or [ expression ]
+    pub fn test(&mut self, name: &str, args: &[String]) -> error::Result<i32> {
+        let args = if name == "[" {
+            match args.split_last() {
+                Some((last, rest)) if last == "]" => rest,
+                _ => {
+                    self.report("[: missing `]'");
+                    return Ok(2);
+                }
+            }
+        } else {
+            args
+        };
+        let result = self.evaluate_test(args);
+        Ok(self.condition_status(name, result))
+    }
+
+    // Runs the words of a [[ ]] command
+    pub fn conditional(&mut self, words: &[String]) -> i32 {
+        let mut evaluator = Evaluator {
+            shell: self,
+            words,
+            position: 0,
+            double: true,
+            skipping: false,
+            depth: 0,
+        };
+        let result = evaluator.or().and_then(|result| match evaluator.peek() {
+            Some(word) => Err(format!("unexpected `{}'", word)),
+            None => Ok(result),
+        });
+        self.condition_status("[[", result)
+    }
+
+    // POSIX decides what test's arguments mean by how many there are, which keeps things like
+    // test -n or [ = ] working, and only parses longer expressions
+    fn evaluate_test(&mut self, args: &[String]) -> Result<bool> {
+        let mut evaluator = Evaluator {
+            shell: self,
+            words: args,
+            position: 0,
+            double: false,
+            skipping: false,
+            depth: 0,
+        };
+        let words: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+        match words.as_slice() {
+            [] => Ok(false),
+            [word] => Ok(!word.is_empty()),
+            ["!", word] => Ok(word.is_empty()),
+            [operator, operand] if UNARY.contains(operator) => evaluator.unary(operator, operand),
+            [operator, _] => Err(format!("{}: unary operator expected", operator)),
+            [left, operator, right] if evaluator.is_binary(operator) => {
+                evaluator.binary(left, operator, right)
+            }
+            ["!", _, _] => {
+                evaluator.position = 1;
+                evaluator.evaluate_rest().map(|result| !result)
+            }
+            ["(", word, ")"] => Ok(!word.is_empty()),
+            ["!", _, operator, _] if evaluator.is_binary(operator) => {
+                evaluator.position = 1;
+                evaluator.evaluate_rest().map(|result| !result)
+            }
+            _ => evaluator.evaluate_rest(),
+        }
+    }
+
+    fn condition_status(&self, name: &str, result: Result<bool>) -> i32 {
+        match result {
+            Ok(true) => 0,
+            Ok(false) => 1,
+            Err(message) => {
+                self.report(&format!("{}: {}", name, message));
+                2
+            }
+        }
+    }
+
+    // A path relative to the working directory
+    pub fn resolve_path(&self, path: &str) -> PathBuf {
+        PathBuf::from(self.variable("PWD").unwrap_or_default()).join(path)
+    }
+
+    // Matches `text` against a POSIX extended regular expression, leaving what the whole
+    // expression and each group matched in BASH_REMATCH
+    fn regex_match(&mut self, text: &str, pattern: &str) -> Result<bool> {
+        match regex_groups(text, pattern)? {
+            Some(captured) => {
+                self.arrays.insert("BASH_REMATCH".to_owned(), Array::from_values(captured));
+                Ok(true)
+            }
+            None => {
+                self.arrays.remove("BASH_REMATCH");
+                Ok(false)
+            }
+        }
+    }
+}
+
+// What a POSIX extended regular expression and each of its groups match in `text`, or None if
+// it doesn't match
+pub fn regex_groups(text: &str, pattern: &str) -> Result<Option<Vec<String>>> {
+    let (text_c, pattern_c) = match (CString::new(text), CString::new(pattern)) {
+        (Ok(text), Ok(pattern)) => (text, pattern),
+        _ => return Ok(None),
+    };
+    let groups = unsafe {
+        let mut regex: libc::regex_t = std::mem::zeroed();
+        if libc::regcomp(&mut regex, pattern_c.as_ptr(), libc::REG_EXTENDED) != 0 {
+            return Err(format!("{}: invalid regular expression", pattern));
+        }
+        let mut matches = vec![libc::regmatch_t { rm_so: -1, rm_eo: -1 }; group_count(pattern) + 1];
+        let found = libc::regexec(
+            &regex,
+            text_c.as_ptr(),
+            matches.len(),
+            matches.as_mut_ptr(),
+            0,
+        ) == 0;
+        libc::regfree(&mut regex);
+        if !found {
+            return Ok(None);
+        }
+        matches
+    };
+    let captured = groups.iter()
+        .map(|m| if m.rm_so < 0 {
+            String::new()
+        } else {
+            text[m.rm_so as usize..m.rm_eo as usize].to_owned()
+        })
+        .collect();
+    Ok(Some(captured))
+}
+
+impl<'a> Evaluator<'a> {
+    // Parses what is left as one expression, which has to use all of it
+    fn evaluate_rest(&mut self) -> Result<bool> {
+        let result = self.or()?;
+        match self.peek() {
+            Some(word) => Err(format!("{}: unexpected operator", word)),
+            None => Ok(result),
+        }
+    }
+}
@@ -1,29 +1,20 @@
 extern crate std;
-extern crate itertools;
 
-use std::iter;
-use self::itertools::Itertools;
+use std::io::Write;
+use exec;
+use error::Result;
 use state::ShellState;
 
 impl ShellState {
-    pub fn echo(&self, args: std::str::SplitWhitespace) {
-        let vars = args.map(|a| self.lookup_envar(a).unwrap_or_else(|| a.to_owned()));
-        for entry in iter::repeat(" ".to_owned())
-            .interleave_shortest(vars)
-            .skip(1)
-        {
-            print!("{}", entry)
+    pub fn echo(&self, args: &[String]) -> Result<i32> {
+        // -n leaves out the newline at the end
+        let newline = args.first().is_none_or(|a| a != "-n");
+        let args = if newline { args } else { &args[1..] };
+        let mut out = exec::output();
+        write!(out, "{}", args.join(" "))?;
+        if newline {
+            writeln!(out)?;
         }
-        println!();
-    }
-
-    fn lookup_envar(&self, arg: &str) -> Option<String> {
-        if arg.starts_with('$') {
-            let (_, key) = arg.split_at(1);
-            if let Some(val) = self.variables.get(key) {
-                return Some(val.to_string_lossy().into_owned());
-            }
-        }
-        None
+        Ok(0)
     }
 }
@@ -0,0 +1,207 @@
+extern crate std;
+extern crate libc;
+
+use std::env;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::io::FromRawFd;
+use std::path::PathBuf;
+use std::rc::Rc;
+use envars::Envars;
+use exec;
+use exec::Flow;
+use state::ShellState;
+
+/// The shell as a library, for programs that use rsh as their scripting language:
+///
+/// ```
+/// use rust_shell::Shell;
+///
+/// let mut shell = Shell::new();
+/// shell.builtin("greet", |_, args| {
+///     println!("hello {}", args.join(" "));
+///     0
+/// });
+/// let output = shell.run("greet world | tr a-z A-Z");
+/// assert_eq!(output.stdout, "HELLO WORLD\n");
+/// assert_eq!(output.status, 0);
+/// ```
+///
+/// # Commands run in this process
+///
+/// There is no sandbox. While a run lasts, file descriptors 0, 1 and 2 of the whole process
+/// are files of its own, so anything another thread prints then ends up in the run's output,
+/// and there should only be one run at a time. `export` and `unset` change the environment of
+/// the process. `cd` changes the working directory too, but only for the run: the shell goes
+/// back to the directory it was in afterwards, and takes up its own again at the next run.
+///
+/// # Input and output are not streamed
+///
+/// Before anything runs the reader given to [`Shell::stdin`] is read to the end, and the
+/// writers given to [`Shell::stdout`] and [`Shell::stderr`] only get what was printed once the
+/// run has finished. So interactive programs and commands that never finish can't be run.
+pub struct Shell {
+    state: ShellState,
+    stdin: Option<Box<dyn Read>>,
+    stdout: Option<Box<dyn Write>>,
+    stderr: Option<Box<dyn Write>>,
+    // The shell's working directory, which it leaves between runs
+    cwd: Option<PathBuf>,
+}
+
+/// How a run ended and what it printed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Output {
+    /// The status of the last command, or of exit
+    pub status: i32,
+    /// Everything the commands printed, which is lossily turned into UTF-8
+    pub stdout: String,
+    /// And the error messages
+    pub stderr: String,
+    /// Whether it ran exit, or stopped for -e or a failed expansion
+    pub exited: bool,
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        Shell::new()
+    }
+}
+
+impl Shell {
+    /// A shell with the environment of the process, in its working directory, and without
+    /// any startup files read
+    pub fn new() -> Shell {
+        let mut state = ShellState::new(Envars::load());
+        state.embedded = true;
+        if let Ok(cwd) = env::current_dir() {
+            state.variables.insert("PWD", &cwd.to_string_lossy());
+        }
+        Shell {
+            state,
+            stdin: None,
+            stdout: None,
+            stderr: None,
+            cwd: None,
+        }
+    }
+
+    /// What commands read. Without it they get nothing, as from /dev/null.
+    pub fn stdin<R: Read + 'static>(&mut self, input: R) -> &mut Shell {
+        self.stdin = Some(Box::new(input));
+        self
+    }
+
+    /// Where what commands print goes, as well as into the [`Output`] of each run
+    pub fn stdout<W: Write + 'static>(&mut self, output: W) -> &mut Shell {
+        self.stdout = Some(Box::new(output));
+        self
+    }
+
+    /// Where error messages go, as well as into the [`Output`] of each run
+    pub fn stderr<W: Write + 'static>(&mut self, output: W) -> &mut Shell {
+        self.stderr = Some(Box::new(output));
+        self
+    }
+
+    /// Adds a builtin, or replaces one. It gets the shell and its arguments, without its own
+    /// name, and gives back its status. What it prints goes where the run's output does.
+    pub fn builtin<F>(&mut self, name: &str, builtin: F) -> &mut Shell
+        where F: Fn(&mut ShellState, &[String]) -> i32 + 'static
+    {
+        self.state.custom_builtins.insert(name.to_owned(), Rc::new(builtin));
+        self
+    }
+
+    /// The shell's variables, functions and options, which carry on from one run to the next
+    pub fn state(&mut self) -> &mut ShellState {
+        &mut self.state
+    }
+
+    /// The value of a variable, or None if it isn't set
+    pub fn variable(&self, name: &str) -> Option<String> {
+        self.state.variable(name)
+    }
+
+    /// Sets a variable, which like any other is in the environment of the process
+    pub fn set_variable(&mut self, name: &str, value: &str) {
+        self.state.variables.insert(name, value);
+    }
+
+    /// Runs commands, the way a script would, in this process and with its file descriptors.
+    /// Syntax errors and files that can't be set up are in the output like any other error.
+    pub fn run(&mut self, source: &str) -> Output {
+        let mut input = Vec::new();
+        if let Some(ref mut stdin) = self.stdin {
+            if let Err(e) = stdin.read_to_end(&mut input) {
+                return failed(format!("rsh: stdin: {}\n", exec::io_message(&e)));
+            }
+        }
+        let input = String::from_utf8_lossy(&input);
+        let files = [exec::temp_file(&input), exec::temp_file(""), exec::temp_file("")];
+        let fds: Vec<i32> = files.iter().filter_map(|f| f.as_ref().ok().cloned()).collect();
+        if fds.len() < files.len() {
+            for fd in fds {
+                unsafe { libc::close(fd) };
+            }
+            let message = files
+                .iter()
+                .find_map(|f| f.as_ref().err())
+                .map(exec::io_message);
+            return failed(format!("rsh: {}\n", message.unwrap_or_default()));
+        }
+
+        let host_cwd = env::current_dir().ok();
+        if let Some(ref cwd) = self.cwd {
+            let _ = env::set_current_dir(cwd);
+        }
+        let mut saved = Vec::new();
+        exec::flush();
+        for (fd, &file) in fds.iter().enumerate() {
+            exec::save_fd(fd as i32, &mut saved);
+            unsafe { libc::dup2(file, fd as i32) };
+        }
+        let status = self.state.run_script(source, None);
+        let exited = self.state.flow == Some(Flow::Exit);
+        // break or return outside of anything they could leave does nothing
+        self.state.flow = None;
+        self.state.restore(saved);
+        self.cwd = env::current_dir().ok();
+        if let Some(cwd) = host_cwd {
+            let _ = env::set_current_dir(cwd);
+        }
+
+        unsafe { libc::close(fds[0]) };
+        let stdout = read_back(fds[1]);
+        let stderr = read_back(fds[2]);
+        for (writer, text) in [(&mut self.stdout, &stdout), (&mut self.stderr, &stderr)] {
+            if let Some(ref mut writer) = *writer {
+                let _ = writer.write_all(text.as_bytes()).and_then(|_| writer.flush());
+            }
+        }
+        Output {
+            status,
+            stdout,
+            stderr,
+            exited,
+        }
+    }
+}
+
+// A run that couldn't start
+fn failed(stderr: String) -> Output {
+    Output {
+        status: 1,
+        stdout: String::new(),
+        stderr,
+        exited: false,
+    }
+}
+
+// Everything written to one of the files a run printed to, which it closes
+fn read_back(fd: i32) -> String {
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    let mut contents = Vec::new();
+    let _ = file.seek(SeekFrom::Start(0)).and_then(|_| file.read_to_end(&mut contents));
+    String::from_utf8_lossy(&contents).into_owned()
+}
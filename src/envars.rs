@@ -15,15 +15,38 @@ impl Envars {
         for (key, value) in env::vars_os() {
             this.map.insert(key, value);
         }
-        return this;
+        this
     }
 
     pub fn get(&self, name: &str) -> Option<&std::ffi::OsString> {
         self.map.get(OsStr::new(name))
     }
 
+    // The shell keeps the value as it is, but the environment can't hold a NUL byte or a name
+    // with = in it, where set_var would panic, so NULs are left out of what programs see
     pub fn insert(&mut self, key: &str, value: &str) {
         self.map.insert(OsString::from(key), OsString::from(value));
-        env::set_var(key, value);
+        if key.is_empty() || key.contains(['=', '\0']) {
+            return;
+        }
+        if value.contains('\0') {
+            env::set_var(key, value.replace('\0', ""));
+        } else {
+            env::set_var(key, value);
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.map.remove(OsStr::new(key));
+        if !key.is_empty() && !key.contains(['=', '\0']) {
+            env::remove_var(key);
+        }
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> =
+            self.map.keys().map(|k| k.to_string_lossy().into_owned()).collect();
+        names.sort();
+        names
     }
 }
@@ -0,0 +1,50 @@
+extern crate std;
+
+use std::fmt;
+use std::io;
+use exec::io_message;
+
+// What stops a builtin or a command. The shell prints it on standard error, after the name
+// of the builtin or the file it was running, and carries on.
+#[derive(Debug)]
+pub enum ShellError {
+    // Writing output failed, as when whatever was reading a pipe has gone
+    Output(io::Error),
+    // A file or system call failed, with what it was for
+    Io(String, io::Error),
+    // Anything else, already worded
+    Message(String),
+}
+
+pub type Result<T> = std::result::Result<T, ShellError>;
+
+impl ShellError {
+    pub fn io(context: &str, error: io::Error) -> ShellError {
+        ShellError::Io(context.to_owned(), error)
+    }
+}
+
+impl fmt::Display for ShellError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ShellError::Output(ref error) => write!(f, "write error: {}", io_message(error)),
+            ShellError::Io(ref context, ref error) => {
+                write!(f, "{}: {}", context, io_message(error))
+            }
+            ShellError::Message(ref message) => write!(f, "{}", message),
+        }
+    }
+}
+
+// So writeln!(io::stdout(), ...)? works in a builtin
+impl From<io::Error> for ShellError {
+    fn from(error: io::Error) -> ShellError {
+        ShellError::Output(error)
+    }
+}
+
+impl From<String> for ShellError {
+    fn from(message: String) -> ShellError {
+        ShellError::Message(message)
+    }
+}
@@ -0,0 +1,1006 @@
+extern crate std;
+extern crate libc;
+extern crate glob;
+
+use std::fs::OpenOptions;
+use std::io;
+use std::env;
+use std::ffi::CString;
+use std::fmt;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::fs::File;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{FromRawFd, IntoRawFd};
+use std::path::Path;
+use std::rc::Rc;
+use self::glob::Pattern;
+use arrays::Assignment;
+use error;
+use error::ShellError;
+use parser;
+use parser::{Command, Parser, Redirect};
+use state::{Job, ShellState};
+
+// Set by return, break and continue, and by exit in an embedded shell, and checked by everything that runs more than one
+// command so the rest is skipped
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Flow {
+    Return,
+    Break(usize),
+    Continue(usize),
+    Exit,
+}
+
+// How deep function calls go without FUNCNEST
+const FUNCNEST: usize = 1000;
+// The stack a function call leaves for the commands in it, which take a lot more of it in a
+// debug build
+const STACK_RESERVE: usize = 1 << 20;
+
+thread_local! {
+    // The lowest address of this thread's stack, once it has been looked up
+    static STACK_END: std::cell::Cell<Option<usize>> = const { std::cell::Cell::new(None) };
+}
+
+// A command either runs to completion in the shell or is started in a process of its own
+pub enum Started {
+    Done(i32),
+    Running(libc::pid_t),
+}
+
+impl ShellState {
+    // Parses and runs commands one line at a time, so that aliases and functions one line
+    // defines can be used on the next, and everything before a syntax error runs. `name` is the
+    // file the commands come from, for errors.
+    pub fn run_script(&mut self, source: &str, name: Option<&str>) -> i32 {
+        let script = match name {
+            Some(name) => self.script.replace(name.to_owned()),
+            None => self.script.clone(),
+        };
+        let mut status = 0;
+        // How much of the source -v has printed
+        let mut printed = 0;
+        let mut parser = Parser::new(source);
+        loop {
+            let command = parser.next_command(&self.aliases);
+            if self.options.verbose {
+                // Whole lines, up to the end of the one the command ended on
+                let mut from = parser.consumed().saturating_sub(1);
+                while !source.is_char_boundary(from) {
+                    from -= 1;
+                }
+                let end = source[from..].find('\n').map_or(source.len(), |n| from + n + 1);
+                if end > printed {
+                    let lines = &source[printed..end];
+                    let newline = if lines.ends_with('\n') { "" } else { "\n" };
+                    let _ = write!(io::stderr(), "{}{}", lines, newline);
+                    printed = end;
+                }
+            }
+            match command {
+                // -n only checks the syntax, except in an interactive shell where it
+                // would leave nothing working
+                Ok(Some(_)) if self.options.noexec && !self.interactive => {}
+                Ok(Some(command)) => status = self.execute(&command),
+                Ok(None) => break,
+                Err(error) => {
+                    self.line = parser.line();
+                    self.report(&format!("syntax error: {}", error));
+                    status = 2;
+                    break;
+                }
+            }
+            if self.flow.is_some() {
+                break;
+            }
+        }
+        self.script = script;
+        self.last_status = status;
+        status
+    }
+
+    // Prints an error along with the file and line it comes from, when running a script
+    pub fn report<M: fmt::Display + ?Sized>(&self, message: &M) {
+        // Standard error may be a pipe nothing reads, where the error has nowhere to go
+        let _ = match self.script {
+            Some(ref script) => writeln!(io::stderr(), "{}:{}: {}", script, self.line, message),
+            None => writeln!(io::stderr(), "rsh: {}", message),
+        };
+    }
+
+    pub fn execute(&mut self, command: &Command) -> i32 {
+        let mut status = match self.start(command, true) {
+            Started::Done(status) => status,
+            Started::Running(pid) => wait_pid(pid),
+        };
+        if self.expansion_failure() {
+            status = 1;
+        }
+        self.last_status = status;
+        // -e stops at the first failing command, unless it was being tested
+        let tested = self.conditions > 0 || self.flow.is_some();
+        if status != 0 && self.options.errexit && !tested {
+            if let Command::Simple { .. } |
+                   Command::Pipeline { negated: false, .. } |
+                   Command::Subshell(_) |
+                   Command::Conditional { .. } = *command
+            {
+                self.leave(status);
+            }
+        }
+        status
+    }
+
+    // A failed expansion like ${name?} or an unset variable under -u stops the command it was
+    // for, and a script along with it
+    fn expansion_failure(&mut self) -> bool {
+        if !self.expansion_failed {
+            return false;
+        }
+        self.expansion_failed = false;
+        if !self.interactive {
+            self.leave(1);
+        }
+        self.last_status = 1;
+        true
+    }
+
+    // Runs a command whose status is being tested, so -e doesn't apply to it
+    fn execute_condition(&mut self, command: &Command) -> i32 {
+        self.conditions += 1;
+        let status = self.execute(command);
+        self.conditions -= 1;
+        status
+    }
+
+    // Runs a command, or with `wait` unset starts it in another process and leaves it running
+    pub fn start(&mut self, command: &Command, wait: bool) -> Started {
+        match *command {
+            Command::Simple {
+                ref assignments,
+                ref words,
+                ref redirects,
+                line,
+            } => {
+                self.line = line;
+                self.with_substitutions(|shell| shell.start_simple(assignments, words, redirects, wait))
+            }
+            // Nothing a subshell changes, like variables or the directory, lasts after it
+            Command::Subshell(ref body) => {
+                let started = self.start_forked(|shell| shell.execute(body));
+                match started {
+                    Started::Running(pid) if wait => Started::Done(wait_pid(pid)),
+                    _ => started,
+                }
+            }
+            _ if wait => Started::Done(self.execute_compound(command)),
+            _ => self.start_forked(|shell| shell.execute(command)),
+        }
+    }
+
+    fn execute_compound(&mut self, command: &Command) -> i32 {
+        match *command {
+            Command::Simple { .. } | Command::Subshell(_) => self.execute(command),
+            Command::Pipeline {
+                ref stages,
+                negated,
+            } => {
+                // A negated pipeline is tested, so -e doesn't apply to it
+                self.conditions += negated as usize;
+                let status = if stages.len() == 1 {
+                    self.execute(&stages[0])
+                } else {
+                    self.execute_pipeline(stages)
+                };
+                self.conditions -= negated as usize;
+                if negated { (status == 0) as i32 } else { status }
+            }
+            Command::And(ref first, ref second) => {
+                let status = self.execute_condition(first);
+                if status == 0 && self.flow.is_none() {
+                    self.execute(second)
+                } else {
+                    status
+                }
+            }
+            Command::Or(ref first, ref second) => {
+                let status = self.execute_condition(first);
+                if status != 0 && self.flow.is_none() {
+                    self.execute(second)
+                } else {
+                    status
+                }
+            }
+            Command::List(ref commands) => {
+                let mut status = 0;
+                for command in commands {
+                    status = self.execute(command);
+                    if self.flow.is_some() {
+                        break;
+                    }
+                }
+                status
+            }
+            Command::Background(ref command, ref text) => {
+                match self.start(command, false) {
+                    Started::Running(pid) => {
+                        let _ = writeln!(io::stdout(), "[{}] {}", self.jobs.len() + 1, pid);
+                        self.jobs.push(Job {
+                            command: text.clone(),
+                            pid,
+                        });
+                        self.last_background = Some(pid);
+                        0
+                    }
+                    Started::Done(status) => status,
+                }
+            }
+            Command::If {
+                ref branches,
+                ref otherwise,
+            } => {
+                for (condition, body) in branches {
+                    let status = self.execute_condition(condition);
+                    if self.flow.is_some() {
+                        return status;
+                    }
+                    if status == 0 {
+                        return self.execute(body);
+                    }
+                }
+                match *otherwise {
+                    Some(ref body) => self.execute(body),
+                    None => 0,
+                }
+            }
+            Command::While {
+                ref condition,
+                ref body,
+                until,
+            } => {
+                let mut status = 0;
+                loop {
+                    let test = self.execute_condition(condition);
+                    if self.flow.is_some() || (test == 0) == until {
+                        break;
+                    }
+                    status = self.execute(body);
+                    if self.end_iteration() {
+                        break;
+                    }
+                }
+                status
+            }
+            Command::For {
+                ref variable,
+                ref words,
+                ref body,
+            } => {
+                let values = match *words {
+                    Some(ref words) => self.expand_words(words),
+                    None => self.arguments.clone(),
+                };
+                if self.expansion_failure() {
+                    return 1;
+                }
+                let mut status = 0;
+                for value in values {
+                    self.variables.insert(variable, &value);
+                    status = self.execute(body);
+                    if self.end_iteration() {
+                        break;
+                    }
+                }
+                status
+            }
+            Command::Case {
+                ref word,
+                ref arms,
+            } => {
+                let subject = self.expand_word(word);
+                if self.expansion_failure() {
+                    return 1;
+                }
+                for (patterns, body) in arms {
+                    for pattern in patterns {
+                        let pattern = self.expand_pattern(pattern);
+                        if Pattern::new(&pattern).is_ok_and(|p| p.matches(&subject)) {
+                            return self.execute(body);
+                        }
+                    }
+                }
+                0
+            }
+            Command::Group(ref body) => self.execute(body),
+            Command::Conditional { ref words, line } => {
+                self.line = line;
+                self.conditional(words)
+            }
+            Command::Expression { ref text, line } => {
+                self.line = line;
+                self.run_expression(text)
+            }
+            Command::Redirected(ref command, ref redirects) => {
+                let started = self.with_substitutions(|shell| {
+                    Started::Done(match shell.redirect(redirects) {
+                        Ok(saved) => {
+                            let status = shell.execute(command);
+                            shell.restore(saved);
+                            status
+                        }
+                        Err(error) => {
+                            shell.report(&error);
+                            1
+                        }
+                    })
+                });
+                match started {
+                    Started::Done(status) => status,
+                    Started::Running(pid) => wait_pid(pid),
+                }
+            }
+            Command::Function { ref name, ref body } => {
+                self.functions.insert(name.clone(), body.clone());
+                0
+            }
+        }
+    }
+
+    // return [status], break [n] and continue [n]
+    pub fn control_flow(&mut self, name: &str, args: &[String]) -> error::Result<i32> {
+        let number = match args.first().map(|n| n.parse::<i32>()) {
+            None => None,
+            Some(Ok(number)) => Some(number),
+            Some(Err(_)) => {
+                self.report(&format!("{}: {}: numeric argument required", name, args[0]));
+                return Ok(2);
+            }
+        };
+        if name == "return" {
+            self.flow = Some(Flow::Return);
+            return Ok(number.unwrap_or(self.last_status));
+        }
+        let levels = number.unwrap_or(1).max(1) as usize;
+        self.flow = Some(if name == "break" {
+            Flow::Break(levels)
+        } else {
+            Flow::Continue(levels)
+        });
+        Ok(0)
+    }
+
+    // Handles break and continue at the end of a loop iteration, returning whether the loop
+    // should stop
+    fn end_iteration(&mut self) -> bool {
+        match self.flow {
+            Some(Flow::Break(n)) => {
+                self.flow = if n > 1 { Some(Flow::Break(n - 1)) } else { None };
+                true
+            }
+            Some(Flow::Continue(n)) if n > 1 => {
+                self.flow = Some(Flow::Continue(n - 1));
+                true
+            }
+            Some(Flow::Continue(_)) => {
+                self.flow = None;
+                false
+            }
+            Some(Flow::Return) | Some(Flow::Exit) => true,
+            None => false,
+        }
+    }
+
+    fn start_simple(
+        &mut self,
+        assignments: &[String],
+        words: &[String],
+        redirects: &[Redirect],
+        wait: bool,
+    ) -> Started {
+        self.substitution_status = None;
+        let expanded: Vec<Assignment> = assignments.iter()
+            .filter_map(|a| self.expand_assignment(a))
+            .collect();
+        // The arguments of declare that are assignments are expanded as assignments
+        let mut declared = Vec::new();
+        let arguments = if words.first().is_some_and(|w| parser::DECLARATIONS.contains(&w.as_str())) {
+            let (assigning, plain): (Vec<String>, Vec<String>) = words.iter()
+                .cloned()
+                .partition(|w| parser::assignment(w).is_some());
+            declared.extend(assigning.iter().filter_map(|a| self.expand_assignment(a)));
+            self.expand_words(&plain)
+        } else {
+            self.expand_words(words)
+        };
+        if self.expansion_failure() {
+            return Started::Done(1);
+        }
+        // The command gets the shell's ends of the pipes to process substitutions. The
+        // processes of the substitutions were started before this, so they don't hold on to
+        // each other's pipes.
+        for &(_, fd) in &self.substitutions {
+            unsafe { libc::fcntl(fd, libc::F_SETFD, 0) };
+        }
+        let values: Vec<(String, String)> = expanded.iter().map(Assignment::pair).collect();
+        let mut traced = arguments.clone();
+        traced.extend(declared.iter().map(|a| a.to_string()));
+        self.trace(&values, &traced);
+
+        // Assignments on their own set shell variables, and give the status of the last
+        // command substitution in them. Redirections still open their files.
+        if arguments.is_empty() {
+            let mut status = self.substitution_status.unwrap_or(0);
+            for assignment in expanded {
+                if let Err(error) = self.assign(assignment) {
+                    self.report(&error);
+                    status = 1;
+                }
+            }
+            return match self.redirect(redirects) {
+                Ok(saved) => {
+                    self.restore(saved);
+                    Started::Done(status)
+                }
+                Err(error) => {
+                    self.report(&error);
+                    Started::Done(1)
+                }
+            };
+        }
+
+        let internal = self.functions.contains_key(&arguments[0]) || self.is_builtin(&arguments[0]);
+        if internal && !wait {
+            return self.start_forked(|shell| {
+                shell.run_internal(&arguments, declared, &values, redirects)
+            });
+        }
+        if internal {
+            return Started::Done(self.run_internal(&arguments, declared, &values, redirects));
+        }
+        let saved = match self.redirect(redirects) {
+            Ok(saved) => saved,
+            Err(error) => {
+                self.report(&error);
+                return Started::Done(1);
+            }
+        };
+        let started = match self.spawn(&arguments, &values) {
+            Ok(pid) if wait => Started::Done(wait_pid(pid)),
+            Ok(pid) => Started::Running(pid),
+            Err(status) => Started::Done(status),
+        };
+        self.restore(saved);
+        started
+    }
+
+    // Runs a function or builtin in the shell itself, with assignments before it only set
+    // while it runs. The assignments among the arguments of declare come separately.
+    fn run_internal(
+        &mut self,
+        arguments: &[String],
+        declared: Vec<Assignment>,
+        values: &[(String, String)],
+        redirects: &[Redirect],
+    ) -> i32 {
+        let saved = match self.redirect(redirects) {
+            Ok(saved) => saved,
+            Err(error) => {
+                self.report(&error);
+                return 1;
+            }
+        };
+        let previous: Vec<_> = values.iter()
+            .map(|(name, value)| {
+                let previous = self.variable(name);
+                self.variables.insert(name, value);
+                (name, previous)
+            })
+            .collect();
+
+        let status = match self.functions.get(&arguments[0]).cloned() {
+            Some(body) => {
+                let result = self.call_function(body, &arguments[1..]);
+                self.builtin_status(&arguments[0], result)
+            }
+            None if !declared.is_empty() => {
+                let result = self.declare(&arguments[1..], declared);
+                self.builtin_status(&arguments[0], result)
+            }
+            None => self.run_builtin(&arguments[0], &arguments[1..]).unwrap_or(127),
+        };
+
+        for (name, value) in previous {
+            match value {
+                Some(value) => self.variables.insert(name, &value),
+                None => self.variables.remove(name),
+            }
+        }
+        self.restore(saved);
+        status
+    }
+
+    // Functions calling themselves without end would run out of stack, so calls can go only
+    // FUNCNEST deep, and no deeper than the stack has room for
+    fn call_function(&mut self, body: Rc<Command>, arguments: &[String]) -> error::Result<i32> {
+        let limit = self
+            .variable("FUNCNEST")
+            .and_then(|n| n.parse().ok())
+            .filter(|&n| n > 0)
+            .unwrap_or(FUNCNEST);
+        if self.function_depth >= limit || stack_low() {
+            let message = format!(
+                "maximum function nesting level exceeded ({})",
+                self.function_depth
+            );
+            return Err(ShellError::Message(message));
+        }
+        let saved = std::mem::replace(&mut self.arguments, arguments.to_vec());
+        self.function_depth += 1;
+        let status = self.execute(&body);
+        self.function_depth -= 1;
+        self.arguments = saved;
+        if self.flow == Some(Flow::Return) {
+            self.flow = None;
+        }
+        Ok(status)
+    }
+
+    // Connects each stage's output to the next one's input and waits for all of them. The
+    // status is that of the last stage.
+    fn execute_pipeline(&mut self, stages: &[Command]) -> i32 {
+        let helpers = self.helpers.len();
+        let mut started = Vec::new();
+        let mut input: Option<i32> = None;
+        for (i, stage) in stages.iter().enumerate() {
+            let pipe = if i + 1 < stages.len() {
+                match make_pipe() {
+                    Ok(pipe) => Some(pipe),
+                    Err(e) => {
+                        self.report(&format!("pipe: {}", e));
+                        break;
+                    }
+                }
+            } else {
+                None
+            };
+            let mut saved = Vec::new();
+            flush();
+            if let Some(read) = input.take() {
+                save_fd(0, &mut saved);
+                unsafe {
+                    libc::dup2(read, 0);
+                    libc::close(read);
+                }
+            }
+            if let Some((_, write)) = pipe {
+                save_fd(1, &mut saved);
+                unsafe {
+                    libc::dup2(write, 1);
+                    libc::close(write);
+                }
+            }
+            started.push(self.start(stage, false));
+            self.restore(saved);
+            input = pipe.map(|(read, _)| read);
+        }
+        if let Some(read) = input {
+            unsafe { libc::close(read) };
+        }
+        let mut status = 0;
+        for stage in started {
+            let stage_status = match stage {
+                Started::Done(status) => status,
+                Started::Running(pid) => wait_pid(pid),
+            };
+            // With pipefail the last command to fail decides the status
+            if stage_status != 0 || !self.options.pipefail {
+                status = stage_status;
+            }
+        }
+        // With the whole pipeline done, so are the process substitutions of its commands
+        for pid in self.helpers.drain(helpers..).collect::<Vec<_>>() {
+            wait_pid(pid);
+        }
+        status
+    }
+
+    // Starts a command that may expand process substitutions. Once it has started, the shell
+    // closes its ends of their pipes, and once it is done, waits for their processes.
+    fn with_substitutions<F: FnOnce(&mut ShellState) -> Started>(&mut self, f: F) -> Started {
+        let outer = std::mem::take(&mut self.substitutions);
+        let started = f(self);
+        let substitutions = std::mem::replace(&mut self.substitutions, outer);
+        for &(_, fd) in &substitutions {
+            unsafe { libc::close(fd) };
+        }
+        for (pid, _) in substitutions {
+            match started {
+                Started::Done(_) => {
+                    wait_pid(pid);
+                }
+                Started::Running(_) => self.helpers.push(pid),
+            }
+        }
+        started
+    }
+
+    // Runs `f` in a copy of the shell in a new process
+    fn start_forked<F: FnOnce(&mut ShellState) -> i32>(&mut self, f: F) -> Started {
+        flush();
+        match unsafe { libc::fork() } {
+            -1 => {
+                self.report(&format!("fork: {}", io::Error::last_os_error()));
+                Started::Done(1)
+            }
+            0 => {
+                close_exec_fds();
+                // A copy of the shell writing to a pipe nothing reads any more goes quietly,
+                // the way other commands do
+                unsafe { libc::signal(libc::SIGPIPE, libc::SIG_DFL) };
+                default_interrupts();
+                // Only the shell itself logs out
+                self.login = false;
+                let status = f(self);
+                flush();
+                unsafe { libc::_exit(status) }
+            }
+            pid => Started::Running(pid),
+        }
+    }
+
+    // Runs commands in another process and gives back what they printed, without the
+    // trailing newlines, for $(...)
+    pub fn capture(&mut self, source: &str) -> String {
+        let (read, write) = match make_pipe() {
+            Ok(pipe) => pipe,
+            Err(e) => {
+                self.report(&format!("pipe: {}", e));
+                return String::new();
+            }
+        };
+        let mut saved = Vec::new();
+        flush();
+        save_fd(1, &mut saved);
+        unsafe {
+            libc::dup2(write, 1);
+            libc::close(write);
+        }
+        let source = source.to_owned();
+        let started = self.start_forked(|shell| shell.run_script(&source, None));
+        self.restore(saved);
+
+        let mut output = Vec::new();
+        let mut file = unsafe { File::from_raw_fd(read) };
+        let _ = file.read_to_end(&mut output);
+        self.last_status = match started {
+            Started::Done(status) => status,
+            Started::Running(pid) => wait_pid(pid),
+        };
+        self.substitution_status = Some(self.last_status);
+        let output = String::from_utf8_lossy(&output);
+        output.trim_end_matches('\n').to_owned()
+    }
+
+    // Starts commands for <(...) or >(...) with their output or input connected to a pipe,
+    // and gives the name of the shell's end for the command being expanded to open
+    pub fn substitute_process(&mut self, source: &str, reading: bool) -> String {
+        let (read, write) = match make_pipe() {
+            Ok(pipe) => pipe,
+            Err(e) => {
+                self.report(&format!("pipe: {}", e));
+                return String::new();
+            }
+        };
+        let (fd, theirs, ours) = if reading { (1, write, read) } else { (0, read, write) };
+        let mut saved = Vec::new();
+        flush();
+        save_fd(fd, &mut saved);
+        unsafe {
+            libc::dup2(theirs, fd);
+            libc::close(theirs);
+        }
+        let source = source.to_owned();
+        let started = self.start_forked(|shell| shell.run_script(&source, None));
+        self.restore(saved);
+        match started {
+            Started::Running(pid) => self.substitutions.push((pid, ours)),
+            Started::Done(_) => unsafe {
+                libc::close(ours);
+            },
+        }
+        format!("/dev/fd/{}", ours)
+    }
+
+    // Points file descriptors where the redirections say, returning what they pointed to
+    // before so they can be put back
+    pub fn redirect(&mut self, redirects: &[Redirect]) -> error::Result<Vec<(i32, i32)>> {
+        let mut saved = Vec::new();
+        flush();
+        for redirect in redirects {
+            // A here-document is only expanded when no part of its delimiter was quoted
+            let target = match redirect.here_document {
+                Some(ref body) if redirect.target.contains(['\'', '"', '\\']) => body.clone(),
+                Some(ref body) => self.expand_here_document(body),
+                None => self.expand_word(&redirect.target),
+            };
+            if let Err(error) = self.apply_redirect(redirect, &target, &mut saved) {
+                self.restore(saved);
+                return Err(error);
+            }
+        }
+        Ok(saved)
+    }
+
+    fn apply_redirect(
+        &self,
+        redirect: &Redirect,
+        target: &str,
+        saved: &mut Vec<(i32, i32)>,
+    ) -> error::Result<()> {
+        let mut options = OpenOptions::new();
+        let (fd, both) = match redirect.operator.as_str() {
+            "<" => {
+                options.read(true);
+                (redirect.fd.unwrap_or(0), false)
+            }
+            "<>" => {
+                options.read(true).write(true).create(true);
+                (redirect.fd.unwrap_or(0), false)
+            }
+            ">>" => {
+                options.append(true).create(true);
+                (redirect.fd.unwrap_or(1), false)
+            }
+            "<<" | "<<-" => {
+                let fd = redirect.fd.unwrap_or(0);
+                let source = here_document_file(target)?;
+                save_fd(fd, saved);
+                unsafe {
+                    libc::dup2(source, fd);
+                    libc::close(source);
+                }
+                return Ok(());
+            }
+            "<&" | ">&" if target == "-" => {
+                let fd = redirect.fd.unwrap_or(if redirect.operator == "<&" { 0 } else { 1 });
+                save_fd(fd, saved);
+                unsafe { libc::close(fd) };
+                return Ok(());
+            }
+            "<&" | ">&" if target.bytes().all(|b| b.is_ascii_digit()) => {
+                let fd = redirect.fd.unwrap_or(if redirect.operator == "<&" { 0 } else { 1 });
+                let source: i32 = target.parse().unwrap_or(-1);
+                if source < 0 || unsafe { libc::fcntl(source, libc::F_GETFD) } == -1 {
+                    return Err(ShellError::Message(format!(
+                        "{}: bad file descriptor",
+                        target
+                    )));
+                }
+                save_fd(fd, saved);
+                unsafe { libc::dup2(source, fd) };
+                return Ok(());
+            }
+            // &>file and >&file send both output and errors to the file
+            "&>" | ">&" => {
+                options.write(true).create(true).truncate(true);
+                (1, true)
+            }
+            _ => {
+                options.write(true).create(true).truncate(true);
+                (redirect.fd.unwrap_or(1), false)
+            }
+        };
+        let cwd = self.variable("PWD").unwrap_or_default();
+        // noclobber keeps > from truncating files, but devices like /dev/null are fine
+        let truncates = matches!(redirect.operator.as_str(), ">" | "&>" | ">&");
+        if truncates && self.options.noclobber && Path::new(&cwd).join(target).is_file() {
+            let message = format!("{}: cannot overwrite existing file", target);
+            return Err(ShellError::Message(message));
+        }
+        let file = options.mode(0o666)
+            .open(Path::new(&cwd).join(target))
+            .map_err(|e| ShellError::io(target, e))?;
+        let source = file.into_raw_fd();
+        for fd in if both { vec![1, 2] } else { vec![fd] } {
+            save_fd(fd, saved);
+            unsafe { libc::dup2(source, fd) };
+        }
+        unsafe { libc::close(source) };
+        Ok(())
+    }
+
+    pub fn restore(&mut self, saved: Vec<(i32, i32)>) {
+        flush();
+        for (fd, copy) in saved.into_iter().rev() {
+            unsafe {
+                if copy >= 0 {
+                    libc::dup2(copy, fd);
+                    libc::close(copy);
+                } else {
+                    libc::close(fd);
+                }
+            }
+        }
+    }
+}
+
+// The text of a here-document, in a file for the command to read
+fn here_document_file(text: &str) -> error::Result<i32> {
+    temp_file(text).map_err(|e| ShellError::io("here-document", e))
+}
+
+// A file holding `text`, read from the start. It is already deleted, so it goes away once
+// nothing has it open.
+pub fn temp_file(text: &str) -> io::Result<i32> {
+    let directory = env::var("TMPDIR").unwrap_or_else(|_| "/tmp".to_owned());
+    let template = format!("{}/rsh-XXXXXX", directory.trim_end_matches('/'));
+    let mut path = CString::new(template)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "bad TMPDIR"))?
+        .into_bytes_with_nul();
+    let fd = unsafe { libc::mkstemp(path.as_mut_ptr() as *mut libc::c_char) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    unsafe { libc::unlink(path.as_ptr() as *const libc::c_char) };
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    file.write_all(text.as_bytes())
+        .and_then(|_| file.seek(SeekFrom::Start(0)))?;
+    // Out of the way of the descriptors that redirections name
+    let fd = file.into_raw_fd();
+    let moved = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 10) };
+    unsafe { libc::close(fd) };
+    if moved < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(moved)
+}
+
+// Keeps a copy of `fd` out of the way, or -1 if it wasn't open, the first time it is redirected
+pub fn save_fd(fd: i32, saved: &mut Vec<(i32, i32)>) {
+    if !saved.iter().any(|&(f, _)| f == fd) {
+        let copy = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 10) };
+        saved.push((fd, copy));
+    }
+}
+
+// A forked shell has no use for the descriptors the shell keeps for itself, like the far end
+// of a pipe it is reading, and holding them open could keep the other side waiting. They are
+// the ones an exec would close.
+fn close_exec_fds() {
+    let fds: Vec<i32> = match std::fs::read_dir("/proc/self/fd") {
+        Ok(entries) => {
+            entries.filter_map(|e| e.ok())
+                .filter_map(|e| e.file_name().to_str().and_then(|n| n.parse().ok()))
+                .collect()
+        }
+        Err(_) => return,
+    };
+    for fd in fds.into_iter().filter(|&fd| fd > 2) {
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+        if flags != -1 && flags & libc::FD_CLOEXEC != 0 {
+            unsafe { libc::close(fd) };
+        }
+    }
+}
+
+// An interactive shell outlives ^C and ^\, which are meant for the command it is running.
+// The line editor reads them as keys, with the terminal sending no signals.
+pub fn ignore_interrupts() {
+    unsafe {
+        libc::signal(libc::SIGINT, libc::SIG_IGN);
+        libc::signal(libc::SIGQUIT, libc::SIG_IGN);
+    }
+}
+
+// Undoes ignore_interrupts in a process the shell starts, since ignored signals stay ignored
+// through exec
+pub fn default_interrupts() {
+    unsafe {
+        libc::signal(libc::SIGINT, libc::SIG_DFL);
+        libc::signal(libc::SIGQUIT, libc::SIG_DFL);
+    }
+}
+
+// Whether the stack is too close to its end for something that recurses to go deeper
+pub fn stack_low() -> bool {
+    stack_left().is_some_and(|left| left < STACK_RESERVE)
+}
+
+// How much of this thread's stack is still free, where the system can tell
+fn stack_left() -> Option<usize> {
+    let marker = 0u8;
+    let here = &marker as *const u8 as usize;
+    let end = match STACK_END.with(|end| end.get()) {
+        Some(end) => end,
+        None => {
+            let mut start = std::ptr::null_mut();
+            let mut size = 0;
+            unsafe {
+                let mut attributes: libc::pthread_attr_t = std::mem::zeroed();
+                if libc::pthread_getattr_np(libc::pthread_self(), &mut attributes) != 0 {
+                    return None;
+                }
+                let found = libc::pthread_attr_getstack(&attributes, &mut start, &mut size);
+                libc::pthread_attr_destroy(&mut attributes);
+                if found != 0 {
+                    return None;
+                }
+            }
+            STACK_END.with(|end| end.set(Some(start as usize)));
+            start as usize
+        }
+    };
+    Some(here.saturating_sub(end))
+}
+
+// A pipe whose ends are closed in the commands the shell starts, unless moved onto their
+// standard input or output
+fn make_pipe() -> io::Result<(i32, i32)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((fds[0], fds[1]))
+}
+
+// Anything buffered has to reach the terminal before the file descriptors change or the
+// process forks
+pub fn flush() {
+    let _ = io::stdout().flush();
+    let _ = io::stderr().flush();
+}
+
+// Standard output for builtins, which unlike io::stdout() has no buffer that output could be
+// left in after a failed write, to turn up later wherever standard output points then
+pub struct Output;
+
+pub fn output() -> Output {
+    Output
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = unsafe { libc::write(1, buf.as_ptr() as *const libc::c_void, buf.len()) };
+        if written < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(written as usize)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// The error without the "(os error N)" that io::Error adds
+pub fn io_message(error: &io::Error) -> String {
+    let message = error.to_string();
+    match message.find(" (os error") {
+        Some(end) => message[..end].to_owned(),
+        None => message,
+    }
+}
+
+// Waits for a process and gives its exit status, 128 plus the signal number if it was killed
+pub fn wait_pid(pid: libc::pid_t) -> i32 {
+    let mut status = 0;
+    loop {
+        if unsafe { libc::waitpid(pid, &mut status, 0) } != -1 {
+            return exit_status(status);
+        }
+        if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+            return 127;
+        }
+    }
+}
+
+pub fn exit_status(status: i32) -> i32 {
+    if libc::WIFEXITED(status) {
+        libc::WEXITSTATUS(status)
+    } else if libc::WIFSIGNALED(status) {
+        128 + libc::WTERMSIG(status)
+    } else {
+        0
+    }
+}
@@ -0,0 +1,725 @@
+extern crate std;
+extern crate glob;
+
+use std::process;
+use self::glob::{MatchOptions, Pattern};
+use expression;
+use json::Value;
+use parser;
+use state::ShellState;
+
+// One argument of an expanded command. `pattern` is the same text with its quoted parts
+// escaped, for matching file names and case patterns.
+#[derive(Default)]
+struct Field {
+    text: String,
+    pattern: String,
+    glob: bool,
+}
+
+// The fields a word expands to, built up a piece at a time
+struct Fields {
+    fields: Vec<Field>,
+    // The next piece starts a new field
+    split: bool,
+    // Whether unquoted expansions are split into several fields, using these separators
+    splitting: bool,
+    separators: String,
+}
+
+impl Fields {
+    fn current(&mut self) -> &mut Field {
+        if self.fields.is_empty() || self.split {
+            self.fields.push(Field::default());
+            self.split = false;
+        }
+        self.fields.last_mut().unwrap()
+    }
+
+    fn literal(&mut self, text: &str) {
+        let field = self.current();
+        field.text.push_str(text);
+        field.pattern.push_str(text);
+        field.glob |= text.contains(['*', '?', '[']);
+    }
+
+    fn quoted(&mut self, text: &str) {
+        let field = self.current();
+        field.text.push_str(text);
+        field.pattern.push_str(&Pattern::escape(text));
+    }
+
+    // The result of an expansion, which is split on $IFS when it isn't quoted
+    fn expanded(&mut self, text: &str, quoted: bool) {
+        if quoted {
+            return self.quoted(text);
+        }
+        if !self.splitting {
+            return self.literal(text);
+        }
+        let separators = self.separators.clone();
+        for (i, piece) in text.split(|c| separators.contains(c)).enumerate() {
+            if i > 0 {
+                self.end_field();
+            }
+            if !piece.is_empty() {
+                self.literal(piece);
+            }
+        }
+    }
+
+    fn end_field(&mut self) {
+        if !self.fields.is_empty() {
+            self.split = true;
+        }
+    }
+}
+
+impl ShellState {
+    // Expands words into the arguments of a command: parameters, command substitution, field
+    // splitting, file name globs and quote removal
+    pub fn expand_words(&mut self, words: &[String]) -> Vec<String> {
+        let mut arguments = Vec::new();
+        for word in words {
+            for field in self.expand_fields(word, true) {
+                let matches = if self.options.noglob { None } else { self.glob(&field) };
+                match matches {
+                    Some(matches) => arguments.extend(matches),
+                    None => arguments.push(field.text),
+                }
+            }
+        }
+        arguments
+    }
+
+    // Expands a word into one string, without splitting it or matching file names, as for
+    // assignments and redirection targets
+    pub fn expand_word(&mut self, word: &str) -> String {
+        let fields: Vec<String> = self.expand_fields(word, false)
+            .into_iter()
+            .map(|f| f.text)
+            .collect();
+        fields.join(" ")
+    }
+
+    // Expands a pattern, like those of case, so that its quoted parts match literally
+    pub fn expand_pattern(&mut self, word: &str) -> String {
+        let fields: Vec<String> = self.expand_fields(word, false)
+            .into_iter()
+            .map(|f| f.pattern)
+            .collect();
+        fields.join(" ")
+    }
+
+    // Value of a variable or of a special parameter like $? or $1
+    pub fn parameter(&self, name: &str) -> Option<String> {
+        match name {
+            "?" => Some(self.last_status.to_string()),
+            "$" => Some(process::id().to_string()),
+            "#" => Some(self.arguments.len().to_string()),
+            "!" => self.last_background.map(|pid| pid.to_string()),
+            "0" => Some(self.name.clone()),
+            "@" | "*" => Some(self.arguments.join(" ")),
+            "-" => Some(self.flags()),
+            _ => {
+                match name.parse::<usize>() {
+                    Ok(n) => self.arguments.get(n.wrapping_sub(1)).cloned(),
+                    // An array on its own stands for its first element
+                    Err(_) => {
+                        self.variable(name)
+                            .or_else(|| self.arrays.get(name).and_then(|a| a.first()))
+                    }
+                }
+            }
+        }
+    }
+
+    // The value of a parameter that is being expanded, which with -u has to be set. $@ and $*
+    // may always be empty.
+    fn lookup(&mut self, name: &str) -> String {
+        match self.parameter(name) {
+            Some(value) => value,
+            None => self.unset_value(name),
+        }
+    }
+
+    // What a parameter or array element that isn't set expands to, which -u makes an error
+    fn unset_value(&mut self, reference: &str) -> String {
+        if self.options.nounset && reference != "@" && reference != "*" {
+            self.report(&format!("{}: unbound variable", reference));
+            self.expansion_failed = true;
+        }
+        String::new()
+    }
+
+    fn expand_fields(&mut self, word: &str, splitting: bool) -> Vec<Field> {
+        let mut fields = Fields {
+            fields: Vec::new(),
+            split: false,
+            splitting,
+            separators: self.variable("IFS").unwrap_or_else(|| " \t\n".to_owned()),
+        };
+        let bytes = word.as_bytes();
+        let mut i = 0;
+
+        // A leading ~ on its own or before a / is the home directory
+        if word == "~" || word.starts_with("~/") {
+            if let Some(home) = self.variable("HOME") {
+                fields.quoted(&home);
+                i = 1;
+            }
+        }
+
+        while i < bytes.len() {
+            match bytes[i] {
+                b'\\' => {
+                    let next = word[i + 1..].chars().next();
+                    match next {
+                        // A line continuation leaves nothing behind
+                        Some('\n') => {}
+                        Some(c) => fields.quoted(c.encode_utf8(&mut [0; 4])),
+                        None => fields.literal("\\"),
+                    }
+                    i += 1 + next.map_or(0, |c| c.len_utf8());
+                }
+                b'\'' => {
+                    let end = word[i + 1..].find('\'').map_or(word.len(), |n| i + 1 + n);
+                    fields.quoted(&word[i + 1..end]);
+                    i = end + 1;
+                }
+                b'"' => {
+                    let end = parser::skip_quoted(word, i).unwrap_or(word.len() + 1);
+                    self.expand_double_quoted(&word[i + 1..end - 1], &mut fields);
+                    i = end;
+                }
+                b'`' => {
+                    let end = parser::skip_quoted(word, i).unwrap_or(word.len() + 1);
+                    let output = self.capture(&unescape_backquoted(&word[i + 1..end - 1]));
+                    fields.expanded(&output, false);
+                    i = end;
+                }
+                b'$' => i = self.expand_dollar(word, i, &mut fields, false),
+                b'<' | b'>' if bytes.get(i + 1) == Some(&b'(') => {
+                    let end = parser::skip_nested(word, i + 1).unwrap_or(word.len() + 1);
+                    let path = self.substitute_process(&word[i + 2..end - 1], bytes[i] == b'<');
+                    fields.quoted(&path);
+                    i = end;
+                }
+                b'@' if bytes.get(i + 1) == Some(&b'(') => {
+                    let end = parser::skip_nested(word, i + 1).unwrap_or(word.len() + 1);
+                    self.expand_expression(&word[i + 2..end - 1], &mut fields);
+                    i = end;
+                }
+                _ => {
+                    let next = i + word[i..].chars().next().map_or(1, |c| c.len_utf8());
+                    let end = word[next..]
+                        .find(|c| "\\'\"`$<>@".contains(c))
+                        .map_or(word.len(), |n| next + n);
+                    fields.literal(&word[i..end]);
+                    i = end;
+                }
+            }
+        }
+        fields.fields
+    }
+
+    fn expand_double_quoted(&mut self, text: &str, fields: &mut Fields) {
+        // "" is still an argument, even though it is empty, but "$@" with no arguments isn't
+        if !expands_each(text) {
+            fields.quoted("");
+        }
+        self.expand_quoted(text, fields, b"$`\"\\");
+    }
+
+    // Expands the body of a here-document whose delimiter wasn't quoted, which is like a
+    // double-quoted string except that " is nothing special
+    pub fn expand_here_document(&mut self, body: &str) -> String {
+        let mut fields = Fields {
+            fields: Vec::new(),
+            split: false,
+            splitting: false,
+            separators: String::new(),
+        };
+        self.expand_quoted(body, &mut fields, b"$`\\");
+        let fields: Vec<String> = fields.fields.into_iter().map(|f| f.text).collect();
+        fields.join(" ")
+    }
+
+    // Parameters and command substitution in quoted text, where a backslash only escapes the
+    // characters in `escapable` and newlines
+    fn expand_quoted(&mut self, text: &str, fields: &mut Fields, escapable: &[u8]) {
+        let bytes = text.as_bytes();
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'\\' => {
+                    match bytes.get(i + 1) {
+                        Some(&b'\n') => i += 2,
+                        Some(c) if escapable.contains(c) => {
+                            fields.quoted(&text[i + 1..i + 2]);
+                            i += 2;
+                        }
+                        _ => {
+                            fields.quoted("\\");
+                            i += 1;
+                        }
+                    }
+                }
+                b'$' => i = self.expand_dollar(text, i, fields, true),
+                b'`' => {
+                    let end = parser::skip_quoted(text, i).unwrap_or(text.len() + 1);
+                    let output = self.capture(&unescape_backquoted(&text[i + 1..end - 1]));
+                    fields.quoted(&output);
+                    i = end;
+                }
+                _ => {
+                    let end = text[i..]
+                        .find(|c| "\\`$".contains(c))
+                        .map_or(text.len(), |n| i + n);
+                    fields.quoted(&text[i..end]);
+                    i = end;
+                }
+            }
+        }
+    }
+
+    // Expands the $ expression at text[i..], returning where it ends
+    fn expand_dollar(&mut self, text: &str, i: usize, fields: &mut Fields, quoted: bool) -> usize {
+        let bytes = text.as_bytes();
+        match bytes.get(i + 1) {
+            Some(&b'(') => {
+                let end = parser::skip_nested(text, i + 1).unwrap_or(text.len() + 1);
+                let output = self.capture(&text[i + 2..end - 1]);
+                fields.expanded(&output, quoted);
+                end
+            }
+            Some(&b'{') => {
+                let end = parser::skip_nested(text, i + 1).unwrap_or(text.len() + 1);
+                self.expand_braced(&text[i + 2..end - 1], fields, quoted);
+                end
+            }
+            Some(&b'\'') if !quoted => {
+                let (value, end) = ansi_c_quoted(text, i + 2);
+                fields.quoted(&value);
+                end
+            }
+            Some(&b'@') if quoted => {
+                // "$@" gives each argument as a field of its own
+                for (n, argument) in self.arguments.clone().iter().enumerate() {
+                    if n > 0 {
+                        fields.end_field();
+                    }
+                    fields.quoted(argument);
+                }
+                i + 2
+            }
+            Some(&b'@') | Some(&b'*') if !quoted => {
+                for (n, argument) in self.arguments.clone().iter().enumerate() {
+                    if n > 0 {
+                        fields.end_field();
+                    }
+                    fields.expanded(argument, false);
+                }
+                i + 2
+            }
+            Some(c) if c.is_ascii_digit() || b"?$#!*-".contains(c) => {
+                let value = self.lookup(&text[i + 1..i + 2]);
+                fields.expanded(&value, quoted);
+                i + 2
+            }
+            Some(c) if c.is_ascii_alphabetic() || *c == b'_' => {
+                let length = bytes[i + 1..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_alphanumeric() || **c == b'_')
+                    .count();
+                let value = self.lookup(&text[i + 1..i + 1 + length]);
+                fields.expanded(&value, quoted);
+                i + 1 + length
+            }
+            _ => {
+                fields.expanded("$", quoted);
+                i + 1
+            }
+        }
+    }
+
+    // ${name}, ${#name}, ${!name} and ${name<operation>word}, where name can have an array
+    // subscript
+    fn expand_braced(&mut self, inner: &str, fields: &mut Fields, quoted: bool) {
+        if let Some(name) = inner.strip_prefix('#').filter(|n| !n.is_empty()) {
+            // ${#name[@]} counts the elements of an array, ${#name[i]} measures one
+            let length = match split_subscript(name) {
+                Some((name, subscript)) if subscript == "@" || subscript == "*" => {
+                    self.elements(name, subscript).map_or(0, |e| e.len())
+                }
+                Some((name, subscript)) => {
+                    self.elements(name, subscript).map_or(0, |e| e.concat().chars().count())
+                }
+                None => self.lookup(name).chars().count(),
+            };
+            return fields.expanded(&length.to_string(), quoted);
+        }
+        if let Some(reference) = inner.strip_prefix('!').filter(|n| !n.is_empty()) {
+            return self.expand_indirect(reference, fields, quoted);
+        }
+        let bytes = inner.as_bytes();
+        let length = match bytes.first() {
+            Some(c) if c.is_ascii_digit() => bytes.iter().take_while(|c| c.is_ascii_digit()).count(),
+            Some(c) if b"?$#!@*-".contains(c) => 1,
+            _ => {
+                bytes.iter()
+                    .take_while(|c| c.is_ascii_alphanumeric() || **c == b'_')
+                    .count()
+            }
+        };
+        let (name, operation) = inner.split_at(length);
+        if name.is_empty() {
+            self.report(&format!("${{{}}}: bad substitution", inner));
+            self.expansion_failed = true;
+            return;
+        }
+        let subscript = split_subscript(inner).map(|(_, subscript)| subscript);
+        let operation = match subscript {
+            Some(subscript) => &operation[subscript.len() + 2..],
+            None => operation,
+        };
+
+        // ${name[@]}, ${name[*]}, $@ and $* are lists, which can be sliced
+        let list = match subscript {
+            Some(subscript) if subscript == "@" || subscript == "*" => {
+                Some((self.elements(name, subscript).unwrap_or_default(), subscript == "@"))
+            }
+            None if name == "@" || name == "*" => Some((self.arguments.clone(), name == "@")),
+            _ => None,
+        };
+        if let Some(slice) = operation.strip_prefix(':').filter(|s| is_slice(s)) {
+            match list {
+                Some((mut values, each)) => {
+                    // Slices of the arguments count $0 as the first
+                    if subscript.is_none() {
+                        values.insert(0, self.name.clone());
+                    }
+                    match self.slice_bounds(slice, values.len()) {
+                        Some((start, end)) => self.expand_list(&values[start..end], each, fields, quoted),
+                        None => self.bad_substitution(inner),
+                    }
+                }
+                None => {
+                    let value = match subscript {
+                        Some(subscript) => self.elements(name, subscript).map(|e| e.concat()),
+                        None => Some(self.lookup(name)),
+                    };
+                    let chars: Vec<char> = value.unwrap_or_default().chars().collect();
+                    match self.slice_bounds(slice, chars.len()) {
+                        Some((start, end)) => {
+                            let value: String = chars[start..end].iter().collect();
+                            fields.expanded(&value, quoted)
+                        }
+                        None => self.bad_substitution(inner),
+                    }
+                }
+            }
+            return;
+        }
+        let value = match list {
+            Some((values, each)) if operation.is_empty() => {
+                return self.expand_list(&values, each, fields, quoted);
+            }
+            Some((values, _)) => {
+                if values.is_empty() { None } else { Some(values.join(" ")) }
+            }
+            None => {
+                match subscript {
+                    Some(subscript) => self.elements(name, subscript).map(|e| e.concat()),
+                    None => self.parameter(name),
+                }
+            }
+        };
+
+        // With a colon, an empty value counts as unset
+        let (colon, operation) = match operation.strip_prefix(':') {
+            Some(rest) if rest.starts_with(|c| "-=+?".contains(c)) => (true, rest),
+            _ => (false, operation),
+        };
+        let set = value.as_ref().is_some_and(|v| !colon || !v.is_empty());
+        // An element that isn't there is unset, like a variable
+        let reference = match subscript {
+            Some(subscript) => format!("{}[{}]", name, subscript),
+            None => name.to_owned(),
+        };
+        let mut chars = operation.chars();
+        let result = match chars.next() {
+            None if value.is_some() => value.unwrap_or_default(),
+            None => self.unset_value(&reference),
+            Some('-') if set => value.unwrap_or_default(),
+            Some('-') => self.expand_word(chars.as_str()),
+            Some('=') if set => value.unwrap_or_default(),
+            Some('=') => {
+                let word = self.expand_word(chars.as_str());
+                if parser::is_name(name) {
+                    self.variables.insert(name, &word);
+                }
+                word
+            }
+            Some('+') if set => self.expand_word(chars.as_str()),
+            Some('+') => String::new(),
+            Some('?') if set => value.unwrap_or_default(),
+            Some('?') => {
+                let message = match chars.as_str() {
+                    "" => "parameter not set".to_owned(),
+                    word => self.expand_word(word),
+                };
+                self.report(&format!("{}: {}", name, message));
+                self.expansion_failed = true;
+                String::new()
+            }
+            Some(c) if c == '#' || c == '%' => {
+                let value = value.unwrap_or_else(|| self.unset_value(&reference));
+                let longest = chars.as_str().starts_with(c);
+                let pattern = if longest { &operation[2..] } else { &operation[1..] };
+                let pattern = self.expand_pattern(pattern);
+                remove_match(&value, &pattern, c == '#', longest)
+            }
+            _ => return self.bad_substitution(inner),
+        };
+        fields.expanded(&result, quoted);
+    }
+
+    // ${!name[@]} gives the indices or keys of an array, and ${!name} the value of the
+    // variable whose name name holds
+    fn expand_indirect(&mut self, reference: &str, fields: &mut Fields, quoted: bool) {
+        match split_subscript(reference) {
+            Some((name, subscript)) if (subscript == "@" || subscript == "*") &&
+                                       reference.len() == name.len() + 3 => {
+                let keys = self.array_keys(name);
+                self.expand_list(&keys, subscript == "@", fields, quoted)
+            }
+            _ if parser::is_name(reference) => {
+                let target = self.lookup(reference);
+                let valid = parser::is_name(&target) ||
+                    split_subscript(&target).is_some_and(|(n, s)| target.len() == n.len() + s.len() + 2) ||
+                    (!target.is_empty() && target.bytes().all(|c| c.is_ascii_digit()));
+                if valid {
+                    self.expand_braced(&target, fields, quoted);
+                } else if !target.is_empty() || self.parameter(reference).is_some() {
+                    self.report(&format!("{}: invalid indirect expansion", target));
+                    self.expansion_failed = true;
+                }
+            }
+            _ => self.bad_substitution(&format!("!{}", reference)),
+        }
+    }
+
+    // Expands to each value as a field of its own for @. For * in double quotes, they are one
+    // field, joined by the first character of $IFS.
+    fn expand_list(&mut self, values: &[String], each: bool, fields: &mut Fields, quoted: bool) {
+        if quoted && !each {
+            let separator = match self.variable("IFS") {
+                Some(ifs) => ifs.chars().take(1).collect(),
+                None => " ".to_owned(),
+            };
+            return fields.expanded(&values.join(&separator), quoted);
+        }
+        for (n, value) in values.iter().enumerate() {
+            if n > 0 {
+                fields.end_field();
+            }
+            fields.expanded(value, quoted);
+        }
+    }
+
+    // @(expression), as its value with nothing split or globbed. A list is a field for each
+    // item, and null is nothing at all.
+    fn expand_expression(&mut self, text: &str, fields: &mut Fields) {
+        match self.evaluate_expression(text) {
+            Ok(Value::Array(items)) => {
+                let items: Vec<String> = items.iter().map(expression::display).collect();
+                self.expand_list(&items, true, fields, true);
+            }
+            Ok(Value::Null) => {}
+            Ok(value) => fields.quoted(&expression::display(&value)),
+            Err(message) => {
+                self.report(&format!("@({}): {}", text, message));
+                self.expansion_failed = true;
+            }
+        }
+    }
+
+    // The start and end that ${name:offset:length} picks out of `len` characters or elements.
+    // A negative offset counts back from the end, and so does a negative length.
+    fn slice_bounds(&mut self, slice: &str, len: usize) -> Option<(usize, usize)> {
+        let (offset, length) = match slice.split_once(':') {
+            Some((offset, length)) => (offset, Some(length)),
+            None => (slice, None),
+        };
+        let len = len as i64;
+        let offset: i64 = self.expand_word(offset).trim().parse().ok()?;
+        let start = if offset < 0 { len + offset } else { offset };
+        if start < 0 {
+            return Some((0, 0));
+        }
+        let start = start.min(len);
+        let end = match length {
+            Some(length) => {
+                let length: i64 = self.expand_word(length).trim().parse().ok()?;
+                if length < 0 { len + length } else { start + length }
+            }
+            None => len,
+        };
+        if end < start {
+            return None;
+        }
+        Some((start as usize, end.min(len) as usize))
+    }
+
+    fn bad_substitution(&mut self, inner: &str) {
+        self.report(&format!("${{{}}}: bad substitution", inner));
+        self.expansion_failed = true;
+    }
+
+    // File names matching a field with unquoted glob characters, relative to the working
+    // directory like the field itself. A glob that matches nothing stays as it is.
+    fn glob(&self, field: &Field) -> Option<Vec<String>> {
+        if !field.glob {
+            return None;
+        }
+        let cwd = self.variable("PWD").unwrap_or_default();
+        let directory = format!("{}/", cwd.trim_end_matches('/'));
+        let absolute = field.pattern.starts_with('/');
+        let pattern = if absolute {
+            field.pattern.clone()
+        } else {
+            format!("{}{}", Pattern::escape(&directory), field.pattern)
+        };
+        let options = MatchOptions {
+            require_literal_leading_dot: true,
+            ..MatchOptions::new()
+        };
+        let matches: Vec<String> = glob::glob_with(&pattern, options)
+            .ok()?
+            .filter_map(Result::ok)
+            .map(|path| {
+                let path = path.to_string_lossy().into_owned();
+                match path.strip_prefix(&directory) {
+                    Some(relative) if !absolute => relative.to_owned(),
+                    _ => path,
+                }
+            })
+            .collect();
+        if matches.is_empty() { None } else { Some(matches) }
+    }
+}
+
+// Whether the text of a double-quoted string is just $@, ${name[@]} or ${!name[@]}, maybe
+// sliced, which give a field for each element and so none at all when there are none
+fn expands_each(text: &str) -> bool {
+    if text == "$@" {
+        return true;
+    }
+    let inner = match text.strip_prefix("${").and_then(|t| t.strip_suffix('}')) {
+        Some(inner) => inner,
+        None => return false,
+    };
+    let inner = inner.strip_prefix('!').unwrap_or(inner);
+    let rest = match split_subscript(inner) {
+        Some((name, "@")) => &inner[name.len() + 3..],
+        Some(_) => return false,
+        None if inner.starts_with('@') => &inner[1..],
+        None => return false,
+    };
+    rest.is_empty() || rest.strip_prefix(':').is_some_and(is_slice)
+}
+
+// Whether what follows the colon in ${name:...} is an offset rather than one of :- := :+ :?
+fn is_slice(rest: &str) -> bool {
+    !rest.starts_with(|c| "-=+?".contains(c))
+}
+
+// Splits name[subscript] at the start of a ${...} expression
+fn split_subscript(inner: &str) -> Option<(&str, &str)> {
+    let open = inner.find('[')?;
+    if !parser::is_name(&inner[..open]) {
+        return None;
+    }
+    let close = open + inner[open..].find(']')?;
+    Some((&inner[..open], &inner[open + 1..close]))
+}
+
+// Removes the shortest or longest prefix or suffix of `value` matching `pattern`
+fn remove_match(value: &str, pattern: &str, prefix: bool, longest: bool) -> String {
+    let pattern = match Pattern::new(pattern) {
+        Ok(pattern) => pattern,
+        Err(_) => return value.to_owned(),
+    };
+    let mut boundaries: Vec<usize> = value.char_indices().map(|(i, _)| i).collect();
+    boundaries.push(value.len());
+    if prefix == longest {
+        boundaries.reverse();
+    }
+    for i in boundaries {
+        if prefix && pattern.matches(&value[..i]) {
+            return value[i..].to_owned();
+        }
+        if !prefix && pattern.matches(&value[i..]) {
+            return value[..i].to_owned();
+        }
+    }
+    value.to_owned()
+}
+
+// In `...`, a backslash only quotes $, ` and another backslash
+fn unescape_backquoted(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' && chars.peek().is_some_and(|n| "$`\\".contains(*n)) {
+            output.push(chars.next().unwrap());
+        } else {
+            output.push(c);
+        }
+    }
+    output
+}
+
+// Decodes the C-style escapes of $'...' starting at `start`, just after the quote, and
+// returns the text along with the index past the closing quote
+fn ansi_c_quoted(text: &str, start: usize) -> (String, usize) {
+    let mut output = String::new();
+    let mut chars = text[start..].char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\'' => return (output, start + i + 1),
+            '\\' => {
+                let escaped = match chars.next() {
+                    Some((_, c)) => c,
+                    None => break,
+                };
+                match escaped {
+                    'n' => output.push('\n'),
+                    't' => output.push('\t'),
+                    'r' => output.push('\r'),
+                    'a' => output.push('\x07'),
+                    'b' => output.push('\x08'),
+                    'e' | 'E' => output.push('\x1b'),
+                    'x' | '0' => {
+                        let (radix, digits) = if escaped == 'x' { (16, 2) } else { (8, 3) };
+                        let mut value = 0;
+                        for _ in 0..digits {
+                            match chars.peek().and_then(|&(_, c)| c.to_digit(radix)) {
+                                Some(digit) => {
+                                    value = value * radix + digit;
+                                    chars.next();
+                                }
+                                None => break,
+                            }
+                        }
+                        output.extend(std::char::from_u32(value));
+                    }
+                    c => output.push(c),
+                }
+            }
+            c => output.push(c),
+        }
+    }
+    (output, text.len())
+}
@@ -0,0 +1,953 @@
+extern crate std;
+
+use std::cmp::Ordering;
+use std::io::Write;
+use arrays::Array;
+use exec;
+use error::ShellError;
+use json::Value;
+use state::ShellState;
+
+// A small expression language for computing things in the middle of commands, like xonsh's
+// Python mode. `= expression` on a line of its own prints the result, and @(expression) in a
+// word expands to it. The values are those of JSON: numbers, strings, lists, maps, true,
+// false and null. $name gives a shell variable, as a string, or as a list or map for arrays.
+
+type Result<T> = std::result::Result<T, String>;
+
+// How deeply brackets, negations and nots can nest, which keeps the recursive parser and
+// evaluator well inside the stack
+const MAX_DEPTH: usize = 200;
+
+// The most characters a string and items a list can have, so that something like
+// "x" * 1e12 fails instead of running out of memory
+const MAX_LENGTH: usize = 1 << 20;
+
+// Whole numbers are exact below 2 ** 53, and arithmetic on them that goes past that is an
+// overflow rather than a quietly rounded result
+const MAX_INTEGER: f64 = 9007199254740991.0;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    String(String),
+    Name(String),
+    Variable(String),
+    Symbol(&'static str),
+}
+
+// Longest first, so that ** isn't read as two *
+const SYMBOLS: &[&str] = &[
+    "**", "//", "==", "!=", "<=", ">=", "+", "-", "*", "/", "%", "<", ">", "(", ")", "[", "]",
+    "{", "}", ",", ":", ".",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() || (c == '.' && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit())) {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            // An exponent, like 1e6 or 2.5E-3
+            if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                let sign = chars.get(i + 1).is_some_and(|&c| c == '+' || c == '-') as usize;
+                if chars.get(i + 1 + sign).is_some_and(|c| c.is_ascii_digit()) {
+                    i += 1 + sign;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let number: String = chars[start..i].iter().collect();
+            let value: f64 = number.parse().map_err(|_| format!("{}: invalid number", number))?;
+            let whole = number.chars().all(|c| c.is_ascii_digit());
+            if !value.is_finite() || (whole && value > MAX_INTEGER) {
+                return Err(format!("{}: number too large", number));
+            }
+            tokens.push(Token::Number(value));
+        } else if c == '"' || c == '\'' {
+            let mut string = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    Some(&end) if end == c => break,
+                    Some('\\') => {
+                        i += 1;
+                        string.push(match chars.get(i) {
+                            Some('n') => '\n',
+                            Some('t') => '\t',
+                            Some('r') => '\r',
+                            Some('0') => '\0',
+                            Some(&other) => other,
+                            None => return Err("unterminated string".to_owned()),
+                        });
+                    }
+                    Some(&other) => string.push(other),
+                    None => return Err("unterminated string".to_owned()),
+                }
+                i += 1;
+            }
+            i += 1;
+            tokens.push(Token::String(string));
+        } else if c == '$' {
+            // $name, or a special parameter like $1 or $?
+            let start = i + 1;
+            i = start;
+            if chars.get(i).is_some_and(|c| c.is_ascii_alphabetic() || *c == '_') {
+                while chars.get(i).is_some_and(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    i += 1;
+                }
+            } else if chars.get(i).is_some_and(|c| c.is_ascii_digit() || "?#$!@*-".contains(*c)) {
+                i += 1;
+            } else {
+                return Err("$ without a variable name".to_owned());
+            }
+            tokens.push(Token::Variable(chars[start..i].iter().collect()));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Name(chars[start..i].iter().collect()));
+        } else {
+            let rest: String = chars[i..].iter().take(2).collect();
+            match SYMBOLS.iter().find(|s| rest.starts_with(*s)) {
+                Some(symbol) => {
+                    tokens.push(Token::Symbol(symbol));
+                    i += symbol.len();
+                }
+                None => return Err(format!("unexpected `{}'", c)),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone)]
+enum Expression {
+    Value(Value),
+    Variable(String),
+    List(Vec<Expression>),
+    Map(Vec<(Expression, Expression)>),
+    Unary(&'static str, Box<Expression>),
+    Binary(&'static str, Box<Expression>, Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    // value if condition else otherwise
+    Choice(Box<Expression>, Box<Expression>, Box<Expression>),
+    Index(Box<Expression>, Box<Expression>),
+    Slice(Box<Expression>, Option<Box<Expression>>, Option<Box<Expression>>),
+    Call(String, Vec<Expression>),
+    Method(Box<Expression>, String, Vec<Expression>),
+}
+
+// Precedence climbing over the tokens, loosest first: if-else, or, and, not, comparisons,
+// + and -, * / // and %, negation, ** and then indexing, calls and method calls
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn eat_symbol(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.peek(), Some(&Token::Symbol(s)) if s == symbol);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn eat_name(&mut self, name: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Name(n)) if n == name);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<()> {
+        if self.eat_symbol(symbol) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("expected `{}'", symbol)))
+        }
+    }
+
+    fn unexpected(&self, message: &str) -> String {
+        match self.peek() {
+            Some(token) => format!("{}, found {}", message, describe(token)),
+            None => format!("{} at the end", message),
+        }
+    }
+
+    // Parses something that can nest, one level deeper
+    fn nested(&mut self, parse: fn(&mut Parser) -> Result<Expression>) -> Result<Expression> {
+        if self.depth >= MAX_DEPTH {
+            return Err("expression nested too deeply".to_owned());
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn expression(&mut self) -> Result<Expression> {
+        self.nested(Parser::choice)
+    }
+
+    fn choice(&mut self) -> Result<Expression> {
+        let value = self.or()?;
+        if !self.eat_name("if") {
+            return Ok(value);
+        }
+        let condition = self.or()?;
+        if !self.eat_name("else") {
+            return Err(self.unexpected("expected else"));
+        }
+        let otherwise = self.expression()?;
+        Ok(Expression::Choice(Box::new(condition), Box::new(value), Box::new(otherwise)))
+    }
+
+    fn or(&mut self) -> Result<Expression> {
+        let mut left = self.and()?;
+        while self.eat_name("or") {
+            left = Expression::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expression> {
+        let mut left = self.not()?;
+        while self.eat_name("and") {
+            left = Expression::And(Box::new(left), Box::new(self.not()?));
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Expression> {
+        if self.eat_name("not") {
+            return Ok(Expression::Unary("not", Box::new(self.nested(Parser::not)?)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expression> {
+        let mut left = self.sum()?;
+        loop {
+            let operator = match self.peek() {
+                Some(&Token::Symbol(s)) if ["==", "!=", "<", "<=", ">", ">="].contains(&s) => s,
+                Some(Token::Name(n)) if n == "in" => "in",
+                Some(Token::Name(n)) if n == "not" => {
+                    // not in
+                    match self.tokens.get(self.position + 1) {
+                        Some(Token::Name(n)) if n == "in" => {
+                            self.position += 1;
+                            "not in"
+                        }
+                        _ => return Ok(left),
+                    }
+                }
+                _ => return Ok(left),
+            };
+            self.position += 1;
+            left = Expression::Binary(operator, Box::new(left), Box::new(self.sum()?));
+        }
+    }
+
+    fn sum(&mut self) -> Result<Expression> {
+        let mut left = self.product()?;
+        loop {
+            let operator = match self.peek() {
+                Some(&Token::Symbol(s)) if s == "+" || s == "-" => s,
+                _ => return Ok(left),
+            };
+            self.position += 1;
+            left = Expression::Binary(operator, Box::new(left), Box::new(self.product()?));
+        }
+    }
+
+    fn product(&mut self) -> Result<Expression> {
+        let mut left = self.unary()?;
+        loop {
+            let operator = match self.peek() {
+                Some(&Token::Symbol(s)) if ["*", "/", "//", "%"].contains(&s) => s,
+                _ => return Ok(left),
+            };
+            self.position += 1;
+            left = Expression::Binary(operator, Box::new(left), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expression> {
+        if self.eat_symbol("-") {
+            return Ok(Expression::Unary("-", Box::new(self.nested(Parser::unary)?)));
+        }
+        if self.eat_symbol("+") {
+            return self.nested(Parser::unary);
+        }
+        self.power()
+    }
+
+    fn power(&mut self) -> Result<Expression> {
+        let base = self.postfix()?;
+        if self.eat_symbol("**") {
+            // Right to left, and tighter than a minus on its left: -2 ** 2 is -4
+            return Ok(Expression::Binary("**", Box::new(base), Box::new(self.unary()?)));
+        }
+        Ok(base)
+    }
+
+    fn postfix(&mut self) -> Result<Expression> {
+        let mut value = self.primary()?;
+        loop {
+            if self.eat_symbol("[") {
+                let start = if self.peek() == Some(&Token::Symbol(":")) {
+                    None
+                } else {
+                    Some(Box::new(self.expression()?))
+                };
+                if self.eat_symbol(":") {
+                    let end = if self.peek() == Some(&Token::Symbol("]")) {
+                        None
+                    } else {
+                        Some(Box::new(self.expression()?))
+                    };
+                    value = Expression::Slice(Box::new(value), start, end);
+                } else {
+                    let index = start.ok_or_else(|| self.unexpected("expected an index"))?;
+                    value = Expression::Index(Box::new(value), index);
+                }
+                self.expect_symbol("]")?;
+            } else if self.eat_symbol(".") {
+                let name = match self.peek() {
+                    Some(Token::Name(name)) => name.clone(),
+                    _ => return Err(self.unexpected("expected a method name")),
+                };
+                self.position += 1;
+                self.expect_symbol("(")?;
+                let arguments = self.arguments(")")?;
+                value = Expression::Method(Box::new(value), name, arguments);
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    // Expressions separated by commas up to `end`, which may follow a last comma
+    fn arguments(&mut self, end: &str) -> Result<Vec<Expression>> {
+        let mut arguments = Vec::new();
+        while !self.eat_symbol(end) {
+            arguments.push(self.expression()?);
+            if !self.eat_symbol(",") {
+                self.expect_symbol(end)?;
+                break;
+            }
+        }
+        Ok(arguments)
+    }
+
+    fn primary(&mut self) -> Result<Expression> {
+        let token = match self.peek() {
+            Some(token) => token.clone(),
+            None => return Err("expected a value at the end".to_owned()),
+        };
+        self.position += 1;
+        match token {
+            Token::Number(number) => Ok(Expression::Value(Value::Number(number))),
+            Token::String(string) => Ok(Expression::Value(Value::String(string))),
+            Token::Variable(name) => Ok(Expression::Variable(name)),
+            Token::Name(ref name) if name == "true" || name == "True" => {
+                Ok(Expression::Value(Value::Bool(true)))
+            }
+            Token::Name(ref name) if name == "false" || name == "False" => {
+                Ok(Expression::Value(Value::Bool(false)))
+            }
+            Token::Name(ref name) if name == "null" || name == "None" => Ok(Expression::Value(Value::Null)),
+            Token::Name(name) => {
+                if !self.eat_symbol("(") {
+                    return Err(format!("{}: unknown name", name));
+                }
+                let arguments = self.arguments(")")?;
+                Ok(Expression::Call(name, arguments))
+            }
+            Token::Symbol("(") => {
+                let value = self.expression()?;
+                self.expect_symbol(")")?;
+                Ok(value)
+            }
+            Token::Symbol("[") => Ok(Expression::List(self.arguments("]")?)),
+            Token::Symbol("{") => {
+                let mut entries = Vec::new();
+                while !self.eat_symbol("}") {
+                    let key = self.expression()?;
+                    self.expect_symbol(":")?;
+                    entries.push((key, self.expression()?));
+                    if !self.eat_symbol(",") {
+                        self.expect_symbol("}")?;
+                        break;
+                    }
+                }
+                Ok(Expression::Map(entries))
+            }
+            _ => {
+                self.position -= 1;
+                Err(self.unexpected("expected a value"))
+            }
+        }
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match *token {
+        Token::Number(number) => Value::Number(number).to_string(),
+        Token::String(ref string) => Value::String(string.clone()).to_string(),
+        Token::Name(ref name) => name.clone(),
+        Token::Variable(ref name) => format!("${}", name),
+        Token::Symbol(symbol) => format!("`{}'", symbol),
+    }
+}
+
+// Whether a value counts as true, which like in Python everything but false, null, zero and
+// empty strings, lists and maps does
+fn truthy(value: &Value) -> bool {
+    match *value {
+        Value::Null => false,
+        Value::Bool(value) => value,
+        Value::Number(number) => number != 0.0,
+        Value::String(ref string) => !string.is_empty(),
+        Value::Array(ref values) => !values.is_empty(),
+        Value::Object(ref fields) => !fields.is_empty(),
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match *value {
+        Value::Null => "null",
+        Value::Bool(_) => "bool",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "list",
+        Value::Object(_) => "map",
+    }
+}
+
+// How a value is printed and interpolated: strings as they are, whole numbers without a
+// decimal point, and lists and maps as JSON
+pub fn display(value: &Value) -> String {
+    value.text()
+}
+
+fn number(value: &Value, what: &str) -> Result<f64> {
+    match *value {
+        Value::Number(number) => Ok(number),
+        Value::Bool(value) => Ok(value as i32 as f64),
+        _ => Err(format!("{} needs a number, not a {}", what, type_name(value))),
+    }
+}
+
+fn string<'a>(value: &'a Value, what: &str) -> Result<&'a str> {
+    match *value {
+        Value::String(ref string) => Ok(string),
+        _ => Err(format!("{} needs a string, not a {}", what, type_name(value))),
+    }
+}
+
+// A position in something `length` long, where negative ones count back from the end
+fn position(index: f64, length: usize) -> Option<usize> {
+    if index.fract() != 0.0 {
+        return None;
+    }
+    let index = if index < 0.0 { index + length as f64 } else { index };
+    if index < 0.0 || index >= length as f64 { None } else { Some(index as usize) }
+}
+
+// The start and end of a slice, clamped to the length like Python's
+fn bounds(start: Option<f64>, end: Option<f64>, length: usize) -> (usize, usize) {
+    let clamp = |index: f64| {
+        let index = if index < 0.0 { index + length as f64 } else { index };
+        index.max(0.0).min(length as f64) as usize
+    };
+    let start = start.map_or(0, clamp);
+    let end = end.map_or(length, clamp);
+    (start, end.max(start))
+}
+
+fn compare(left: &Value, right: &Value) -> Result<Ordering> {
+    match (left, right) {
+        (&Value::Number(a), &Value::Number(b)) => Ok(a.partial_cmp(&b).unwrap_or(Ordering::Equal)),
+        (Value::String(a), Value::String(b)) => Ok(a.cmp(b)),
+        (Value::Array(a), Value::Array(b)) => {
+            for (a, b) in a.iter().zip(b) {
+                let ordering = compare(a, b)?;
+                if ordering != Ordering::Equal {
+                    return Ok(ordering);
+                }
+            }
+            Ok(a.len().cmp(&b.len()))
+        }
+        _ => Err(format!("can't compare a {} with a {}", type_name(left), type_name(right))),
+    }
+}
+
+// A number that arithmetic came up with, as long as it is one
+fn finite(number: f64) -> Result<Value> {
+    if number.is_nan() {
+        Err("result is not a number".to_owned())
+    } else if number.is_infinite() {
+        Err("number too large".to_owned())
+    } else {
+        Ok(Value::Number(number))
+    }
+}
+
+// The result of arithmetic on two numbers, which for exact whole numbers has to stay exact
+fn arithmetic(a: f64, b: f64, result: f64) -> Result<Value> {
+    let exact = |n: f64| n.fract() == 0.0 && n.abs() <= MAX_INTEGER;
+    if exact(a) && exact(b) && result.abs() > MAX_INTEGER {
+        return Err("integer overflow".to_owned());
+    }
+    finite(result)
+}
+
+// Checks the length of a string or list about to be made
+fn length(count: usize, what: &str) -> Result<()> {
+    if count > MAX_LENGTH {
+        return Err(format!("{} too long, over {}", what, MAX_LENGTH));
+    }
+    Ok(())
+}
+
+// How many times to repeat something, with anything below one being none
+fn times(n: f64) -> usize {
+    if n >= 1.0 { n as usize } else { 0 }
+}
+
+fn binary(operator: &str, left: Value, right: Value) -> Result<Value> {
+    let mismatch = |left: &Value, right: &Value| {
+        Err(format!("can't use {} on a {} and a {}", operator, type_name(left), type_name(right)))
+    };
+    Ok(match operator {
+        "==" => Value::Bool(left == right),
+        "!=" => Value::Bool(left != right),
+        "<" => Value::Bool(compare(&left, &right)? == Ordering::Less),
+        "<=" => Value::Bool(compare(&left, &right)? != Ordering::Greater),
+        ">" => Value::Bool(compare(&left, &right)? == Ordering::Greater),
+        ">=" => Value::Bool(compare(&left, &right)? != Ordering::Less),
+        "in" | "not in" => {
+            let found = match right {
+                Value::String(ref text) => text.contains(string(&left, "in")?),
+                Value::Array(ref values) => values.contains(&left),
+                Value::Object(ref fields) => fields.iter().any(|f| Value::String(f.0.clone()) == left),
+                _ => return mismatch(&left, &right),
+            };
+            Value::Bool(found == (operator == "in"))
+        }
+        "+" => {
+            match (left, right) {
+                (Value::Number(a), Value::Number(b)) => arithmetic(a, b, a + b)?,
+                (Value::String(a), Value::String(b)) => {
+                    length(a.len() + b.len(), "string")?;
+                    Value::String(a + &b)
+                }
+                (Value::Array(mut a), Value::Array(b)) => {
+                    length(a.len() + b.len(), "list")?;
+                    a.extend(b);
+                    Value::Array(a)
+                }
+                (Value::Object(mut a), Value::Object(b)) => {
+                    for (key, value) in b {
+                        a.retain(|f| f.0 != key);
+                        a.push((key, value));
+                    }
+                    Value::Object(a)
+                }
+                (left, right) => return mismatch(&left, &right),
+            }
+        }
+        "*" => {
+            match (left, right) {
+                (Value::Number(a), Value::Number(b)) => arithmetic(a, b, a * b)?,
+                (Value::String(text), Value::Number(n)) | (Value::Number(n), Value::String(text)) => {
+                    let n = if text.is_empty() { 0 } else { times(n) };
+                    length(text.len().saturating_mul(n), "string")?;
+                    Value::String(text.repeat(n))
+                }
+                (Value::Array(values), Value::Number(n)) | (Value::Number(n), Value::Array(values)) => {
+                    let n = if values.is_empty() { 0 } else { times(n) };
+                    length(values.len().saturating_mul(n), "list")?;
+                    let mut repeated = Vec::new();
+                    for _ in 0..n {
+                        repeated.extend(values.iter().cloned());
+                    }
+                    Value::Array(repeated)
+                }
+                (left, right) => return mismatch(&left, &right),
+            }
+        }
+        _ => {
+            let (a, b) = match (&left, &right) {
+                (&Value::Number(a), &Value::Number(b)) => (a, b),
+                _ => return mismatch(&left, &right),
+            };
+            if b == 0.0 && operator != "-" && operator != "**" {
+                return Err("division by zero".to_owned());
+            }
+            match operator {
+                "-" => arithmetic(a, b, a - b)?,
+                "/" => finite(a / b)?,
+                "//" => arithmetic(a, b, (a / b).floor())?,
+                // The sign follows the divisor, as in Python
+                "%" => arithmetic(a, b, a - b * (a / b).floor())?,
+                _ => arithmetic(a, b, a.powf(b))?,
+            }
+        }
+    })
+}
+
+fn call(name: &str, arguments: Vec<Value>) -> Result<Value> {
+    let count = |range: &[usize]| {
+        if range.contains(&arguments.len()) {
+            Ok(())
+        } else {
+            Err(format!("{}() takes {} arguments, not {}", name,
+                range.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(" or "),
+                arguments.len()))
+        }
+    };
+    let first = arguments.first().cloned().unwrap_or(Value::Null);
+    // Functions of a list take the list itself, or their arguments as one
+    let items = || match first {
+        Value::Array(ref values) if arguments.len() == 1 => values.clone(),
+        _ => arguments.clone(),
+    };
+    Ok(match name {
+        "len" => {
+            count(&[1])?;
+            Value::Number(match first {
+                Value::String(ref text) => text.chars().count(),
+                Value::Array(ref values) => values.len(),
+                Value::Object(ref fields) => fields.len(),
+                _ => return Err(format!("a {} has no length", type_name(&first))),
+            } as f64)
+        }
+        "str" => {
+            count(&[1])?;
+            Value::String(display(&first))
+        }
+        "int" | "float" => {
+            count(&[1])?;
+            let value = match first {
+                Value::String(ref text) => {
+                    text.trim().parse().map_err(|_| format!("{}: not a number", Value::String(text.clone())))?
+                }
+                _ => number(&first, name)?,
+            };
+            finite(if name == "int" { value.trunc() } else { value })?
+        }
+        "abs" => {
+            count(&[1])?;
+            Value::Number(number(&first, name)?.abs())
+        }
+        "round" => {
+            count(&[1, 2])?;
+            let scale = 10f64.powf(arguments.get(1).map_or(Ok(0.0), |d| number(d, name))?);
+            finite((number(&first, name)? * scale).round() / scale)?
+        }
+        "range" => {
+            count(&[1, 2, 3])?;
+            let numbers: Vec<f64> = arguments.iter().map(|a| number(a, name)).collect::<Result<_>>()?;
+            let (start, end, step) = match numbers[..] {
+                [end] => (0.0, end, 1.0),
+                [start, end] => (start, end, 1.0),
+                _ => (numbers[0], numbers[1], numbers[2]),
+            };
+            if step == 0.0 {
+                return Err("range() step can't be zero".to_owned());
+            }
+            let count = ((end - start) / step).ceil().max(0.0);
+            if count.is_nan() || count > MAX_LENGTH as f64 {
+                return Err(format!("range() too long, over {}", MAX_LENGTH));
+            }
+            let values = (0..count as usize).map(|i| Value::Number(start + i as f64 * step));
+            Value::Array(values.collect())
+        }
+        "sum" => {
+            let mut total = 0.0;
+            for item in items() {
+                total += number(&item, name)?;
+            }
+            finite(total)?
+        }
+        "min" | "max" | "sorted" => {
+            let mut values = items();
+            let mut failed = None;
+            values.sort_by(|a, b| compare(a, b).unwrap_or_else(|e| {
+                failed = Some(e);
+                Ordering::Equal
+            }));
+            if let Some(message) = failed {
+                return Err(message);
+            }
+            match name {
+                "sorted" => Value::Array(values),
+                _ if values.is_empty() => return Err(format!("{}() of nothing", name)),
+                "min" => values.remove(0),
+                _ => values.pop().unwrap(),
+            }
+        }
+        "reversed" => {
+            count(&[1])?;
+            let mut values = items();
+            values.reverse();
+            Value::Array(values)
+        }
+        "type" => {
+            count(&[1])?;
+            Value::String(type_name(&first).to_owned())
+        }
+        _ => return Err(format!("{}: unknown function", name)),
+    })
+}
+
+fn method(value: Value, name: &str, arguments: Vec<Value>) -> Result<Value> {
+    let argument = |i: usize| -> Result<&str> {
+        match arguments.get(i) {
+            Some(value) => string(value, name),
+            None => Err(format!("{}() needs more arguments", name)),
+        }
+    };
+    let text = match value {
+        Value::String(ref text) => text.clone(),
+        Value::Object(ref fields) => {
+            return Ok(match name {
+                "keys" => Value::Array(fields.iter().map(|f| Value::String(f.0.clone())).collect()),
+                "values" => Value::Array(fields.iter().map(|f| f.1.clone()).collect()),
+                "items" => {
+                    Value::Array(fields.iter()
+                        .map(|f| Value::Array(vec![Value::String(f.0.clone()), f.1.clone()]))
+                        .collect())
+                }
+                "get" => {
+                    let key = argument(0)?;
+                    let found = fields.iter().find(|f| f.0 == key).map(|f| f.1.clone());
+                    found.or_else(|| arguments.get(1).cloned()).unwrap_or(Value::Null)
+                }
+                _ => return Err(format!("a map has no method {}()", name)),
+            });
+        }
+        Value::Array(ref values) => {
+            let item = arguments.first().ok_or_else(|| format!("{}() needs an argument", name))?;
+            return Ok(match name {
+                "index" => {
+                    match values.iter().position(|v| v == item) {
+                        Some(i) => Value::Number(i as f64),
+                        None => return Err(format!("{} isn't in the list", item)),
+                    }
+                }
+                "count" => Value::Number(values.iter().filter(|v| *v == item).count() as f64),
+                _ => return Err(format!("a list has no method {}()", name)),
+            });
+        }
+        _ => return Err(format!("a {} has no method {}()", type_name(&value), name)),
+    };
+    Ok(match name {
+        "upper" => Value::String(text.to_uppercase()),
+        "lower" => Value::String(text.to_lowercase()),
+        "strip" => Value::String(text.trim().to_owned()),
+        "lstrip" => Value::String(text.trim_start().to_owned()),
+        "rstrip" => Value::String(text.trim_end().to_owned()),
+        "split" => {
+            let pieces: Vec<&str> = match arguments.first() {
+                Some(_) => text.split(argument(0)?).collect(),
+                None => text.split_whitespace().collect(),
+            };
+            Value::Array(pieces.into_iter().map(|p| Value::String(p.to_owned())).collect())
+        }
+        "join" => {
+            let items = match arguments.first() {
+                Some(Value::Array(items)) => items,
+                _ => return Err("join() needs a list".to_owned()),
+            };
+            let items: Vec<String> = items.iter().map(display).collect();
+            length(items.iter().map(|i| i.len() + text.len()).sum(), "string")?;
+            Value::String(items.join(&text))
+        }
+        "replace" => {
+            let (old, new) = (argument(0)?, argument(1)?);
+            length(text.len() + text.matches(old).count().saturating_mul(new.len()), "string")?;
+            Value::String(text.replace(old, new))
+        }
+        "startswith" => Value::Bool(text.starts_with(argument(0)?)),
+        "endswith" => Value::Bool(text.ends_with(argument(0)?)),
+        "find" => {
+            let found = text.find(argument(0)?).map(|i| text[..i].chars().count() as f64);
+            Value::Number(found.unwrap_or(-1.0))
+        }
+        "count" => Value::Number(text.matches(argument(0)?).count() as f64),
+        _ => return Err(format!("a string has no method {}()", name)),
+    })
+}
+
+impl ShellState {
+    // A `= expression` line, which prints the value unless it is null
+    pub fn run_expression(&mut self, text: &str) -> i32 {
+        match self.evaluate_expression(text) {
+            Ok(Value::Null) => 0,
+            Ok(value) => {
+                match writeln!(exec::output(), "{}", display(&value)) {
+                    Ok(()) => 0,
+                    Err(e) => {
+                        self.report(&format!("=: {}", ShellError::Output(e)));
+                        1
+                    }
+                }
+            }
+            Err(message) => {
+                self.report(&format!("=: {}", message));
+                1
+            }
+        }
+    }
+
+    // Parses and works out an expression
+    pub fn evaluate_expression(&self, text: &str) -> Result<Value> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            position: 0,
+            depth: 0,
+        };
+        let expression = parser.expression()?;
+        if parser.peek().is_some() {
+            return Err(parser.unexpected("expected the end"));
+        }
+        self.evaluate(&expression)
+    }
+
+    fn evaluate(&self, expression: &Expression) -> Result<Value> {
+        Ok(match *expression {
+            Expression::Value(ref value) => value.clone(),
+            Expression::Variable(ref name) => self.expression_variable(name),
+            Expression::List(ref items) => {
+                Value::Array(items.iter().map(|i| self.evaluate(i)).collect::<Result<_>>()?)
+            }
+            Expression::Map(ref entries) => {
+                let mut fields: Vec<(String, Value)> = Vec::new();
+                for (key, value) in entries {
+                    let key = display(&self.evaluate(key)?);
+                    let value = self.evaluate(value)?;
+                    fields.retain(|f| f.0 != key);
+                    fields.push((key, value));
+                }
+                Value::Object(fields)
+            }
+            Expression::Unary(operator, ref operand) => {
+                let operand = self.evaluate(operand)?;
+                match operator {
+                    "not" => Value::Bool(!truthy(&operand)),
+                    _ => Value::Number(-number(&operand, "-")?),
+                }
+            }
+            Expression::Binary(operator, ref left, ref right) => {
+                binary(operator, self.evaluate(left)?, self.evaluate(right)?)?
+            }
+            Expression::And(ref left, ref right) => {
+                let left = self.evaluate(left)?;
+                if truthy(&left) { self.evaluate(right)? } else { left }
+            }
+            Expression::Or(ref left, ref right) => {
+                let left = self.evaluate(left)?;
+                if truthy(&left) { left } else { self.evaluate(right)? }
+            }
+            Expression::Choice(ref condition, ref value, ref otherwise) => {
+                if truthy(&self.evaluate(condition)?) {
+                    self.evaluate(value)?
+                } else {
+                    self.evaluate(otherwise)?
+                }
+            }
+            Expression::Index(ref value, ref index) => {
+                let value = self.evaluate(value)?;
+                let index = self.evaluate(index)?;
+                let missing = || format!("{} is out of range", index);
+                match value {
+                    Value::Array(ref values) => {
+                        let i = position(number(&index, "an index")?, values.len()).ok_or_else(missing)?;
+                        values[i].clone()
+                    }
+                    Value::String(ref text) => {
+                        let chars: Vec<char> = text.chars().collect();
+                        let i = position(number(&index, "an index")?, chars.len()).ok_or_else(missing)?;
+                        Value::String(chars[i].to_string())
+                    }
+                    Value::Object(_) => {
+                        let key = display(&index);
+                        value.get(&key).cloned().ok_or_else(|| format!("{}: no such key", index))?
+                    }
+                    _ => return Err(format!("a {} can't be indexed", type_name(&value))),
+                }
+            }
+            Expression::Slice(ref value, ref start, ref end) => {
+                let value = self.evaluate(value)?;
+                let limit = |bound: &Option<Box<Expression>>| -> Result<Option<f64>> {
+                    match *bound {
+                        Some(ref bound) => Ok(Some(number(&self.evaluate(bound)?, "a slice")?)),
+                        None => Ok(None),
+                    }
+                };
+                let (start, end) = (limit(start)?, limit(end)?);
+                match value {
+                    Value::Array(ref values) => {
+                        let (start, end) = bounds(start, end, values.len());
+                        Value::Array(values[start..end].to_vec())
+                    }
+                    Value::String(ref text) => {
+                        let chars: Vec<char> = text.chars().collect();
+                        let (start, end) = bounds(start, end, chars.len());
+                        Value::String(chars[start..end].iter().collect())
+                    }
+                    _ => return Err(format!("a {} can't be sliced", type_name(&value))),
+                }
+            }
+            Expression::Call(ref name, ref arguments) => {
+                let arguments = arguments.iter().map(|a| self.evaluate(a)).collect::<Result<_>>()?;
+                call(name, arguments)?
+            }
+            Expression::Method(ref value, ref name, ref arguments) => {
+                let value = self.evaluate(value)?;
+                let arguments = arguments.iter().map(|a| self.evaluate(a)).collect::<Result<_>>()?;
+                method(value, name, arguments)?
+            }
+        })
+    }
+
+    // A shell variable: arrays as lists or maps and everything else as a string. Variables
+    // that aren't set are null.
+    fn expression_variable(&self, name: &str) -> Value {
+        match self.arrays.get(name) {
+            Some(Array::Indexed(elements)) => {
+                Value::Array(elements.values().map(|v| Value::String(v.clone())).collect())
+            }
+            Some(Array::Associative(elements)) => {
+                Value::Object(elements.iter()
+                    .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+                    .collect())
+            }
+            None if name == "@" || name == "*" => {
+                Value::Array(self.arguments.iter().map(|a| Value::String(a.clone())).collect())
+            }
+            None => self.parameter(name).map_or(Value::Null, Value::String),
+        }
+    }
+}
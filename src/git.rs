@@ -0,0 +1,1008 @@
+extern crate std;
+extern crate glob;
+
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::str;
+use std::time::{Duration, Instant};
+use inflate::zlib_decompress;
+use sha1::Sha1;
+
+type Oid = [u8; 20];
+
+const COMMIT: u8 = 1;
+const TREE: u8 = 2;
+// Largest object the prompt is willing to decompress
+const OBJECT_LIMIT: usize = 64 << 20;
+// Decoded pack objects are kept around for delta chains until they add up to this much
+const CACHE_LIMIT: usize = 32 << 20;
+
+// What the prompt shows about the repository the shell is in
+#[derive(Default)]
+pub struct GitStatus {
+    pub branch: String,
+    pub ahead: usize,
+    pub behind: usize,
+    pub staged: bool,
+    pub dirty: bool,
+    pub untracked: bool,
+    // An operation in progress, like "REBASE 2/5" or "MERGING"
+    pub state: Option<String>,
+    // False when the time budget ran out before every check finished
+    pub complete: bool,
+}
+
+impl GitStatus {
+    // Compact form for the prompt, like "main|REBASE 2/5 ↑1↓2 +!?"
+    pub fn summary(&self) -> String {
+        let mut summary = self.branch.clone();
+        if let Some(ref state) = self.state {
+            summary.push('|');
+            summary.push_str(state);
+        }
+        if self.ahead > 0 || self.behind > 0 {
+            summary.push(' ');
+            if self.ahead > 0 {
+                summary.push_str(&format!("↑{}", self.ahead));
+            }
+            if self.behind > 0 {
+                summary.push_str(&format!("↓{}", self.behind));
+            }
+        }
+        let markers: String = [(self.staged, '+'), (self.dirty, '!'), (self.untracked, '?')]
+            .iter()
+            .filter(|&&(set, _)| set)
+            .map(|&(_, marker)| marker)
+            .collect();
+        if !markers.is_empty() {
+            summary.push(' ');
+            summary.push_str(&markers);
+        }
+        if !self.complete {
+            summary.push('…');
+        }
+        summary
+    }
+}
+
+// Status of the repository containing `dir`, giving up on the slower checks once `budget`
+// has passed so that huge repositories don't stall the prompt
+pub fn git_status(dir: &Path, budget: Duration) -> Option<GitStatus> {
+    let deadline = Instant::now() + budget;
+    let mut repository = Repository::discover(dir, deadline)?;
+    let mut status = GitStatus {
+        complete: true,
+        ..Default::default()
+    };
+    let (branch, head) = repository.head()?;
+    status.branch = match branch {
+        Some(ref branch) => branch.clone(),
+        None => format!("({})", head.map(|h| to_hex(&h)[..7].to_owned()).unwrap_or_default()),
+    };
+    if let Some((state, rebasing)) = repository.operation() {
+        status.state = Some(state);
+        if let Some(rebasing) = rebasing {
+            status.branch = rebasing;
+        }
+    }
+
+    // Once the time is up only the branch is shown
+    if Instant::now() > deadline {
+        status.complete = false;
+        return Some(status);
+    }
+
+    let upstream = branch.and_then(|b| repository.upstream(&b));
+    if let (Some(head), Some(upstream)) = (head, upstream) {
+        match repository.ahead_behind(head, upstream, deadline) {
+            Some((ahead, behind)) => {
+                status.ahead = ahead;
+                status.behind = behind;
+            }
+            None => status.complete = false,
+        }
+    }
+
+    let index = match repository.read_index(deadline) {
+        Some(index) => index,
+        None => {
+            status.complete = false;
+            return Some(status);
+        }
+    };
+    let checks = [
+        repository.find_staged(&index, head, deadline).map(|s| status.staged = s),
+        repository.find_modified(&index, deadline).map(|d| status.dirty = d),
+        repository.find_untracked(&index, deadline).map(|u| status.untracked = u),
+    ];
+    status.complete = status.complete && checks.iter().all(|c| c.is_some());
+    Some(status)
+}
+
+fn to_hex(oid: &Oid) -> String {
+    oid.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Oid> {
+    if hex.len() != 40 {
+        return None;
+    }
+    let mut oid = [0u8; 20];
+    for (i, byte) in oid.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(oid)
+}
+
+fn oid_at(data: &[u8], start: usize) -> Option<Oid> {
+    let mut oid = [0u8; 20];
+    oid.copy_from_slice(data.get(start..start + 20)?);
+    Some(oid)
+}
+
+fn be32(data: &[u8], start: usize) -> Option<u32> {
+    let b = data.get(start..start + 4)?;
+    Some(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn blob_oid(data: &[u8]) -> Oid {
+    let mut sha = Sha1::new();
+    sha.update(format!("blob {}\0", data.len()).as_bytes());
+    sha.update(data);
+    sha.finish()
+}
+
+// Reads up to `len` bytes at `offset`, fewer at the end of the file
+fn read_upto(file: &File, offset: u64, len: usize) -> Option<Vec<u8>> {
+    let mut buffer = vec![0u8; len];
+    let mut filled = 0;
+    while filled < len {
+        match file.read_at(&mut buffer[filled..], offset + filled as u64) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(_) => return None,
+        }
+    }
+    buffer.truncate(filled);
+    Some(buffer)
+}
+
+// Keys are section.subsection.key with the section and key in lower case
+fn parse_config(path: &Path) -> HashMap<String, String> {
+    let mut config = HashMap::new();
+    let contents = fs::read_to_string(path).unwrap_or_default();
+    let mut section = String::new();
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if line.starts_with('[') {
+            if let Some(end) = line.find(']') {
+                let header = &line[1..end];
+                section = match header.split_once(' ') {
+                    Some((name, subsection)) => {
+                        format!("{}.{}", name.to_lowercase(), subsection.trim().trim_matches('"'))
+                    }
+                    None => header.to_lowercase(),
+                };
+            }
+            continue;
+        }
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim().trim_matches('"')),
+            None => (line, "true"),
+        };
+        config.insert(format!("{}.{}", section, key.to_lowercase()), value.to_owned());
+    }
+    config
+}
+
+// Git's variable-length offset encoding, used by v4 indexes and offset deltas
+fn offset_varint(data: &[u8]) -> Option<(usize, usize)> {
+    let mut i = 0;
+    let mut byte = *data.first()?;
+    let mut value = (byte & 0x7f) as usize;
+    while byte & 0x80 != 0 {
+        i += 1;
+        byte = *data.get(i)?;
+        value = ((value + 1) << 7) | (byte & 0x7f) as usize;
+    }
+    Some((value, i + 1))
+}
+
+fn apply_delta(base: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    let mut i = 0;
+    let size = |i: &mut usize| -> Option<usize> {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = *delta.get(*i)?;
+            *i += 1;
+            value |= ((byte & 0x7f) as usize).checked_shl(shift)?;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+    };
+    if size(&mut i)? != base.len() {
+        return None;
+    }
+    let result_size = size(&mut i)?;
+    let mut result = Vec::with_capacity(result_size);
+    while i < delta.len() {
+        let op = delta[i];
+        i += 1;
+        if op & 0x80 != 0 {
+            // Copy from the base, with the offset and length bytes that are present
+            let mut offset = 0usize;
+            let mut length = 0usize;
+            for bit in 0..7 {
+                if op & (1 << bit) != 0 {
+                    let byte = *delta.get(i)? as usize;
+                    i += 1;
+                    if bit < 4 {
+                        offset |= byte << (8 * bit);
+                    } else {
+                        length |= byte << (8 * (bit - 4));
+                    }
+                }
+            }
+            if length == 0 {
+                length = 0x10000;
+            }
+            result.extend_from_slice(base.get(offset..offset + length)?);
+        } else if op != 0 {
+            result.extend_from_slice(delta.get(i..i + op as usize)?);
+            i += op as usize;
+        } else {
+            return None;
+        }
+    }
+    if result.len() == result_size {
+        Some(result)
+    } else {
+        None
+    }
+}
+
+// A pack and its version 2 index, which are searched in place rather than loaded
+struct Pack {
+    index: File,
+    pack: File,
+    fanout: Vec<u32>,
+}
+
+impl Pack {
+    fn open(index_path: &Path) -> Option<Pack> {
+        let index = File::open(index_path).ok()?;
+        let header = read_upto(&index, 0, 8 + 256 * 4)?;
+        if header.get(..8)? != b"\xfftOc\0\0\0\x02" {
+            return None;
+        }
+        let fanout = (0..256).map(|i| be32(&header, 8 + i * 4)).collect::<Option<Vec<u32>>>()?;
+        let pack = File::open(index_path.with_extension("pack")).ok()?;
+        Some(Pack {
+            index,
+            pack,
+            fanout,
+        })
+    }
+
+    fn find(&self, oid: &Oid) -> Option<u64> {
+        let count = self.fanout[255] as u64;
+        let first = oid[0] as usize;
+        let mut low = if first == 0 { 0 } else { self.fanout[first - 1] as u64 };
+        let mut high = self.fanout[first] as u64;
+        let names = 8 + 256 * 4;
+        while low < high {
+            let middle = (low + high) / 2;
+            let name = read_upto(&self.index, names + middle * 20, 20)?;
+            match name.as_slice().cmp(&oid[..]) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => {
+                    let offsets = names + count * 24;
+                    let offset = be32(&read_upto(&self.index, offsets + middle * 4, 4)?, 0)?;
+                    if offset & 0x8000_0000 == 0 {
+                        return Some(offset as u64);
+                    }
+                    let large = offsets + count * 4 + (offset & 0x7fff_ffff) as u64 * 8;
+                    let bytes = read_upto(&self.index, large, 8)?;
+                    let high = be32(&bytes, 0)? as u64;
+                    return Some((high << 32) | be32(&bytes, 4)? as u64);
+                }
+            }
+        }
+        None
+    }
+}
+
+#[derive(Clone)]
+struct Commit {
+    tree: Oid,
+    parents: Vec<Oid>,
+    time: u64,
+}
+
+struct IndexEntry {
+    path: String,
+    oid: Oid,
+    mode: u32,
+    size: u32,
+    mtime: (u32, u32),
+    stage: u16,
+    // Marked assume-unchanged or skip-worktree, so the working tree isn't checked
+    skip: bool,
+}
+
+struct Index {
+    entries: Vec<IndexEntry>,
+    // Tree of the whole index from the cache tree extension, when it is up to date
+    cache_tree: Option<Oid>,
+}
+
+struct IgnoreRule {
+    // Directory of the .gitignore the rule came from, relative to the work tree
+    base: String,
+    pattern: glob::Pattern,
+    negated: bool,
+    directory_only: bool,
+    // Patterns containing a slash match the whole path rather than just the name
+    anchored: bool,
+}
+
+fn read_ignore_file(path: &Path, base: &str, rules: &mut Vec<IgnoreRule>) {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(_) => return,
+    };
+    for line in contents.lines() {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let negated = line.starts_with('!');
+        let line = line.trim_start_matches('!');
+        let line = line.strip_prefix('\\').unwrap_or(line);
+        let directory_only = line.ends_with('/');
+        let line = line.trim_end_matches('/');
+        let anchored = line.contains('/');
+        if let Ok(pattern) = glob::Pattern::new(line.trim_start_matches('/')) {
+            rules.push(IgnoreRule {
+                base: base.to_owned(),
+                pattern,
+                negated,
+                directory_only,
+                anchored,
+            });
+        }
+    }
+}
+
+// The last rule that matches decides, like in git
+fn is_ignored(rules: &[IgnoreRule], path: &str, is_dir: bool) -> bool {
+    let options = glob::MatchOptions {
+        case_sensitive: true,
+        require_literal_separator: true,
+        require_literal_leading_dot: false,
+    };
+    for rule in rules.iter().rev() {
+        if rule.directory_only && !is_dir {
+            continue;
+        }
+        let relative = match path.strip_prefix(rule.base.as_str()) {
+            Some(relative) => relative,
+            None => continue,
+        };
+        let subject = if rule.anchored {
+            relative
+        } else {
+            relative.rsplit('/').next().unwrap_or(relative)
+        };
+        if rule.pattern.matches_with(subject, options) {
+            return !rule.negated;
+        }
+    }
+    false
+}
+
+struct Repository {
+    // Per-worktree files like HEAD and the index
+    git_dir: PathBuf,
+    // Objects, refs and config, shared between worktrees
+    common_dir: PathBuf,
+    work_tree: PathBuf,
+    config: HashMap<String, String>,
+    packs: Option<Vec<Pack>>,
+    cache: HashMap<(usize, u64), (u8, Vec<u8>)>,
+    cache_size: usize,
+    commits: HashMap<Oid, Commit>,
+}
+
+impl Repository {
+    fn discover(dir: &Path, deadline: Instant) -> Option<Repository> {
+        for work_tree in dir.ancestors() {
+            if Instant::now() > deadline {
+                return None;
+            }
+            let dot_git = work_tree.join(".git");
+            let git_dir = if dot_git.is_dir() {
+                dot_git
+            } else if dot_git.is_file() {
+                // Worktrees and submodules point at their git directory
+                let contents = fs::read_to_string(&dot_git).ok()?;
+                work_tree.join(contents.trim().strip_prefix("gitdir:")?.trim())
+            } else {
+                continue;
+            };
+            let common_dir = match fs::read_to_string(git_dir.join("commondir")) {
+                Ok(common_dir) => git_dir.join(common_dir.trim()),
+                Err(_) => git_dir.clone(),
+            };
+            return Some(Repository {
+                config: parse_config(&common_dir.join("config")),
+                git_dir,
+                common_dir,
+                work_tree: work_tree.to_owned(),
+                packs: None,
+                cache: HashMap::new(),
+                cache_size: 0,
+                commits: HashMap::new(),
+            });
+        }
+        None
+    }
+
+    fn read(&self, name: &str) -> Option<String> {
+        fs::read_to_string(self.git_dir.join(name)).ok().map(|s| s.trim().to_owned())
+    }
+
+    fn resolve_ref(&self, name: &str) -> Option<Oid> {
+        let mut name = name.to_owned();
+        // Symbolic refs can point at each other, but not forever
+        for _ in 0..5 {
+            let dir = if name.starts_with("refs/") {
+                &self.common_dir
+            } else {
+                &self.git_dir
+            };
+            let contents = match fs::read_to_string(dir.join(&name)) {
+                Ok(contents) => contents,
+                Err(_) => return self.packed_ref(&name),
+            };
+            match contents.trim().strip_prefix("ref:") {
+                Some(target) => name = target.trim().to_owned(),
+                None => return from_hex(contents.trim()),
+            }
+        }
+        None
+    }
+
+    fn packed_ref(&self, name: &str) -> Option<Oid> {
+        let contents = fs::read_to_string(self.common_dir.join("packed-refs")).ok()?;
+        contents.lines().find_map(|line| {
+            let (oid, reference) = line.split_once(' ')?;
+            if reference == name {
+                from_hex(oid)
+            } else {
+                None
+            }
+        })
+    }
+
+    // The checked out branch, if any, and the commit HEAD points at, if there is one yet
+    fn head(&self) -> Option<(Option<String>, Option<Oid>)> {
+        let head = self.read("HEAD")?;
+        match head.strip_prefix("ref:") {
+            Some(target) => {
+                let target = target.trim();
+                let branch = target.strip_prefix("refs/heads/").unwrap_or(target);
+                Some((Some(branch.to_owned()), self.resolve_ref(target)))
+            }
+            None => Some((None, Some(from_hex(&head)?))),
+        }
+    }
+
+    // The operation in progress, and for a rebase the branch being rebased
+    fn operation(&self) -> Option<(String, Option<String>)> {
+        for &(dir, step, total) in &[("rebase-merge", "msgnum", "end"), ("rebase-apply", "next", "last")] {
+            if !self.git_dir.join(dir).is_dir() {
+                continue;
+            }
+            let label = if dir == "rebase-merge" || self.git_dir.join(dir).join("rebasing").exists() {
+                "REBASE"
+            } else if self.git_dir.join(dir).join("applying").exists() {
+                "AM"
+            } else {
+                "AM/REBASE"
+            };
+            let label = match (self.read(&format!("{}/{}", dir, step)),
+                               self.read(&format!("{}/{}", dir, total))) {
+                (Some(step), Some(total)) => format!("{} {}/{}", label, step, total),
+                _ => label.to_owned(),
+            };
+            let branch = self.read(&format!("{}/head-name", dir))
+                .map(|name| name.trim_start_matches("refs/heads/").to_owned());
+            return Some((label, branch));
+        }
+        let states = [
+            ("MERGE_HEAD", "MERGING"),
+            ("CHERRY_PICK_HEAD", "CHERRY-PICKING"),
+            ("REVERT_HEAD", "REVERTING"),
+            ("BISECT_LOG", "BISECTING"),
+        ];
+        states
+            .iter()
+            .find(|&&(file, _)| self.git_dir.join(file).exists())
+            .map(|&(_, label)| (label.to_owned(), None))
+    }
+
+    fn upstream(&self, branch: &str) -> Option<Oid> {
+        let remote = self.config.get(&format!("branch.{}.remote", branch))?;
+        let merge = self.config.get(&format!("branch.{}.merge", branch))?;
+        if remote == "." {
+            return self.resolve_ref(merge);
+        }
+        self.resolve_ref(&format!(
+            "refs/remotes/{}/{}",
+            remote,
+            merge.trim_start_matches("refs/heads/")
+        ))
+    }
+
+    fn read_object(&mut self, oid: &Oid, depth: usize) -> Option<(u8, Vec<u8>)> {
+        let hex = to_hex(oid);
+        let loose = self.common_dir.join("objects").join(&hex[..2]).join(&hex[2..]);
+        if let Ok(compressed) = fs::read(&loose) {
+            let data = zlib_decompress(&compressed, OBJECT_LIMIT)?;
+            let nul = data.iter().position(|&b| b == 0)?;
+            let kind = match str::from_utf8(&data[..nul]).ok()?.split(' ').next()? {
+                "commit" => COMMIT,
+                "tree" => TREE,
+                "blob" => 3,
+                "tag" => 4,
+                _ => return None,
+            };
+            return Some((kind, data[nul + 1..].to_vec()));
+        }
+
+        if self.packs.is_none() {
+            let mut packs = Vec::new();
+            if let Ok(entries) = fs::read_dir(self.common_dir.join("objects/pack")) {
+                for entry in entries.filter_map(|e| e.ok()) {
+                    let path = entry.path();
+                    if path.extension().is_some_and(|e| e == "idx") {
+                        packs.extend(Pack::open(&path));
+                    }
+                }
+            }
+            self.packs = Some(packs);
+        }
+        let found = self.packs
+            .as_ref()?
+            .iter()
+            .enumerate()
+            .find_map(|(i, pack)| pack.find(oid).map(|offset| (i, offset)));
+        let (pack, offset) = found?;
+        self.read_packed(pack, offset, depth)
+    }
+
+    fn read_packed(&mut self, pack: usize, offset: u64, depth: usize) -> Option<(u8, Vec<u8>)> {
+        if let Some(object) = self.cache.get(&(pack, offset)) {
+            return Some(object.clone());
+        }
+        if depth > 64 {
+            return None;
+        }
+        let header = read_upto(&self.packs.as_ref()?[pack].pack, offset, 32)?;
+        let mut byte = *header.first()?;
+        let kind = (byte >> 4) & 7;
+        let mut size = (byte & 0x0f) as usize;
+        let mut shift = 4;
+        let mut i = 0;
+        while byte & 0x80 != 0 {
+            i += 1;
+            byte = *header.get(i)?;
+            size |= ((byte & 0x7f) as usize).checked_shl(shift)?;
+            shift += 7;
+        }
+        i += 1;
+
+        let object = match kind {
+            // Delta against an object earlier in the same pack
+            6 => {
+                let (back, n) = offset_varint(&header[i..])?;
+                let delta = self.inflate_at(pack, offset + (i + n) as u64, size)?;
+                let (kind, base) = self.read_packed(pack, offset.checked_sub(back as u64)?, depth + 1)?;
+                (kind, apply_delta(&base, &delta)?)
+            }
+            // Delta against an object named by its id
+            7 => {
+                let base = oid_at(&header, i)?;
+                let delta = self.inflate_at(pack, offset + i as u64 + 20, size)?;
+                let (kind, base) = self.read_object(&base, depth + 1)?;
+                (kind, apply_delta(&base, &delta)?)
+            }
+            1..=4 => (kind, self.inflate_at(pack, offset + i as u64, size)?),
+            _ => return None,
+        };
+
+        if self.cache_size + object.1.len() > CACHE_LIMIT {
+            self.cache.clear();
+            self.cache_size = 0;
+        }
+        self.cache_size += object.1.len();
+        self.cache.insert((pack, offset), object.clone());
+        Some(object)
+    }
+
+    fn inflate_at(&self, pack: usize, offset: u64, size: usize) -> Option<Vec<u8>> {
+        if size > OBJECT_LIMIT {
+            return None;
+        }
+        // Deflate never grows data by more than a few bytes per block
+        let compressed = read_upto(&self.packs.as_ref()?[pack].pack, offset, size + size / 1000 + 64)?;
+        let data = zlib_decompress(&compressed, size)?;
+        if data.len() == size {
+            Some(data)
+        } else {
+            None
+        }
+    }
+
+    fn commit(&mut self, oid: &Oid) -> Option<Commit> {
+        if let Some(commit) = self.commits.get(oid) {
+            return Some(commit.clone());
+        }
+        let (kind, data) = self.read_object(oid, 0)?;
+        if kind != COMMIT {
+            return None;
+        }
+        let mut tree = None;
+        let mut parents = Vec::new();
+        let mut time = 0;
+        for line in data.split(|&b| b == b'\n') {
+            if line.is_empty() {
+                break;
+            }
+            let line = String::from_utf8_lossy(line);
+            if let Some(oid) = line.strip_prefix("tree ") {
+                tree = from_hex(oid);
+            } else if let Some(oid) = line.strip_prefix("parent ") {
+                parents.push(from_hex(oid)?);
+            } else if line.starts_with("committer ") {
+                // committer Name <email> 1700000000 +0100
+                time = line.rsplit(' ').nth(1).and_then(|t| t.parse().ok()).unwrap_or(0);
+            }
+        }
+        let commit = Commit {
+            tree: tree?,
+            parents,
+            time,
+        };
+        self.commits.insert(*oid, commit.clone());
+        Some(commit)
+    }
+
+    // Counts the commits only reachable from `local` and only from `upstream`. Both histories
+    // are walked newest first, marking each commit with which side reaches it, and a commit
+    // found to be reachable from both passes that on to its parents even after they were
+    // walked. Committer dates can be equal, as after a rebase, so the walk goes on until nothing
+    // left to visit is as new as a commit only one side reaches, and the counting waits until
+    // the end.
+    fn ahead_behind(&mut self, local: Oid, upstream: Oid, deadline: Instant) -> Option<(usize, usize)> {
+        if local == upstream {
+            return Some((0, 0));
+        }
+        let mut flags: HashMap<Oid, u8> = HashMap::new();
+        let mut times: HashMap<Oid, u64> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        for &(oid, flag) in &[(local, 1u8), (upstream, 2u8)] {
+            let time = self.commit(&oid)?.time;
+            queue.push((time, oid));
+            times.insert(oid, time);
+            flags.insert(oid, flag);
+        }
+        while let Some((_, oid)) = queue.pop() {
+            if Instant::now() > deadline {
+                return None;
+            }
+            let flag = flags[&oid];
+            for parent in self.commit(&oid)?.parents {
+                let old = flags.get(&parent).cloned().unwrap_or(0);
+                if old | flag == old {
+                    continue;
+                }
+                flags.insert(parent, old | flag);
+                let time = self.commit(&parent)?.time;
+                times.insert(parent, time);
+                queue.push((time, parent));
+            }
+            if queue.iter().all(|&(_, oid)| flags[&oid] == 3) {
+                let oldest_one_sided = flags.iter()
+                    .filter(|&(_, &flag)| flag != 3)
+                    .map(|(oid, _)| times[oid])
+                    .min();
+                match (queue.peek(), oldest_one_sided) {
+                    (Some(&(newest, _)), Some(oldest)) if newest >= oldest => {}
+                    _ => break,
+                }
+            }
+        }
+        let ahead = flags.values().filter(|&&flag| flag == 1).count();
+        let behind = flags.values().filter(|&&flag| flag == 2).count();
+        Some((ahead, behind))
+    }
+
+    // None when the index can't be read, or not before the deadline
+    fn read_index(&self, deadline: Instant) -> Option<Index> {
+        let data = match fs::read(self.git_dir.join("index")) {
+            Ok(data) => data,
+            // A new repository has no index until something is added
+            Err(_) => {
+                return Some(Index {
+                    entries: Vec::new(),
+                    cache_tree: None,
+                })
+            }
+        };
+        if data.get(..4)? != b"DIRC" {
+            return None;
+        }
+        let version = be32(&data, 4)?;
+        let count = be32(&data, 8)?;
+        let mut entries = Vec::with_capacity(count as usize);
+        let mut i = 12;
+        let mut previous: Vec<u8> = Vec::new();
+        for n in 0..count {
+            if n % 1024 == 0 && Instant::now() > deadline {
+                return None;
+            }
+            let start = i;
+            let flags = u16::from_be_bytes([*data.get(i + 60)?, *data.get(i + 61)?]);
+            let mut skip = flags & 0x8000 != 0;
+            i += 62;
+            if version >= 3 && flags & 0x4000 != 0 {
+                skip |= *data.get(i)? & 0x40 != 0;
+                i += 2;
+            }
+            let path = if version >= 4 {
+                // Paths are stored as the number of bytes to drop from the previous path
+                // followed by what to append
+                let (strip, n) = offset_varint(&data[i..])?;
+                i += n;
+                let nul = i + data[i..].iter().position(|&b| b == 0)?;
+                let mut path = previous[..previous.len().checked_sub(strip)?].to_vec();
+                path.extend_from_slice(&data[i..nul]);
+                i = nul + 1;
+                previous = path.clone();
+                path
+            } else {
+                let nul = i + data[i..].iter().position(|&b| b == 0)?;
+                // Entries are padded with one to eight NULs to a multiple of eight bytes
+                let path = data[i..nul].to_vec();
+                i = start + ((nul - start + 8) & !7);
+                path
+            };
+            entries.push(IndexEntry {
+                path: String::from_utf8_lossy(&path).into_owned(),
+                oid: oid_at(&data, start + 40)?,
+                mode: be32(&data, start + 24)?,
+                size: be32(&data, start + 36)?,
+                mtime: (be32(&data, start + 8)?, be32(&data, start + 12)?),
+                stage: (flags >> 12) & 3,
+                skip,
+            });
+        }
+
+        // Extensions follow the entries, up to the trailing checksum
+        let mut cache_tree = None;
+        while i + 8 + 20 <= data.len() {
+            let size = be32(&data, i + 4)? as usize;
+            let body = data.get(i + 8..i + 8 + size)?;
+            if &data[i..i + 4] == b"TREE" && body.first() == Some(&0) {
+                // The root comes first: an empty path, then "entries subtrees\n" and its id,
+                // or an entry count of -1 when it has been invalidated
+                let newline = body.iter().position(|&b| b == b'\n')?;
+                let counts = str::from_utf8(&body[1..newline]).ok()?;
+                if !counts.starts_with('-') {
+                    cache_tree = oid_at(body, newline + 1);
+                }
+            }
+            i += 8 + size;
+        }
+        Some(Index {
+            entries,
+            cache_tree,
+        })
+    }
+
+    fn flatten_tree(
+        &mut self,
+        oid: &Oid,
+        prefix: &str,
+        entries: &mut HashMap<String, (u32, Oid)>,
+        deadline: Instant,
+    ) -> Option<()> {
+        if Instant::now() > deadline {
+            return None;
+        }
+        let (kind, data) = self.read_object(oid, 0)?;
+        if kind != TREE {
+            return None;
+        }
+        // Entries are "mode name\0" followed by the binary id
+        let mut i = 0;
+        while i < data.len() {
+            let space = i + data[i..].iter().position(|&b| b == b' ')?;
+            let mode = u32::from_str_radix(str::from_utf8(&data[i..space]).ok()?, 8).ok()?;
+            let nul = space + data[space..].iter().position(|&b| b == 0)?;
+            let path = format!("{}{}", prefix, String::from_utf8_lossy(&data[space + 1..nul]));
+            let child = oid_at(&data, nul + 1)?;
+            i = nul + 21;
+            if mode == 0o40000 {
+                self.flatten_tree(&child, &format!("{}/", path), entries, deadline)?;
+            } else {
+                entries.insert(path, (mode, child));
+            }
+        }
+        Some(())
+    }
+
+    // Whether the index differs from the commit checked out
+    fn find_staged(&mut self, index: &Index, head: Option<Oid>, deadline: Instant) -> Option<bool> {
+        let tree = match head {
+            Some(head) => self.commit(&head)?.tree,
+            None => return Some(!index.entries.is_empty()),
+        };
+        if index.cache_tree == Some(tree) {
+            return Some(false);
+        }
+        let mut committed = HashMap::new();
+        self.flatten_tree(&tree, "", &mut committed, deadline)?;
+        let mut count = 0;
+        for entry in index.entries.iter().filter(|e| e.stage == 0) {
+            count += 1;
+            match committed.get(&entry.path) {
+                Some(&(mode, oid)) if mode == entry.mode && oid == entry.oid => {}
+                _ => return Some(true),
+            }
+        }
+        Some(count != committed.len())
+    }
+
+    // Whether any tracked file in the working tree differs from the index, hashing files
+    // only when their size and modification time don't settle it
+    fn find_modified(&self, index: &Index, deadline: Instant) -> Option<bool> {
+        let filemode = self.config.get("core.filemode").is_none_or(|v| v != "false");
+        for entry in &index.entries {
+            // Unmerged paths
+            if entry.stage != 0 {
+                return Some(true);
+            }
+            // Submodules are left to their own prompt
+            if entry.skip || entry.mode == 0o160000 {
+                continue;
+            }
+            if Instant::now() > deadline {
+                return None;
+            }
+            let path = self.work_tree.join(&entry.path);
+            let metadata = match fs::symlink_metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(_) => return Some(true),
+            };
+            let symlink = entry.mode == 0o120000;
+            if metadata.file_type().is_symlink() != symlink || metadata.is_dir() ||
+                metadata.size() as u32 != entry.size
+            {
+                return Some(true);
+            }
+            if filemode && !symlink && (metadata.mode() & 0o100 != 0) != (entry.mode == 0o100755) {
+                return Some(true);
+            }
+            if metadata.mtime() as u32 == entry.mtime.0 &&
+                metadata.mtime_nsec() as u32 == entry.mtime.1
+            {
+                continue;
+            }
+            let contents = if symlink {
+                fs::read_link(&path).map(|target| target.as_os_str().as_bytes().to_vec())
+            } else {
+                fs::read(&path)
+            };
+            match contents {
+                Ok(contents) if blob_oid(&contents) == entry.oid => {}
+                _ => return Some(true),
+            }
+        }
+        Some(false)
+    }
+
+    // Whether the working tree has a file that is neither tracked nor ignored
+    fn find_untracked(&self, index: &Index, deadline: Instant) -> Option<bool> {
+        if self.config.get("status.showuntrackedfiles").is_some_and(|v| v == "no") {
+            return Some(false);
+        }
+        let tracked: HashSet<&str> = index.entries.iter().map(|e| e.path.as_str()).collect();
+        let mut tracked_dirs = HashSet::new();
+        for path in &tracked {
+            for (i, _) in path.match_indices('/') {
+                tracked_dirs.insert(&path[..i]);
+            }
+        }
+
+        let mut rules = Vec::new();
+        let global = match self.config.get("core.excludesfile") {
+            Some(path) => match path.strip_prefix("~/") {
+                Some(rest) => env::var("HOME").map(|home| Path::new(&home).join(rest)).ok(),
+                None => Some(PathBuf::from(path)),
+            },
+            None => env::var("XDG_CONFIG_HOME")
+                .map(|config| Path::new(&config).join("git/ignore"))
+                .or_else(|_| env::var("HOME").map(|home| Path::new(&home).join(".config/git/ignore")))
+                .ok(),
+        };
+        if let Some(global) = global {
+            read_ignore_file(&global, "", &mut rules);
+        }
+        read_ignore_file(&self.common_dir.join("info/exclude"), "", &mut rules);
+        self.scan_untracked("", &tracked, &tracked_dirs, &mut rules, deadline)
+    }
+
+    fn scan_untracked(
+        &self,
+        dir: &str,
+        tracked: &HashSet<&str>,
+        tracked_dirs: &HashSet<&str>,
+        rules: &mut Vec<IgnoreRule>,
+        deadline: Instant,
+    ) -> Option<bool> {
+        let inherited = rules.len();
+        let path = self.work_tree.join(dir);
+        read_ignore_file(&path.join(".gitignore"), dir, rules);
+        let entries = match fs::read_dir(&path) {
+            Ok(entries) => entries,
+            Err(_) => return Some(false),
+        };
+        let mut found = Some(false);
+        for entry in entries.filter_map(|e| e.ok()) {
+            if Instant::now() > deadline {
+                found = None;
+                break;
+            }
+            let name = entry.file_name();
+            if name == ".git" {
+                continue;
+            }
+            let relative = format!("{}{}", dir, name.to_string_lossy());
+            let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+            if tracked.contains(relative.as_str()) || is_ignored(rules, &relative, is_dir) {
+                continue;
+            }
+            if !is_dir {
+                found = Some(true);
+                break;
+            }
+            // A nested repository shows up as untracked as a whole
+            if !tracked_dirs.contains(relative.as_str()) && entry.path().join(".git").exists() {
+                found = Some(true);
+                break;
+            }
+            let subdir = format!("{}/", relative);
+            found = self.scan_untracked(&subdir, tracked, tracked_dirs, rules, deadline);
+            if found != Some(false) {
+                break;
+            }
+        }
+        rules.truncate(inherited);
+        found
+    }
+}
@@ -0,0 +1,375 @@
+extern crate std;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use parser;
+use parser::TokenKind;
+use run_command::BUILTINS;
+use structured::STRUCTURED;
+use state::ShellState;
+
+// What a piece of the command line is, which decides its color
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Highlight {
+    Command,
+    Builtin,
+    Keyword,
+    Unknown,
+    String,
+    Variable,
+    Operator,
+    Path,
+    Comment,
+    Error,
+}
+
+// The variable that styles each kind of text and its default, in the style tags of prompt
+// templates
+const THEME: &[(Highlight, &str, &str)] = &[
+    (Highlight::Command, "HIGHLIGHT_COMMAND", "{GREEN}"),
+    (Highlight::Builtin, "HIGHLIGHT_BUILTIN", "{BRIGHT_GREEN}"),
+    (Highlight::Keyword, "HIGHLIGHT_KEYWORD", "{BLUE}"),
+    (Highlight::Unknown, "HIGHLIGHT_UNKNOWN", "{RED}"),
+    (Highlight::String, "HIGHLIGHT_STRING", "{YELLOW}"),
+    (Highlight::Variable, "HIGHLIGHT_VARIABLE", "{CYAN}"),
+    (Highlight::Operator, "HIGHLIGHT_OPERATOR", "{BRIGHT_BLUE}"),
+    (Highlight::Path, "HIGHLIGHT_PATH", "{UNDERLINE}"),
+    (Highlight::Comment, "HIGHLIGHT_COMMENT", "{BRIGHT_BLACK}"),
+    (Highlight::Error, "HIGHLIGHT_ERROR", "{BOLD}{RED}"),
+];
+
+const KEYWORDS: &[&str] = &[
+    "if", "then", "else", "elif", "fi", "case", "esac", "for", "select", "while", "until", "do",
+    "done", "function", "time", "!", "{", "}", "[[",
+];
+
+// Colors the line editor's input. It takes what it needs from the shell when the prompt is
+// drawn, so it can run on every keystroke without borrowing the shell.
+#[derive(Default)]
+pub struct Highlighter {
+    styles: HashMap<Highlight, String>,
+    path: Vec<PathBuf>,
+    cwd: PathBuf,
+    home: Option<String>,
+    // Whether each command name was found in PATH, or is an alias or function
+    commands: RefCell<HashMap<String, bool>>,
+}
+
+impl ShellState {
+    pub fn highlighter(&self) -> Highlighter {
+        let mut styles = HashMap::new();
+        for &(kind, variable, default) in THEME {
+            let template = self.variables
+                .get(variable)
+                .map(|v| v.to_string_lossy().into_owned())
+                .unwrap_or_else(|| default.to_owned());
+            styles.insert(kind, self.render_style(&template));
+        }
+        let path = self.variables
+            .get("PATH")
+            .map(|p| p.to_string_lossy().split(':').map(PathBuf::from).collect())
+            .unwrap_or_default();
+        // Aliases and functions count as commands as well
+        let mut commands: HashMap<String, bool> = self.aliases
+            .keys()
+            .chain(self.functions.keys())
+            .map(|name| (name.clone(), true))
+            .collect();
+        // So do the structured builtins, once they are turned on
+        if self.options.structured {
+            commands.extend(STRUCTURED.iter().map(|name| (name.to_string(), true)));
+        }
+        Highlighter {
+            styles,
+            path,
+            cwd: self.variables.get("PWD").map(PathBuf::from).unwrap_or_default(),
+            home: self.variables.get("HOME").map(|h| h.to_string_lossy().into_owned()),
+            commands: RefCell::new(commands),
+        }
+    }
+}
+
+impl Highlighter {
+    // Splits the input into byte ranges along with the escapes that style them. The ranges
+    // cover all of the input, in order.
+    pub fn highlight(&self, input: &str) -> Vec<(usize, usize, &str)> {
+        let mut kinds = vec![None; input.len()];
+        self.classify(input, &mut kinds);
+
+        let mut spans: Vec<(usize, usize, &str)> = Vec::new();
+        for (i, kind) in kinds.iter().enumerate() {
+            let style = kind.and_then(|k| self.styles.get(&k))
+                .map(|s| s.as_str())
+                .unwrap_or("");
+            match spans.last_mut() {
+                Some(span) if span.2 == style => span.1 = i + 1,
+                _ => spans.push((i, i + 1, style)),
+            }
+        }
+        spans
+    }
+
+    // Works out the kind of each byte of the input, following the parser's idea of where
+    // commands start and which blocks are open
+    fn classify(&self, input: &str, kinds: &mut [Option<Highlight>]) {
+        let (tokens, _) = parser::tokenize_partial(input);
+        // Closing word or bracket of each open block, and where its opener is
+        let mut open: Vec<(&str, usize, usize)> = Vec::new();
+        let mut command_position = true;
+        // Where an assignment that can start an array literal ends, and whether one is open
+        let mut assigned = None;
+        let mut in_array = false;
+        for token in &tokens {
+            let text = token.text.as_str();
+            let after_assignment = assigned.take() == Some(token.start);
+            match token.kind {
+                TokenKind::Comment => mark(kinds, token.start, token.end, Highlight::Comment),
+                TokenKind::HereDocument => mark(kinds, token.start, token.end, Highlight::String),
+                TokenKind::Newline => command_position = !in_array,
+                TokenKind::Operator if (text == "(" && after_assignment) || (text == ")" && in_array) => {
+                    // The elements of name=(...) are arguments, and the command still comes
+                    // after it
+                    mark(kinds, token.start, token.end, Highlight::Operator);
+                    in_array = text == "(";
+                    command_position = !in_array;
+                }
+                TokenKind::Operator => {
+                    let mut kind = Highlight::Operator;
+                    if text == "(" {
+                        open.push((")", token.start, token.end));
+                    } else if text == ")" {
+                        if open.last().is_some_and(|o| o.0 == ")") {
+                            open.pop();
+                        } else {
+                            kind = Highlight::Error;
+                        }
+                    }
+                    mark(kinds, token.start, token.end, kind);
+                    command_position =
+                        matches!(text, "|" | "||" | "&&" | "&" | ";" | ";;" | "(" | ")");
+                }
+                TokenKind::Word if command_position && parser::is_expression(text) => {
+                    mark(kinds, token.start, token.start + 1, Highlight::Keyword);
+                    self.expansions(&text[1..], token.start + 1, kinds);
+                    command_position = false;
+                }
+                TokenKind::Word if command_position => {
+                    if let Some(name_end) = parser::assignment(text) {
+                        // NAME=value before a command leaves the next word in command position
+                        mark(kinds, token.start, token.start + name_end, Highlight::Variable);
+                        self.argument(&text[name_end + 1..], token.start + name_end + 1, kinds);
+                        if text.ends_with('=') {
+                            assigned = Some(token.end);
+                        }
+                        continue;
+                    }
+                    let kind = if let Some(closer) = parser::block_closer(text) {
+                        open.push((closer, token.start, token.end));
+                        Highlight::Keyword
+                    } else if matches!(text, "fi" | "esac" | "done" | "}") {
+                        if open.last().is_some_and(|o| o.0 == text) {
+                            open.pop();
+                            Highlight::Keyword
+                        } else {
+                            Highlight::Error
+                        }
+                    } else if KEYWORDS.contains(&text) {
+                        Highlight::Keyword
+                    } else {
+                        match unquote(text) {
+                            Some(name) => self.command(&name),
+                            None => {
+                                self.expansions(text, token.start, kinds);
+                                command_position = false;
+                                continue;
+                            }
+                        }
+                    };
+                    mark(kinds, token.start, token.end, kind);
+                    self.expansions(text, token.start, kinds);
+                    command_position = parser::is_command_prefix(text);
+                }
+                TokenKind::Word => {
+                    // declare name=(...)
+                    if text.ends_with('=') && parser::assignment(text).is_some() {
+                        assigned = Some(token.end);
+                    }
+                    self.argument(text, token.start, kinds)
+                }
+            }
+        }
+        // Brackets that are never closed. Blocks like if may still be closed on a later line.
+        for (closer, start, end) in open {
+            if closer == ")" || closer == "}" {
+                mark(kinds, start, end, Highlight::Error);
+            }
+        }
+    }
+
+    fn command(&self, name: &str) -> Highlight {
+        if BUILTINS.contains(&name) {
+            return Highlight::Builtin;
+        }
+        let found = if name.contains('/') {
+            is_executable(&self.resolve(name))
+        } else {
+            *self.commands
+                .borrow_mut()
+                .entry(name.to_owned())
+                .or_insert_with(|| self.path.iter().any(|dir| is_executable(&dir.join(name))))
+        };
+        if found {
+            Highlight::Command
+        } else {
+            Highlight::Unknown
+        }
+    }
+
+    // Words after the command are underlined when they name a file that exists
+    fn argument(&self, text: &str, start: usize, kinds: &mut [Option<Highlight>]) {
+        if let Some(path) = unquote(text) {
+            if !path.is_empty() && !path.starts_with('-') && self.resolve(&path).exists() {
+                mark(kinds, start, start + text.len(), Highlight::Path);
+            }
+        }
+        self.expansions(text, start, kinds);
+    }
+
+    // Marks the quotes, variables and substitutions within a word, and quotes that are never
+    // closed
+    fn expansions(&self, text: &str, start: usize, kinds: &mut [Option<Highlight>]) {
+        let bytes = text.as_bytes();
+        let mut i = 0;
+        while i < bytes.len() {
+            let (kind, end) = match bytes[i] {
+                b'\\' => {
+                    i += 1 + text[i + 1..].chars().next().map(|c| c.len_utf8()).unwrap_or(0);
+                    continue;
+                }
+                b'\'' | b'"' => {
+                    match parser::skip_quoted(text, i) {
+                        Ok(end) => {
+                            mark(kinds, start + i, start + end, Highlight::String);
+                            if bytes[i] == b'"' {
+                                self.variables(&text[..end - 1], i + 1, start, kinds);
+                            }
+                            i = end;
+                            continue;
+                        }
+                        Err(_) => (Highlight::Error, bytes.len()),
+                    }
+                }
+                b'`' => {
+                    match parser::skip_quoted(text, i) {
+                        Ok(end) => (Highlight::Variable, end),
+                        Err(_) => (Highlight::Error, bytes.len()),
+                    }
+                }
+                b'$' => {
+                    match variable_end(text, i) {
+                        Some(Ok(end)) => (Highlight::Variable, end),
+                        Some(Err(_)) => (Highlight::Error, bytes.len()),
+                        None => (Highlight::Variable, i + 1),
+                    }
+                }
+                b'@' if bytes.get(i + 1) == Some(&b'(') => {
+                    match parser::skip_nested(text, i + 1) {
+                        Ok(end) => (Highlight::Variable, end),
+                        Err(_) => (Highlight::Error, bytes.len()),
+                    }
+                }
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            };
+            mark(kinds, start + i, start + end, kind);
+            i = end;
+        }
+    }
+
+    // Marks the variables within the double-quoted text[from..]
+    fn variables(&self, text: &str, from: usize, start: usize, kinds: &mut [Option<Highlight>]) {
+        let bytes = text.as_bytes();
+        let mut i = from;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'\\' => i += 2,
+                b'$' => {
+                    match variable_end(text, i) {
+                        Some(Ok(end)) => {
+                            mark(kinds, start + i, start + end, Highlight::Variable);
+                            i = end;
+                        }
+                        _ => i += 1,
+                    }
+                }
+                _ => i += 1,
+            }
+        }
+    }
+
+    // Makes a path relative to the working directory absolute, expanding a leading ~
+    fn resolve(&self, path: &str) -> PathBuf {
+        match (path.strip_prefix('~'), self.home.as_ref()) {
+            (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+                PathBuf::from(format!("{}{}", home, rest))
+            }
+            _ => self.cwd.join(path),
+        }
+    }
+}
+
+fn mark(kinds: &mut [Option<Highlight>], start: usize, end: usize, kind: Highlight) {
+    let end = end.min(kinds.len());
+    for k in &mut kinds[start..end] {
+        *k = Some(kind);
+    }
+}
+
+// End of the variable reference or substitution starting with the $ at `i`, or None when the
+// $ doesn't start one
+fn variable_end(text: &str, i: usize) -> Option<Result<usize, parser::ParseError>> {
+    let bytes = text.as_bytes();
+    match bytes.get(i + 1) {
+        Some(&b'(') | Some(&b'{') => Some(parser::skip_nested(text, i + 1)),
+        Some(c) if b"?$!#*@-".contains(c) || c.is_ascii_digit() => Some(Ok(i + 2)),
+        Some(c) if c.is_ascii_alphabetic() || *c == b'_' => {
+            let length = bytes[i + 1..]
+                .iter()
+                .take_while(|c| c.is_ascii_alphanumeric() || **c == b'_')
+                .count();
+            Some(Ok(i + 1 + length))
+        }
+        _ => None,
+    }
+}
+
+// The word with its quotes and backslashes taken out, as long as nothing in it is expanded
+fn unquote(word: &str) -> Option<String> {
+    let mut output = String::new();
+    let mut quote = None;
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            ('$', q) | ('`', q) if q != Some('\'') => return None,
+            ('*', None) | ('?', None) | ('[', None) => return None,
+            ('\'', None) | ('"', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('\\', q) if q != Some('\'') => output.push(chars.next()?),
+            (c, _) => output.push(c),
+        }
+    }
+    if quote.is_some() {
+        None
+    } else {
+        Some(output)
+    }
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
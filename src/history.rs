@@ -1,52 +1,556 @@
 extern crate std;
+extern crate libc;
+extern crate glob;
 
-use std::io::Write;
-use std::io::BufRead;
-use std::ffi::OsString;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ffi::{CString, OsString};
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use state::ShellState;
 use circular_buffer::CircularBuffer;
+use redact;
+
+// How far this session has read the shared history file
+#[derive(Debug, Default)]
+pub struct HistoryFile {
+    offset: u64,
+    inode: u64,
+    entries: usize,
+    // Entries recorded by this session since the last `history -a`
+    pub unappended: Vec<HistoryEntry>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HistoryEntry {
+    pub command: String,
+    // Seconds since the epoch at which the command was entered
+    pub timestamp: u64,
+    pub cwd: String,
+    pub status: Option<i32>,
+    // In milliseconds
+    pub duration: Option<u64>,
+}
+
+impl HistoryEntry {
+    pub fn new(command: &str, cwd: &str) -> Self {
+        HistoryEntry {
+            command: command.to_owned(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            cwd: cwd.to_owned(),
+            status: None,
+            duration: None,
+        }
+    }
+
+    // Entries are stored as `: <timestamp>:<duration ms>:<status>:<cwd>;<command>`, a superset of
+    // zsh's EXTENDED_HISTORY `: <timestamp>:<duration s>;<command>`. The command is written with
+    // escape_command, so multi-line commands carry on over several lines.
+    pub fn to_line(&self) -> String {
+        let mut cwd = String::new();
+        for c in self.cwd.chars() {
+            match c {
+                '\\' => cwd.push_str("\\\\"),
+                ';' => cwd.push_str("\\;"),
+                '\n' => cwd.push_str("\\n"),
+                _ => cwd.push(c),
+            }
+        }
+        format!(
+            ": {}:{}:{}:{};{}\n",
+            self.timestamp,
+            self.duration.map(|d| d.to_string()).unwrap_or_default(),
+            self.status.map(|s| s.to_string()).unwrap_or_default(),
+            cwd,
+            escape_command(&self.command)
+        )
+    }
+
+    // Parses one logical record, falling back to treating it as a bare command
+    pub fn parse(record: &str) -> Self {
+        parse_extended(record).unwrap_or_else(|| {
+            HistoryEntry {
+                command: record.to_owned(),
+                ..Default::default()
+            }
+        })
+    }
+}
+
+fn take_field(input: &str) -> Option<(&str, &str)> {
+    let end = input.find(&[':', ';'][..])?;
+    Some((&input[..end], &input[end..]))
+}
+
+fn parse_extended(record: &str) -> Option<HistoryEntry> {
+    let rest = record.strip_prefix(": ")?;
+    let (timestamp, rest) = take_field(rest)?;
+    let timestamp = timestamp.parse::<u64>().ok()?;
+    let (duration, rest) = take_field(rest.strip_prefix(':')?)?;
+    let duration = if duration.is_empty() {
+        None
+    } else {
+        Some(duration.parse::<u64>().ok()?)
+    };
+
+    // zsh records the duration in seconds and nothing else
+    if let Some(command) = rest.strip_prefix(';') {
+        return Some(HistoryEntry {
+            command: command.to_owned(),
+            timestamp,
+            cwd: String::new(),
+            status: None,
+            duration: duration.map(|d| d * 1000),
+        });
+    }
+
+    let (status, rest) = take_field(rest.strip_prefix(':')?)?;
+    let status = if status.is_empty() {
+        None
+    } else {
+        Some(status.parse::<i32>().ok()?)
+    };
+    let rest = rest.strip_prefix(':')?;
+    let mut chars = rest.char_indices();
+    let mut cwd = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                match chars.next() {
+                    Some((_, 'n')) => cwd.push('\n'),
+                    Some((_, c)) => cwd.push(c),
+                    None => return None,
+                }
+            }
+            ';' => {
+                return Some(HistoryEntry {
+                    command: rest[i + 1..].to_owned(),
+                    timestamp,
+                    cwd,
+                    status,
+                    duration,
+                })
+            }
+            _ => cwd.push(c),
+        }
+    }
+    None
+}
+
+// How a command is written to the history file. Backslashes at the end of a line are doubled,
+// and every line but the last gets one more, so a line ending in an odd number of them carries
+// on to the next and a command that itself ends in a backslash doesn't.
+pub fn escape_command(command: &str) -> String {
+    let mut escaped = String::new();
+    for (i, line) in command.split('\n').enumerate() {
+        if i > 0 {
+            escaped.push_str("\\\n");
+        }
+        let trailing = line.len() - line.trim_end_matches('\\').len();
+        escaped.push_str(line);
+        escaped.push_str(&"\\".repeat(trailing));
+    }
+    escaped
+}
+
+// Splits a history file into entries, joining lines that end in an odd number of backslashes
+pub fn parse_history(contents: &str) -> Vec<HistoryEntry> {
+    let mut entries = Vec::new();
+    let mut record = String::new();
+    for line in contents.lines() {
+        let text = line.trim_end_matches('\\');
+        let trailing = line.len() - text.len();
+        record.push_str(text);
+        record.push_str(&"\\".repeat(trailing / 2));
+        if trailing % 2 == 1 {
+            record.push('\n');
+        } else {
+            entries.push(HistoryEntry::parse(&record));
+            record.clear();
+        }
+    }
+    if !record.is_empty() {
+        entries.push(HistoryEntry::parse(&record));
+    }
+    entries
+}
+
+// zsh escapes bytes that are special to it with 0x83 followed by the byte xor 32
+fn unmetafy(bytes: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(bytes.len());
+    let mut iter = bytes.iter();
+    while let Some(&b) = iter.next() {
+        if b == 0x83 {
+            if let Some(&next) = iter.next() {
+                output.push(next ^ 32);
+            }
+        } else {
+            output.push(b);
+        }
+    }
+    output
+}
+
+fn lock(file: &File, exclusive: bool) -> io::Result<()> {
+    let operation = if exclusive { libc::LOCK_EX } else { libc::LOCK_SH };
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+            return Ok(());
+        }
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+}
+
+// Opens and locks the history file. The lock is released when the file is dropped.
+fn open_locked(path: &Path, exclusive: bool) -> io::Result<File> {
+    loop {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .mode(0o600)
+            .open(path)?;
+        lock(&file, exclusive)?;
+        // A compaction in another session may have renamed a new file into place while we were
+        // waiting for the lock, in which case we hold the lock on the old one
+        if fs::metadata(path)?.ino() == file.metadata()?.ino() {
+            return Ok(file);
+        }
+    }
+}
 
 impl ShellState {
-    pub fn load_history(&mut self) {
-        let histsize = self.variables
+    // HISTFILE, or else ~/.rsh_history where older versions kept it, or the data directory
+    pub fn history_path(&self) -> PathBuf {
+        if let Some(path) = self.variables.get("HISTFILE") {
+            return PathBuf::from(path);
+        }
+        let legacy = Path::new(self.variables.get("HOME").unwrap_or(&OsString::from("/")))
+            .join(".rsh_history");
+        match self.data_dir() {
+            Some(dir) if !legacy.exists() => dir.join("history"),
+            _ => legacy,
+        }
+    }
+
+    fn histsize(&self) -> usize {
+        self.variables
             .get("HISTSIZE")
             .unwrap_or(&OsString::from("10000"))
             .to_string_lossy()
             .parse::<usize>()
-            .unwrap_or(10000);
-        self.history = CircularBuffer::new(histsize);
+            .unwrap_or(10000)
+    }
 
-        if let Ok(file) = std::fs::File::open(
-            &self.variables.get("HISTFILE").unwrap_or(&OsString::from(
-                ".rsh_history",
-            )),
-        )
-        {
-            let reader = std::io::BufReader::new(file);
-            for line in reader.lines().filter_map(|l| l.ok()) {
-                self.history.push(line);
+    // HISTCONTROL is a colon-separated list of options, ignoredups when unset.
+    // ignoreboth stands for ignorespace and ignoredups.
+    pub fn histcontrol(&self, option: &str) -> bool {
+        match self.variables.get("HISTCONTROL") {
+            Some(value) => {
+                value.to_string_lossy().split(':').any(|o| {
+                    o == option ||
+                        (o == "ignoreboth" &&
+                             (option == "ignorespace" || option == "ignoredups"))
+                })
             }
+            None => option == "ignoredups",
         }
     }
 
-    pub fn print_history(&self) {
-        for (i, entry) in self.history.iter().enumerate() {
-            println!("{:>5.} {}", i, entry);
+    // Applies HISTCONTROL=ignorespace, the HISTIGNORE patterns and secret redaction to a command
+    // on its way into the history. Returns None if it should not be saved at all.
+    fn filter_history(&self, mut entry: HistoryEntry) -> Option<HistoryEntry> {
+        if self.histcontrol("ignorespace") && entry.command.starts_with(' ') {
+            return None;
         }
+
+        // HISTIGNORE is a colon-separated list of globs matched against the whole line, where
+        // & stands for the previous entry
+        if let Some(patterns) = self.variables.get("HISTIGNORE") {
+            let previous = self.history.tail().map(|e| e.command.as_str());
+            for pattern in patterns.to_string_lossy().split(':') {
+                let ignored = if pattern == "&" {
+                    previous == Some(entry.command.as_str())
+                } else {
+                    glob::Pattern::new(pattern)
+                        .map(|p| p.matches(&entry.command))
+                        .unwrap_or(false)
+                };
+                if ignored {
+                    return None;
+                }
+            }
+        }
+
+        // HISTREDACT=off keeps secrets in the history
+        let redact = self.variables
+            .get("HISTREDACT")
+            .is_none_or(|v| v != "off" && v != "0");
+        if redact {
+            entry.command = redact::redact_secrets(&entry.command);
+        }
+        Some(entry)
     }
 
-    pub fn save_history(&self) {
-#![allow(unused)] // We do this on the way out, so failures are just too bad
-        if let Ok(mut file) = std::fs::File::create(
-            self.variables.get("HISTFILE").unwrap_or(&OsString::from(
-                ".rsh_history",
-            )),
-        )
+    pub fn load_history(&mut self) {
+        if self.variables.get("HISTFILE").is_none() {
+            if let Some(dir) = self.history_path().parent() {
+                let _ = fs::create_dir_all(dir);
+            }
+        }
+        self.history = CircularBuffer::new(self.histsize());
+        self.history_file = HistoryFile::default();
+        self.sync_history();
+    }
+
+    // Picks up entries other sessions appended to the history file since we last looked.
+    // Runs before every prompt, like zsh's SHARE_HISTORY.
+    pub fn sync_history(&mut self) {
+        #![allow(unused)]
+        if let Ok(mut file) = open_locked(&self.history_path(), false) {
+            self.read_new_entries(&mut file);
+        }
+    }
+
+    // Must be called with the history file locked
+    fn read_new_entries(&mut self, file: &mut File) -> io::Result<()> {
+        let metadata = file.metadata()?;
+        if metadata.ino() != self.history_file.inode || metadata.len() < self.history_file.offset {
+            // The file was compacted or replaced by someone else, start over from its contents
+            self.history = CircularBuffer::new(self.histsize());
+            self.history_file = HistoryFile {
+                offset: 0,
+                inode: metadata.ino(),
+                entries: 0,
+                unappended: std::mem::take(&mut self.history_file.unappended),
+            };
+        }
+
+        file.seek(SeekFrom::Start(self.history_file.offset))?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        self.history_file.offset += contents.len() as u64;
+        for entry in parse_history(&String::from_utf8_lossy(&contents)) {
+            self.history_file.entries += 1;
+            self.add_to_history(entry);
+        }
+        Ok(())
+    }
+
+    // Adds an entry to the in-memory history, applying the HISTCONTROL dedup options.
+    // Returns false if the entry was dropped.
+    pub fn add_to_history(&mut self, entry: HistoryEntry) -> bool {
+        if entry.command.trim().is_empty() {
+            return false;
+        }
+        if self.histcontrol("erasedups") {
+            self.history.retain(|e| e.command != entry.command);
+        } else if self.histcontrol("ignoredups") &&
+                   self.history.tail().map(|e| &e.command) == Some(&entry.command)
         {
-            for entry in self.history.iter() {
-                file.write_all(entry.as_bytes());
-                file.write_all(b"\n");
+            return false;
+        }
+        self.history.push(entry);
+        true
+    }
+
+    // Adds entries to the history and appends them to the history file while holding its lock,
+    // after first merging whatever other sessions wrote since we last looked. Compacts the file
+    // once it holds a tenth more than HISTSIZE entries, so it isn't rewritten after every command.
+    fn append_history(&mut self, entries: Vec<HistoryEntry>) -> io::Result<()> {
+        let path = self.history_path();
+        let mut file = open_locked(&path, true);
+        if let Ok(ref mut file) = file {
+            self.read_new_entries(file)?;
+        }
+
+        let mut lines = String::new();
+        for entry in entries {
+            let line = entry.to_line();
+            if self.add_to_history(entry) {
+                lines.push_str(&line);
+                self.history_file.entries += 1;
+            }
+        }
+
+        let mut file = file?;
+        file.write_all(lines.as_bytes())?;
+        self.history_file.offset += lines.len() as u64;
+
+        let histsize = self.histsize();
+        if self.history_file.entries > histsize + histsize / 10 {
+            self.write_history(&path)?;
+        }
+        Ok(())
+    }
+
+    // Replaces the history file with the in-memory history. The new file is written next to the
+    // old one and renamed over it, so other sessions never see it half-written.
+    fn write_history(&mut self, path: &Path) -> io::Result<()> {
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(format!(".{}.tmp", std::process::id()));
+        let temp_path = PathBuf::from(temp_path);
+
+        let mut lines = String::new();
+        for entry in self.history.iter() {
+            lines.push_str(&entry.to_line());
+        }
+        let written = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&temp_path)
+            .and_then(|mut file| {
+                file.write_all(lines.as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&temp_path, path));
+        if written.is_err() {
+            let _ = fs::remove_file(&temp_path);
+            return written;
+        }
+
+        self.history_file.offset = lines.len() as u64;
+        self.history_file.inode = fs::metadata(path)?.ino();
+        self.history_file.entries = self.history.len();
+        Ok(())
+    }
+
+    // Reads a zsh history file, plain or EXTENDED_HISTORY, into the history
+    pub fn import_history(&mut self, path: &Path) -> io::Result<usize> {
+        let mut contents = Vec::new();
+        File::open(path)?.read_to_end(&mut contents)?;
+        let entries: Vec<HistoryEntry> =
+            parse_history(&String::from_utf8_lossy(&unmetafy(&contents)))
+                .into_iter()
+                .filter_map(|e| self.filter_history(e))
+                .collect();
+        let count = entries.len();
+        self.append_history(entries)?;
+        Ok(count)
+    }
+
+    // Starts a history entry for a command that is about to run
+    pub fn history_entry(&self, command: &str) -> HistoryEntry {
+        let cwd = self.variables
+            .get("PWD")
+            .map(|p| p.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        HistoryEntry::new(command, &cwd)
+    }
+
+    // Fills in how the command went, then adds it to the history and the history file
+    pub fn record_history(&mut self, mut entry: HistoryEntry, status: i32, duration: Duration) {
+        #![allow(unused)] // Failing to write the history shouldn't interrupt the user
+        entry.status = Some(status);
+        entry.duration = Some(duration.as_millis() as u64);
+        let entry = match self.filter_history(entry) {
+            Some(entry) => entry,
+            None => return,
+        };
+        self.history_file.unappended.push(entry.clone());
+        self.append_history(vec![entry]);
+    }
+
+    pub fn save_history(&mut self) -> io::Result<()> {
+        let path = self.history_path();
+        let mut file = open_locked(&path, true)?;
+        self.read_new_entries(&mut file)?;
+        self.write_history(&path)
+    }
+}
+
+// Matches when every char of the query appears in the candidate in order. An all-lowercase
+// query matches case-insensitively.
+pub fn fuzzy_match(query: &str, candidate: &str) -> bool {
+    let ignore_case = !query.chars().any(|c| c.is_uppercase());
+    let mut candidate = candidate.chars();
+    query.chars().all(|q| {
+        candidate.any(|c| if ignore_case {
+            c.to_lowercase().eq(q.to_lowercase())
+        } else {
+            c == q
+        })
+    })
+}
+
+impl ShellState {
+    // Searches the history from `start` (inclusive) towards older entries when `reverse` is set,
+    // or towards newer ones otherwise. Entries identical to `skip` are passed over.
+    pub fn search_history<F>(
+        &self,
+        start: usize,
+        reverse: bool,
+        skip: &str,
+        matches: F,
+    ) -> Option<usize>
+    where
+        F: Fn(&str) -> bool,
+    {
+        let len = self.history.len();
+        let mut index = start;
+        while index < len {
+            if let Some(entry) = self.history.get(index) {
+                if entry.command != skip && matches(&entry.command) {
+                    return Some(index);
+                }
+            }
+            if reverse {
+                if index == 0 {
+                    return None;
+                }
+                index -= 1;
+            } else {
+                index += 1;
             }
         }
+        None
+    }
+}
+
+pub fn format_time(timestamp: u64, format: &str) -> String {
+    let format = match CString::new(format) {
+        Ok(format) => format,
+        Err(_) => return String::new(),
+    };
+    let time = timestamp as libc::time_t;
+    let mut buffer = [0u8; 256];
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return String::new();
+        }
+        let len = libc::strftime(
+            buffer.as_mut_ptr() as *mut libc::c_char,
+            buffer.len(),
+            format.as_ptr(),
+            &tm,
+        );
+        String::from_utf8_lossy(&buffer[..len]).into_owned()
+    }
+}
+
+// Short human-readable form of a duration in milliseconds, like 340ms, 2.5s or 1h02m
+pub fn format_duration(millis: u64) -> String {
+    let seconds = millis / 1000;
+    if millis < 1000 {
+        format!("{}ms", millis)
+    } else if seconds < 60 {
+        format!("{}.{}s", seconds, millis % 1000 / 100)
+    } else if seconds < 3600 {
+        format!("{}m{:02}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{}h{:02}m", seconds / 3600, seconds % 3600 / 60)
     }
 }
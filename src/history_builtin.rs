@@ -0,0 +1,413 @@
+extern crate std;
+extern crate glob;
+
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Instant;
+use exec;
+use error::{Result, ShellError};
+use state::ShellState;
+use history;
+use parser;
+
+const HISTORY_USAGE: &str = "usage: history [-c] [-d offset] [-anrw [file]] [-iD] [-t format] \
+                             [-m pattern] [n | string]\n       history import FILE";
+const FC_USAGE: &str = "usage: fc [-e editor] [-lnr] [first [last]]\n       \
+                        fc -s [old=new] [command]\n       fc -e - [old=new] [command]";
+
+// How to show entries when listing them
+#[derive(Default)]
+struct ListOptions {
+    numbers: bool,
+    reverse: bool,
+    time_format: Option<String>,
+    durations: bool,
+}
+
+impl ShellState {
+    // Resolves a history index as printed by `history`, a negative offset from the end,
+    // or the most recent command starting with a string
+    fn history_index(&self, spec: &str) -> Option<usize> {
+        let len = self.history.len();
+        if let Ok(n) = spec.parse::<i64>() {
+            return if n < 0 {
+                len.checked_sub(n.unsigned_abs() as usize)
+            } else if (n as usize) < len {
+                Some(n as usize)
+            } else {
+                None
+            };
+        }
+        self.search_history(len.checked_sub(1)?, true, "", |e| e.starts_with(spec))
+    }
+
+    fn list_history(&self, indices: &[usize], options: &ListOptions) -> Result<()> {
+        let mut indices = indices.to_vec();
+        if options.reverse {
+            indices.reverse();
+        }
+        for index in indices {
+            let entry = match self.history.get(index) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let mut line = String::new();
+            if options.numbers {
+                line.push_str(&format!("{:>5.} ", index));
+            }
+            if let Some(ref format) = options.time_format {
+                if entry.timestamp > 0 {
+                    line.push_str(&history::format_time(entry.timestamp, format));
+                    line.push(' ');
+                }
+            }
+            if options.durations {
+                let duration = entry.duration.map(history::format_duration).unwrap_or_default();
+                line.push_str(&format!("{:>6} ", duration));
+            }
+            line.push_str(&entry.command);
+            writeln!(exec::output(), "{}", line)?;
+        }
+        Ok(())
+    }
+
+    pub fn print_history(&self) -> Result<()> {
+        let indices: Vec<usize> = (0..self.history.len()).collect();
+        self.list_history(
+            &indices,
+            &ListOptions {
+                numbers: true,
+                ..Default::default()
+            },
+        )
+    }
+
+    pub fn history_builtin(&mut self, args: &[String]) -> Result<i32> {
+        let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+        let mut options = ListOptions {
+            numbers: true,
+            time_format: self.variables
+                .get("HISTTIMEFORMAT")
+                .map(|f| f.to_string_lossy().into_owned()),
+            ..Default::default()
+        };
+        let mut pattern: Option<glob::Pattern> = None;
+        let mut filter: Option<&str> = None;
+        let mut count: Option<usize> = None;
+
+        let mut i = 0;
+        while i < args.len() {
+            let file = args.get(i + 1).cloned();
+            match args[i] {
+                "import" => {
+                    let path = match file {
+                        Some(path) => path,
+                        None => {
+                            let _ = writeln!(io::stderr(), "{}", HISTORY_USAGE);
+                            return Ok(2);
+                        }
+                    };
+                    let count = self
+                        .import_history(Path::new(path))
+                        .map_err(|e| ShellError::io(path, e))?;
+                    writeln!(exec::output(), "imported {} entries", count)?;
+                    return Ok(0);
+                }
+                "-c" => {
+                    self.history.retain(|_| false);
+                    return Ok(0);
+                }
+                "-d" => {
+                    let index = file.and_then(|spec| {
+                        if spec.parse::<i64>().is_ok() {
+                            self.history_index(spec)
+                        } else {
+                            None
+                        }
+                    });
+                    return match index {
+                        Some(index) => {
+                            let mut position = 0;
+                            self.history.retain(|_| {
+                                position += 1;
+                                position - 1 != index
+                            });
+                            Ok(0)
+                        }
+                        None => {
+                            Err(ShellError::Message(format!(
+                                "{}: history position out of range",
+                                file.unwrap_or("")
+                            )))
+                        }
+                    };
+                }
+                "-a" | "-r" | "-w" => return self.history_file_operation(args[i], file),
+                "-i" => options.time_format = Some("%Y-%m-%d %H:%M".to_owned()),
+                "-D" => options.durations = true,
+                "-t" => {
+                    options.time_format = file.map(|f| f.to_owned());
+                    i += 1;
+                }
+                "-m" => {
+                    match file.map(glob::Pattern::new) {
+                        Some(Ok(p)) => pattern = Some(p),
+                        _ => {
+                            self.report("history: -m: bad pattern");
+                            return Ok(2);
+                        }
+                    }
+                    i += 1;
+                }
+                arg if arg.starts_with('-') && arg.len() > 1 => {
+                    self.report(&format!("history: {}: invalid option", arg));
+                    let _ = writeln!(io::stderr(), "{}", HISTORY_USAGE);
+                    return Ok(2);
+                }
+                arg => {
+                    match arg.parse::<usize>() {
+                        Ok(n) => count = Some(n),
+                        Err(_) => filter = Some(arg),
+                    }
+                }
+            }
+            i += 1;
+        }
+
+        let mut indices: Vec<usize> = (0..self.history.len())
+            .filter(|&i| {
+                let command = &self.history.get(i).unwrap().command;
+                filter.is_none_or(|f| command.contains(f)) &&
+                    pattern.as_ref().is_none_or(|p| p.matches(command))
+            })
+            .collect();
+        if let Some(count) = count {
+            let skip = indices.len().saturating_sub(count);
+            indices.drain(..skip);
+        }
+        self.list_history(&indices, &options)?;
+        Ok(0)
+    }
+
+    // history -a, -r and -w, on HISTFILE unless another file is given
+    fn history_file_operation(&mut self, operation: &str, file: Option<&str>) -> Result<i32> {
+        let histfile = self.history_path();
+        let path = file.map(Path::new).unwrap_or(&histfile).to_owned();
+        let result = match operation {
+            // Commands are added to HISTFILE as soon as they finish, so only other files need this
+            "-a" if path == histfile => Ok(()),
+            "-a" => {
+                let mut lines = String::new();
+                for entry in self.history_file.unappended.drain(..) {
+                    lines.push_str(&entry.to_line());
+                }
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .and_then(|mut f| f.write_all(lines.as_bytes()))
+            }
+            "-r" => {
+                let mut contents = Vec::new();
+                fs::File::open(&path).and_then(|mut f| f.read_to_end(&mut contents)).map(|_| {
+                    for entry in history::parse_history(&String::from_utf8_lossy(&contents)) {
+                        self.add_to_history(entry);
+                    }
+                })
+            }
+            _ if path == histfile => self.save_history(),
+            _ => {
+                let mut lines = String::new();
+                for entry in self.history.iter() {
+                    lines.push_str(&entry.to_line());
+                }
+                fs::write(&path, lines)
+            }
+        };
+        result.map_err(|e| ShellError::io(&path.to_string_lossy(), e))?;
+        Ok(0)
+    }
+
+    // Runs commands from fc, echoing them first and adding them to the history
+    fn run_history_commands(&mut self, commands: &str) -> Result<i32> {
+        let mut status = 0;
+        let mut command = String::new();
+        for line in commands.lines() {
+            if !command.is_empty() {
+                command.push('\n');
+            }
+            command.push_str(line);
+            if !parser::is_complete(&command) {
+                continue;
+            }
+            if !command.trim().is_empty() {
+                writeln!(exec::output(), "{}", command)?;
+                let entry = self.history_entry(&command);
+                let started = Instant::now();
+                status = self.run_line(&command);
+                self.record_history(entry, status, started.elapsed());
+                self.history_rerun = true;
+            }
+            command.clear();
+        }
+        Ok(status)
+    }
+
+    pub fn fc(&mut self, args: &[String]) -> Result<i32> {
+        let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+        let mut list = false;
+        let mut options = ListOptions {
+            numbers: true,
+            ..Default::default()
+        };
+        let mut editor: Option<String> = None;
+        let mut substitute = false;
+        let mut operands = Vec::new();
+
+        let mut i = 0;
+        while i < args.len() {
+            match args[i] {
+                "-l" => list = true,
+                "-n" => options.numbers = false,
+                "-r" => options.reverse = true,
+                "-s" => substitute = true,
+                "-e" => {
+                    i += 1;
+                    match args.get(i) {
+                        // fc -e - reruns without an editor, the same as fc -s
+                        Some(&"-") => substitute = true,
+                        e => editor = e.map(|e| e.to_string()),
+                    }
+                }
+                // Negative numbers are offsets, not options
+                arg if arg.starts_with('-') && arg.len() > 1 &&
+                           arg[1..].parse::<i64>().is_err() => {
+                    self.report(&format!("fc: {}: invalid option", arg));
+                    let _ = writeln!(io::stderr(), "{}", FC_USAGE);
+                    return Ok(2);
+                }
+                arg => operands.push(arg),
+            }
+            i += 1;
+        }
+
+        if substitute {
+            let (replacement, spec) = match operands.first() {
+                Some(op) if op.contains('=') => (op.split_once('='), operands.get(1)),
+                _ => (None, operands.first()),
+            };
+            let index = match spec {
+                Some(spec) => self.history_index(spec),
+                None => self.history.len().checked_sub(1),
+            };
+            let mut command = match index.and_then(|i| self.history.get(i)) {
+                Some(entry) => entry.command.clone(),
+                None => return Err(ShellError::Message("no command found".to_owned())),
+            };
+            if let Some((old, new)) = replacement {
+                command = command.replacen(old, new, 1);
+            }
+            return self.run_history_commands(&command);
+        }
+
+        let len = self.history.len();
+        let resolve = |spec: Option<&&str>, default: Option<usize>| match spec {
+            Some(spec) => self.history_index(spec),
+            None => default,
+        };
+        let (first, last) = if list {
+            let first = resolve(operands.first(), Some(len.saturating_sub(16)));
+            (first, resolve(operands.get(1), len.checked_sub(1)))
+        } else {
+            let first = resolve(operands.first(), len.checked_sub(1));
+            (first, resolve(operands.get(1), first))
+        };
+        let (first, last) = match (first, last) {
+            (Some(first), Some(last)) => (first, last),
+            _ => {
+                return Err(ShellError::Message(
+                    "history specification out of range".to_owned(),
+                ))
+            }
+        };
+        // A backwards range lists or edits in reverse
+        let mut indices: Vec<usize> = (first.min(last)..first.max(last) + 1).collect();
+        if first > last {
+            indices.reverse();
+        }
+
+        if list {
+            self.list_history(&indices, &options)?;
+            return Ok(0);
+        }
+
+        let mut commands = String::new();
+        if options.reverse {
+            indices.reverse();
+        }
+        for index in indices {
+            if let Some(entry) = self.history.get(index) {
+                commands.push_str(&entry.command);
+                commands.push('\n');
+            }
+        }
+        match self.edit_commands(&commands, editor)? {
+            Some(edited) => self.run_history_commands(&edited),
+            None => Ok(1),
+        }
+    }
+
+    // Opens the commands in FCEDIT, EDITOR or vi and returns what was saved, or None if the
+    // editor failed
+    fn edit_commands(&self, commands: &str, editor: Option<String>) -> Result<Option<String>> {
+        let editor = editor
+            .or_else(|| self.variables.get("FCEDIT").map(|e| e.to_string_lossy().into_owned()))
+            .or_else(|| self.variables.get("EDITOR").map(|e| e.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| "vi".to_owned());
+        let path = write_new_file(commands)?;
+
+        let mut words = editor.split_whitespace();
+        let program = words.next().unwrap_or("vi");
+        let edited = match Command::new(program).args(words).arg(&path).status() {
+            Ok(status) if status.success() => fs::read_to_string(&path).ok(),
+            Ok(_) => None,
+            Err(e) => {
+                let _ = fs::remove_file(&path);
+                return Err(ShellError::io(program, e));
+            }
+        };
+        let _ = fs::remove_file(&path);
+        Ok(edited)
+    }
+}
+
+// Writes the commands for fc to a new file only we can read. It is never one that was already
+// there, so another user can't have put a link in its place.
+fn write_new_file(commands: &str) -> Result<PathBuf> {
+    let mut attempt = 0;
+    loop {
+        let name = format!("rsh-fc-{}-{}.sh", std::process::id(), attempt);
+        let path = std::env::temp_dir().join(name);
+        let file = OpenOptions::new().write(true).create_new(true).mode(0o600).open(&path);
+        match file {
+            Ok(mut file) => {
+                return match file.write_all(commands.as_bytes()) {
+                    Ok(()) => Ok(path),
+                    Err(e) => {
+                        let _ = fs::remove_file(&path);
+                        Err(ShellError::io(&path.to_string_lossy(), e))
+                    }
+                };
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists && attempt < 100 => {
+                attempt += 1
+            }
+            Err(e) => return Err(ShellError::io(&path.to_string_lossy(), e)),
+        }
+    }
+}
@@ -0,0 +1,361 @@
+extern crate std;
+
+use std::iter::Peekable;
+use std::str::Chars;
+use state::ShellState;
+use parser;
+use parser::TokenKind;
+
+pub struct Expansion {
+    pub line: String,
+    // Whether any history reference was replaced
+    pub changed: bool,
+    // Set by the :p modifier, the line should be shown and added to history but not run
+    pub print_only: bool,
+}
+
+// An event with the string that !?str? searched for, which % refers to
+struct Event {
+    command: String,
+    search: Option<String>,
+}
+
+fn split_words(command: &str) -> Vec<String> {
+    match parser::tokenize(command) {
+        Ok(tokens) => {
+            tokens
+                .into_iter()
+                .filter(|t| t.kind == TokenKind::Word || t.kind == TokenKind::Operator)
+                .map(|t| t.text)
+                .collect()
+        }
+        Err(_) => command.split_whitespace().map(|w| w.to_owned()).collect(),
+    }
+}
+
+fn take_number(chars: &mut Peekable<Chars>) -> Option<usize> {
+    let mut digits = String::new();
+    while let Some(&c) = chars.peek() {
+        if !c.is_ascii_digit() {
+            break;
+        }
+        digits.push(c);
+        chars.next();
+    }
+    digits.parse().ok()
+}
+
+fn ends_event_string(c: char) -> bool {
+    c.is_whitespace() || matches!(c, ':' | ';' | '|' | '&' | '"' | '\'' | ')' | '<' | '>')
+}
+
+// Replaces the first (or every, if `global`) occurrence of `old` with `new`, where & in `new`
+// stands for `old`
+fn substitute(text: &str, old: &str, new: &str, global: bool) -> Result<String, String> {
+    if old.is_empty() || !text.contains(old) {
+        return Err(format!(":s/{}/: substitution failed", old));
+    }
+    let new = new.replace('&', old);
+    Ok(if global {
+        text.replace(old, &new)
+    } else {
+        text.replacen(old, &new, 1)
+    })
+}
+
+// Reads the parts of s/old/new/ after the s, with any delimiter
+fn take_substitution(chars: &mut Peekable<Chars>) -> Result<(String, String), String> {
+    let delimiter = chars.next().ok_or_else(|| "bad substitution".to_owned())?;
+    let mut parts = vec![String::new()];
+    while let Some(c) = chars.next() {
+        if c == delimiter {
+            if parts.len() == 2 {
+                break;
+            }
+            parts.push(String::new());
+        } else if c == '\\' && chars.peek() == Some(&delimiter) {
+            parts.last_mut().unwrap().push(delimiter);
+            chars.next();
+        } else {
+            parts.last_mut().unwrap().push(c);
+        }
+    }
+    let new = if parts.len() == 2 { parts.pop().unwrap() } else { String::new() };
+    Ok((parts.pop().unwrap(), new))
+}
+
+impl ShellState {
+    fn history_event(&self, chars: &mut Peekable<Chars>) -> Result<Event, String> {
+        let len = self.history.len();
+        let event = |index: Option<usize>, what: &str| {
+            index
+                .and_then(|i| self.history.get(i))
+                .map(|e| {
+                    Event {
+                        command: e.command.clone(),
+                        search: None,
+                    }
+                })
+                .ok_or_else(|| format!("!{}: event not found", what))
+        };
+
+        match chars.peek().cloned() {
+            Some('!') => {
+                chars.next();
+                event(len.checked_sub(1), "!")
+            }
+            // A bare word designator refers to the previous command
+            Some('^') | Some('$') | Some('*') | Some(':') | Some('%') => {
+                event(len.checked_sub(1), "")
+            }
+            Some('-') => {
+                chars.next();
+                let n = take_number(chars).ok_or_else(|| "!-: event not found".to_owned())?;
+                event(len.checked_sub(n), &format!("-{}", n))
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let n = take_number(chars).unwrap_or(0);
+                event(Some(n), &n.to_string())
+            }
+            Some('?') => {
+                chars.next();
+                let mut search = String::new();
+                for c in chars.by_ref() {
+                    if c == '?' || c == '\n' {
+                        break;
+                    }
+                    search.push(c);
+                }
+                let found = self.search_history(len.saturating_sub(1), true, "", |e| {
+                    e.contains(search.as_str())
+                });
+                let mut found = event(found, &format!("?{}", search))?;
+                found.search = Some(search);
+                Ok(found)
+            }
+            _ => {
+                let mut prefix = String::new();
+                while let Some(&c) = chars.peek() {
+                    if ends_event_string(c) {
+                        break;
+                    }
+                    prefix.push(c);
+                    chars.next();
+                }
+                let found = self.search_history(len.saturating_sub(1), true, "", |e| {
+                    e.starts_with(prefix.as_str())
+                });
+                event(found, &prefix)
+            }
+        }
+    }
+
+    // Expands one history reference, with `chars` positioned just after the !
+    fn expand_reference(
+        &self,
+        chars: &mut Peekable<Chars>,
+        print_only: &mut bool,
+    ) -> Result<String, String> {
+        let event = self.history_event(chars)?;
+        let words = split_words(&event.command);
+        let last = words.len().saturating_sub(1);
+
+        // Word designators, either after a colon or directly for ^ $ * %
+        let mut lookahead = chars.clone();
+        let colon = lookahead.peek() == Some(&':');
+        if colon {
+            lookahead.next();
+        }
+        let selected = match lookahead.peek().cloned() {
+            Some(c) if matches!(c, '^' | '$' | '*' | '%') ||
+                           (colon && (c.is_ascii_digit() || c == '-')) => {
+                *chars = lookahead;
+                Some(self.word_range(chars, &words, &event)?)
+            }
+            _ => None,
+        };
+        let mut text = match selected {
+            Some((first, end)) => {
+                if first > end || end > last {
+                    if first == 1 && end == last && words.len() == 1 {
+                        String::new()
+                    } else {
+                        return Err(format!("{}: bad word specifier", event.command));
+                    }
+                } else {
+                    words[first..end + 1].join(" ")
+                }
+            }
+            None => event.command.clone(),
+        };
+
+        // Modifiers
+        loop {
+            let mut lookahead = chars.clone();
+            if lookahead.next() != Some(':') {
+                break;
+            }
+            match lookahead.next() {
+                Some('h') => {
+                    if let Some(i) = text.rfind('/') {
+                        text.truncate(if i == 0 { 1 } else { i });
+                    }
+                }
+                Some('t') => {
+                    if let Some(i) = text.rfind('/') {
+                        text = text[i + 1..].to_owned();
+                    }
+                }
+                Some('r') => {
+                    if let Some(i) = text.rfind('.') {
+                        if !text[i..].contains('/') {
+                            text.truncate(i);
+                        }
+                    }
+                }
+                Some('e') => {
+                    if let Some(i) = text.rfind('.') {
+                        if !text[i..].contains('/') {
+                            text = text[i + 1..].to_owned();
+                        }
+                    }
+                }
+                Some('q') => text = format!("'{}'", text.replace('\'', "'\\''")),
+                Some('p') => *print_only = true,
+                Some('s') => {
+                    let (old, new) = take_substitution(&mut lookahead)?;
+                    text = substitute(&text, &old, &new, false)?;
+                }
+                Some('g') if lookahead.peek() == Some(&'s') => {
+                    lookahead.next();
+                    let (old, new) = take_substitution(&mut lookahead)?;
+                    text = substitute(&text, &old, &new, true)?;
+                }
+                _ => break,
+            }
+            *chars = lookahead;
+        }
+        Ok(text)
+    }
+
+    // Parses a word designator into an inclusive range of word indices
+    fn word_range(
+        &self,
+        chars: &mut Peekable<Chars>,
+        words: &[String],
+        event: &Event,
+    ) -> Result<(usize, usize), String> {
+        let last = words.len().saturating_sub(1);
+        let first = match chars.peek().cloned() {
+            Some('^') => {
+                chars.next();
+                1
+            }
+            Some('$') => {
+                chars.next();
+                last
+            }
+            Some('*') => {
+                chars.next();
+                return Ok((1, last));
+            }
+            Some('%') => {
+                chars.next();
+                let search = event
+                    .search
+                    .as_ref()
+                    .ok_or_else(|| "%: bad word specifier".to_owned())?;
+                return words
+                    .iter()
+                    .position(|w| w.contains(search.as_str()))
+                    .map(|i| (i, i))
+                    .ok_or_else(|| "%: bad word specifier".to_owned());
+            }
+            // x-y with x omitted means 0-y
+            Some('-') => 0,
+            _ => take_number(chars).unwrap_or(0),
+        };
+        match chars.peek().cloned() {
+            Some('*') => {
+                chars.next();
+                Ok((first, last))
+            }
+            Some('-') => {
+                chars.next();
+                match chars.peek().cloned() {
+                    Some('$') => {
+                        chars.next();
+                        Ok((first, last))
+                    }
+                    Some(c) if c.is_ascii_digit() => Ok((first, take_number(chars).unwrap_or(0))),
+                    // x- leaves out the last word
+                    _ => Ok((first, last.saturating_sub(1))),
+                }
+            }
+            _ => Ok((first, first)),
+        }
+    }
+
+    // csh-style history expansion of a line, before it is parsed
+    pub fn expand_history(&self, line: &str) -> Result<Expansion, String> {
+        let mut expansion = Expansion {
+            line: String::new(),
+            changed: false,
+            print_only: false,
+        };
+
+        // ^old^new^ is short for !!:s/old/new/
+        if line.starts_with('^') {
+            let mut chars = line.chars().peekable();
+            let (old, new) = take_substitution(&mut chars)?;
+            let previous = self.history
+                .tail()
+                .map(|e| e.command.clone())
+                .ok_or_else(|| "^: event not found".to_owned())?;
+            expansion.line = substitute(&previous, &old, &new, false)?;
+            expansion.line.extend(chars);
+            expansion.changed = true;
+            return Ok(expansion);
+        }
+
+        let mut chars = line.chars().peekable();
+        let mut in_single_quotes = false;
+        let mut in_double_quotes = false;
+        let mut previous = ['\0', '\0'];
+        while let Some(c) = chars.next() {
+            match c {
+                '\'' if !in_double_quotes => in_single_quotes = !in_single_quotes,
+                '"' if !in_single_quotes => in_double_quotes = !in_double_quotes,
+                '\\' if !in_single_quotes => {
+                    expansion.line.push(c);
+                    if let Some(next) = chars.next() {
+                        expansion.line.push(next);
+                    }
+                    previous = ['\\', '\\'];
+                    continue;
+                }
+                '!' if !in_single_quotes => {
+                    let literal = match chars.peek() {
+                        None => true,
+                        Some(&next) => {
+                            next.is_whitespace() || next == '=' || next == '(' ||
+                                (next == '"' && in_double_quotes)
+                        }
+                    };
+                    // $! and ${!name} are parameter expansions
+                    if !literal && previous[1] != '$' && previous != ['$', '{'] {
+                        let text = self.expand_reference(&mut chars, &mut expansion.print_only)?;
+                        expansion.line.push_str(&text);
+                        expansion.changed = true;
+                        previous = ['\0', '\0'];
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+            expansion.line.push(c);
+            previous = [previous[1], c];
+        }
+        Ok(expansion)
+    }
+}
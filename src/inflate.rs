@@ -0,0 +1,205 @@
+extern crate std;
+
+// Decoder for zlib streams (RFC 1950 and 1951), enough to read git objects
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115,
+    131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// Order in which the code length code lengths of a dynamic block are stored
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+struct Bits<'a> {
+    data: &'a [u8],
+    position: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn bits(&mut self, n: u32) -> Option<u32> {
+        while self.count < n {
+            let byte = *self.data.get(self.position)?;
+            self.position += 1;
+            self.buffer |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1u64 << n) - 1) as u32;
+        self.buffer >>= n;
+        self.count -= n;
+        Some(value)
+    }
+
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+// Canonical Huffman code, stored as the number of codes of each length and the symbols
+// ordered by code
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for i in 1..16 {
+            offsets[i] = offsets[i - 1] + counts[i - 1];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Option<u16> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for length in 1..16 {
+            code |= bits.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - count < first {
+                return self.symbols.get((index + code - first) as usize).cloned();
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        None
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_codes(bits: &mut Bits) -> Option<(Huffman, Huffman)> {
+    let literals = bits.bits(5)? as usize + 257;
+    let distances = bits.bits(5)? as usize + 1;
+    let code_lengths = bits.bits(4)? as usize + 4;
+    let mut lengths = [0u8; 19];
+    for &i in CODE_LENGTH_ORDER.iter().take(code_lengths) {
+        lengths[i] = bits.bits(3)? as u8;
+    }
+    let code = Huffman::new(&lengths);
+
+    let mut lengths = Vec::with_capacity(literals + distances);
+    while lengths.len() < literals + distances {
+        let symbol = code.decode(bits)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last()?, 3 + bits.bits(2)?),
+            17 => (0, 3 + bits.bits(3)?),
+            _ => (0, 11 + bits.bits(7)?),
+        };
+        for _ in 0..repeat {
+            lengths.push(value);
+        }
+    }
+    if lengths.len() != literals + distances {
+        return None;
+    }
+    Some((Huffman::new(&lengths[..literals]), Huffman::new(&lengths[literals..])))
+}
+
+// Decompresses a zlib stream, giving up if the output would grow past `limit` bytes
+pub fn zlib_decompress(data: &[u8], limit: usize) -> Option<Vec<u8>> {
+    // Compression method 8 and no preset dictionary
+    if data.len() < 2 || data[0] & 0x0f != 8 || data[1] & 0x20 != 0 {
+        return None;
+    }
+    let mut bits = Bits {
+        data,
+        position: 2,
+        buffer: 0,
+        count: 0,
+    };
+    let mut output = Vec::new();
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => {
+                bits.align();
+                let header = data.get(bits.position..bits.position + 4)?;
+                let length = u16::from_le_bytes([header[0], header[1]]) as usize;
+                bits.position += 4;
+                output.extend_from_slice(data.get(bits.position..bits.position + length)?);
+                bits.position += length;
+            }
+            kind @ 1..=2 => {
+                let (literal, distance) = if kind == 1 {
+                    fixed_codes()
+                } else {
+                    dynamic_codes(&mut bits)?
+                };
+                loop {
+                    let symbol = literal.decode(&mut bits)? as usize;
+                    if symbol < 256 {
+                        output.push(symbol as u8);
+                    } else if symbol == 256 {
+                        break;
+                    } else {
+                        let i = symbol - 257;
+                        let length = *LENGTH_BASE.get(i)? as usize +
+                            bits.bits(LENGTH_EXTRA[i] as u32)? as usize;
+                        let i = distance.decode(&mut bits)? as usize;
+                        let back = *DISTANCE_BASE.get(i)? as usize +
+                            bits.bits(DISTANCE_EXTRA[i] as u32)? as usize;
+                        if back > output.len() {
+                            return None;
+                        }
+                        let start = output.len() - back;
+                        for j in 0..length {
+                            output.push(output[start + j]);
+                        }
+                    }
+                    if output.len() > limit {
+                        return None;
+                    }
+                }
+            }
+            _ => return None,
+        }
+        if output.len() > limit {
+            return None;
+        }
+        if last {
+            return Some(output);
+        }
+    }
+}
@@ -0,0 +1,285 @@
+extern crate std;
+
+use std::fmt;
+
+// JSON, which structured pipelines pass between commands. Objects keep their keys in the order
+// they were written, so columns come out the way they went in.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match *self {
+            Value::Object(ref fields) => fields.iter().find(|f| f.0 == key).map(|f| &f.1),
+            _ => None,
+        }
+    }
+
+    // The value as text, without quotes around strings
+    pub fn text(&self) -> String {
+        match *self {
+            Value::String(ref text) => text.clone(),
+            _ => self.to_string(),
+        }
+    }
+
+    // Indented over several lines, for to json
+    pub fn pretty(&self) -> String {
+        let mut output = String::new();
+        self.write_pretty(&mut output, 0);
+        output
+    }
+
+    fn write_pretty(&self, output: &mut String, depth: usize) {
+        let indent = "  ".repeat(depth + 1);
+        match *self {
+            Value::Array(ref values) if !values.is_empty() => {
+                output.push_str("[\n");
+                for (i, value) in values.iter().enumerate() {
+                    output.push_str(&indent);
+                    value.write_pretty(output, depth + 1);
+                    output.push_str(if i + 1 < values.len() { ",\n" } else { "\n" });
+                }
+                output.push_str(&"  ".repeat(depth));
+                output.push(']');
+            }
+            Value::Object(ref fields) if !fields.is_empty() => {
+                output.push_str("{\n");
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    output.push_str(&indent);
+                    output.push_str(&quote(key));
+                    output.push_str(": ");
+                    value.write_pretty(output, depth + 1);
+                    output.push_str(if i + 1 < fields.len() { ",\n" } else { "\n" });
+                }
+                output.push_str(&"  ".repeat(depth));
+                output.push('}');
+            }
+            _ => output.push_str(&self.to_string()),
+        }
+    }
+}
+
+// Compact, on one line
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Number(number) if number.is_finite() => write!(f, "{}", number),
+            Value::Number(_) => write!(f, "null"),
+            Value::String(ref text) => write!(f, "{}", quote(text)),
+            Value::Array(ref values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    write!(f, "{}{}", if i > 0 { "," } else { "" }, value)?;
+                }
+                write!(f, "]")
+            }
+            Value::Object(ref fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    write!(f, "{}{}:{}", if i > 0 { "," } else { "" }, quote(key), value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+pub fn parse(text: &str) -> Result<Value, String> {
+    let mut parser = Parser {
+        chars: text.chars().collect(),
+        position: 0,
+    };
+    let value = parser.value()?;
+    parser.skip_space();
+    if parser.position < parser.chars.len() {
+        return Err(parser.error("unexpected text after the value"));
+    }
+    Ok(value)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Parser {
+    fn error(&self, message: &str) -> String {
+        format!("{} at character {}", message, self.position + 1)
+    }
+
+    fn skip_space(&mut self) {
+        while self.chars.get(self.position).is_some_and(|c| c.is_whitespace()) {
+            self.position += 1;
+        }
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.get(self.position).cloned();
+        self.position += 1;
+        c
+    }
+
+    fn expect(&mut self, word: &str, value: Value) -> Result<Value, String> {
+        for expected in word.chars() {
+            if self.next() != Some(expected) {
+                self.position -= 1;
+                return Err(self.error("invalid value"));
+            }
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_space();
+        match self.chars.get(self.position) {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => self.string().map(Value::String),
+            Some('t') => self.expect("true", Value::Bool(true)),
+            Some('f') => self.expect("false", Value::Bool(false)),
+            Some('n') => self.expect("null", Value::Null),
+            Some(c) if *c == '-' || c.is_ascii_digit() => self.number(),
+            Some(_) => Err(self.error("invalid value")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, String> {
+        self.position += 1;
+        let mut fields = Vec::new();
+        self.skip_space();
+        if self.chars.get(self.position) == Some(&'}') {
+            self.position += 1;
+            return Ok(Value::Object(fields));
+        }
+        loop {
+            self.skip_space();
+            if self.chars.get(self.position) != Some(&'"') {
+                return Err(self.error("expected a key"));
+            }
+            let key = self.string()?;
+            self.skip_space();
+            if self.next() != Some(':') {
+                self.position -= 1;
+                return Err(self.error("expected :"));
+            }
+            let value = self.value()?;
+            fields.push((key, value));
+            self.skip_space();
+            match self.next() {
+                Some(',') => {}
+                Some('}') => return Ok(Value::Object(fields)),
+                _ => {
+                    self.position -= 1;
+                    return Err(self.error("expected , or }"));
+                }
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.position += 1;
+        let mut values = Vec::new();
+        self.skip_space();
+        if self.chars.get(self.position) == Some(&']') {
+            self.position += 1;
+            return Ok(Value::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            self.skip_space();
+            match self.next() {
+                Some(',') => {}
+                Some(']') => return Ok(Value::Array(values)),
+                _ => {
+                    self.position -= 1;
+                    return Err(self.error("expected , or ]"));
+                }
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.position += 1;
+        let mut text = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(text),
+                Some('\\') => {
+                    let c = match self.next() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('u') => self.unicode_escape()?,
+                        Some(c) if "\"\\/".contains(c) => c,
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    text.push(c);
+                }
+                Some(c) => text.push(c),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    // \uXXXX, where characters outside the basic plane come as two of them
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.hex()?;
+        let code = if (0xD800..0xDC00).contains(&high) {
+            if self.next() != Some('\\') || self.next() != Some('u') {
+                return Err(self.error("invalid surrogate pair"));
+            }
+            let low = self.hex()?;
+            0x10000 + ((high - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF)
+        } else {
+            high
+        };
+        std::char::from_u32(code).ok_or_else(|| self.error("invalid character"))
+    }
+
+    fn hex(&mut self) -> Result<u32, String> {
+        let digits: String = (0..4).filter_map(|_| self.next()).collect();
+        u32::from_str_radix(&digits, 16).map_err(|_| self.error("invalid \\u escape"))
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.position;
+        while self.chars.get(self.position).is_some_and(|c| c.is_ascii_digit() || "+-.eE".contains(*c)) {
+            self.position += 1;
+        }
+        let text: String = self.chars[start..self.position].iter().collect();
+        text.parse().map(Value::Number).map_err(|_| {
+            self.position = start;
+            self.error("invalid number")
+        })
+    }
+}
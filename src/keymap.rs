@@ -0,0 +1,238 @@
+extern crate std;
+
+use std::collections::HashMap;
+use std::io::Write;
+use alias::quote;
+use exec;
+use error::Result;
+use state::ShellState;
+
+// Something the line editor does when a key is pressed
+#[derive(Clone, Debug, PartialEq)]
+pub enum Widget {
+    AcceptLine,
+    Interrupt,
+    DeleteCharOrExit,
+    AcceptSuggestion,
+    BackwardChar,
+    ForwardChar,
+    UpLineOrHistory,
+    DownLineOrHistory,
+    BeginningOfLine,
+    EndOfLine,
+    DeleteChar,
+    BackwardDeleteChar,
+    SearchBackward,
+    SearchForward,
+    KillLine,
+    BackwardKillLine,
+    BackwardKillWord,
+    ClearScreen,
+    // Types the text, as bind -s sets up
+    Insert(String),
+}
+
+const WIDGETS: &[(&str, Widget)] = &[
+    ("accept-line", Widget::AcceptLine),
+    ("interrupt", Widget::Interrupt),
+    ("delete-char-or-exit", Widget::DeleteCharOrExit),
+    ("accept-suggestion", Widget::AcceptSuggestion),
+    ("backward-char", Widget::BackwardChar),
+    ("forward-char", Widget::ForwardChar),
+    ("up-line-or-history", Widget::UpLineOrHistory),
+    ("down-line-or-history", Widget::DownLineOrHistory),
+    ("beginning-of-line", Widget::BeginningOfLine),
+    ("end-of-line", Widget::EndOfLine),
+    ("delete-char", Widget::DeleteChar),
+    ("backward-delete-char", Widget::BackwardDeleteChar),
+    ("history-incremental-search-backward", Widget::SearchBackward),
+    ("history-incremental-search-forward", Widget::SearchForward),
+    ("kill-line", Widget::KillLine),
+    ("backward-kill-line", Widget::BackwardKillLine),
+    ("backward-kill-word", Widget::BackwardKillWord),
+    ("clear-screen", Widget::ClearScreen),
+];
+
+// Key sequences, as the terminal sends them, and what they do
+pub type Keymap = HashMap<Vec<u8>, Widget>;
+
+pub fn default_keymap() -> Keymap {
+    let keys: &[(&[u8], Widget)] = &[
+        (b"\r", Widget::AcceptLine),
+        (b"\x03", Widget::Interrupt),
+        (b"\x04", Widget::DeleteCharOrExit),
+        (b"\t", Widget::AcceptSuggestion),
+        (b"\x12", Widget::SearchBackward),
+        (b"\x13", Widget::SearchForward),
+        (b"\x7f", Widget::BackwardDeleteChar),
+        (b"\x08", Widget::BackwardDeleteChar),
+        (b"\x01", Widget::BeginningOfLine),
+        (b"\x05", Widget::EndOfLine),
+        (b"\x02", Widget::BackwardChar),
+        (b"\x06", Widget::ForwardChar),
+        (b"\x0b", Widget::KillLine),
+        (b"\x15", Widget::BackwardKillLine),
+        (b"\x17", Widget::BackwardKillWord),
+        (b"\x0c", Widget::ClearScreen),
+        (b"\x1b[A", Widget::UpLineOrHistory),
+        (b"\x1b[B", Widget::DownLineOrHistory),
+        (b"\x1b[C", Widget::ForwardChar),
+        (b"\x1b[D", Widget::BackwardChar),
+        (b"\x1b[H", Widget::BeginningOfLine),
+        (b"\x1b[F", Widget::EndOfLine),
+        (b"\x1b[1~", Widget::BeginningOfLine),
+        (b"\x1b[7~", Widget::BeginningOfLine),
+        (b"\x1b[3~", Widget::DeleteChar),
+        (b"\x1b[4~", Widget::EndOfLine),
+        (b"\x1b[8~", Widget::EndOfLine),
+    ];
+    keys.iter().map(|&(key, ref widget)| (key.to_vec(), widget.clone())).collect()
+}
+
+// Reads key notation: ^X for control keys, \e or \M-x for escape sequences, \C-x, and the
+// usual backslash escapes
+pub fn parse_key(notation: &str) -> Option<Vec<u8>> {
+    let mut key = Vec::new();
+    let mut chars = notation.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '^' => {
+                let c = chars.next()?;
+                key.push(if c == '?' { 0x7f } else { c.to_ascii_uppercase() as u8 ^ 0x40 });
+            }
+            '\\' => {
+                match chars.next()? {
+                    'e' | 'E' => key.push(0x1b),
+                    't' => key.push(b'\t'),
+                    'n' => key.push(b'\n'),
+                    'r' => key.push(b'\r'),
+                    'C' if chars.next() == Some('-') => {
+                        key.push(chars.next()?.to_ascii_uppercase() as u8 ^ 0x40)
+                    }
+                    'M' if chars.next() == Some('-') => {
+                        key.push(0x1b);
+                        key.extend(chars.next()?.to_string().bytes());
+                    }
+                    c => key.extend(c.to_string().bytes()),
+                }
+            }
+            c => key.extend(c.to_string().bytes()),
+        }
+    }
+    if key.is_empty() { None } else { Some(key) }
+}
+
+// The opposite of parse_key, for listing bindings
+pub fn key_name(key: &[u8]) -> String {
+    let mut name = String::new();
+    for &b in key {
+        match b {
+            0x1b => name.push_str("\\e"),
+            0x7f => name.push_str("^?"),
+            b'^' | b'\\' => {
+                name.push('\\');
+                name.push(b as char);
+            }
+            b if b < 0x20 => {
+                name.push('^');
+                name.push((b ^ 0x40) as char);
+            }
+            b => name.push(b as char),
+        }
+    }
+    name
+}
+
+pub fn widget_name(widget: &Widget) -> Option<&'static str> {
+    WIDGETS.iter().find(|&(_, w)| w == widget).map(|&(name, _)| name)
+}
+
+impl ShellState {
+    // bind [-l] [-r key] [-s key text] [key [widget]]
+    pub fn bind(&mut self, args: &[String]) -> Result<i32> {
+        let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+        let mut out = exec::output();
+        Ok(match args.as_slice() {
+            [] => {
+                let mut bindings: Vec<(String, &Widget)> =
+                    self.keymap.iter().map(|(key, widget)| (key_name(key), widget)).collect();
+                bindings.sort_by(|a, b| a.0.cmp(&b.0));
+                for (key, widget) in bindings {
+                    match *widget {
+                        Widget::Insert(ref text) => writeln!(out, "bind -s {} {}", quote(&key), quote(text))?,
+                        ref widget => {
+                            writeln!(out, "bind {} {}", quote(&key), widget_name(widget).unwrap_or(""))?
+                        }
+                    }
+                }
+                0
+            }
+            ["-l"] => {
+                for &(name, _) in WIDGETS {
+                    writeln!(out, "{}", name)?;
+                }
+                0
+            }
+            ["-r", key] => {
+                match parse_key(key) {
+                    Some(key) => {
+                        self.keymap.remove(&key);
+                        0
+                    }
+                    None => self.bad_key(key),
+                }
+            }
+            ["-s", key, text] => {
+                match parse_key(key) {
+                    Some(key) => {
+                        self.keymap.insert(key, Widget::Insert(text.to_string()));
+                        0
+                    }
+                    None => self.bad_key(key),
+                }
+            }
+            [key] => {
+                let widget = parse_key(key).and_then(|k| self.keymap.get(&k));
+                match widget {
+                    Some(Widget::Insert(text)) => {
+                        writeln!(out, "bind -s {} {}", quote(key), quote(text))?
+                    }
+                    Some(widget) => writeln!(
+                        out,
+                        "bind {} {}",
+                        quote(key),
+                        widget_name(widget).unwrap_or("")
+                    )?,
+                    None => {
+                        self.report(&format!("bind: {}: not bound", key));
+                        return Ok(1);
+                    }
+                }
+                0
+            }
+            [key, name] => {
+                let widget = WIDGETS.iter().find(|&&(n, _)| n == *name).map(|(_, w)| w.clone());
+                match (parse_key(key), widget) {
+                    (Some(key), Some(widget)) => {
+                        self.keymap.insert(key, widget);
+                        0
+                    }
+                    (None, _) => self.bad_key(key),
+                    (_, None) => {
+                        self.report(&format!("bind: {}: no such widget", name));
+                        1
+                    }
+                }
+            }
+            _ => {
+                self.report("bind: usage: bind [-l] [-r key] [-s key text] [key [widget]]");
+                2
+            }
+        })
+    }
+
+    fn bad_key(&self, key: &str) -> i32 {
+        self.report(&format!("bind: {}: bad key", key));
+        1
+    }
+}
@@ -1,8 +1,34 @@
 pub mod state;
+pub mod prompt;
+pub mod render;
+pub mod highlight;
+pub mod git;
+pub mod inflate;
+pub mod sha1;
 pub mod cd;
 pub mod echo;
 pub mod circular_buffer;
 pub mod history;
+pub mod history_expansion;
+pub mod history_builtin;
+pub mod redact;
 pub mod run_command;
 pub mod envars;
 pub mod read_config;
+pub mod parser;
+pub mod expand;
+pub mod exec;
+pub mod alias;
+pub mod variables;
+pub mod keymap;
+pub mod completion;
+pub mod options;
+pub mod conditional;
+pub mod arrays;
+pub mod json;
+pub mod structured;
+pub mod expression;
+pub mod embed;
+pub mod error;
+
+pub use embed::{Output, Shell};
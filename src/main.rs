@@ -1,67 +1,316 @@
 extern crate hostname;
 extern crate glob;
+extern crate libc;
 extern crate rust_shell as shell;
 
+use std::env;
+use std::ffi::CStr;
+use std::fs;
 use std::io;
 use std::io::Write;
-use std::str;
+use std::path::Path;
+use std::process;
+use std::time::{Duration, Instant};
 use hostname::get_hostname;
 use shell::state::ShellState;
-use shell::circular_buffer::CircularBuffer;
 use shell::envars::Envars;
+use shell::options::Options;
+use shell::parser;
+
+const USAGE: &str = "\
+usage: rsh [options] [script [argument ...]]
+       rsh [options] -c command [name [argument ...]]
+       rsh [options] -s [argument ...]
+
+  -c          run the command given as the first argument
+  -s          read commands from standard input
+  -i          run interactively, even when not reading from a terminal
+  -l, --login run as a login shell, with the profile scripts
+  -e          exit as soon as a command fails
+  -x          print commands before running them
+  -o option   turn on an option by name, as with set -o
+  -C -f -n -u -v
+              turn on the options of those letters, as with set
+  --norc      don't run the rshrc scripts
+  --noprofile don't run the profile scripts of a login shell
+  --rcfile f  run f instead of the user's rshrc
+  --version   print the version and exit
+  --help      print this message and exit
+";
+
+// What the command line asks for
+#[derive(Default)]
+struct Invocation {
+    command: bool,
+    stdin: bool,
+    interactive: bool,
+    login: bool,
+    norc: bool,
+    noprofile: bool,
+    rcfile: Option<String>,
+    options: Options,
+    // What follows the options: the command or script, then its name and arguments
+    operands: Vec<String>,
+}
+
+// Reads the options, which come before the first operand and may be combined as in -ex. A + in
+// place of the - turns -e and -x off again.
+fn parse_args(mut args: Vec<String>) -> Invocation {
+    // login starts login shells with a - in front of their name
+    let mut invocation = Invocation {
+        login: args.first().is_some_and(|name| name.starts_with('-')),
+        ..Default::default()
+    };
+    let mut i = 1;
+    while i < args.len() {
+        let arg = args[i].clone();
+        match arg.as_str() {
+            "--" => {
+                i += 1;
+                break;
+            }
+            "--login" => invocation.login = true,
+            "--norc" => invocation.norc = true,
+            "--noprofile" => invocation.noprofile = true,
+            "--rcfile" => {
+                i += 1;
+                match args.get(i) {
+                    Some(file) => invocation.rcfile = Some(file.clone()),
+                    None => usage_error("--rcfile: option requires an argument"),
+                }
+            }
+            "--version" => {
+                println!("rsh {}", env!("CARGO_PKG_VERSION"));
+                process::exit(0);
+            }
+            "--help" => {
+                print!("{}", USAGE);
+                process::exit(0);
+            }
+            _ if arg.starts_with("--") => usage_error(&format!("{}: invalid option", arg)),
+            _ if arg.len() > 1 && (arg.starts_with('-') || arg.starts_with('+')) => {
+                let on = arg.starts_with('-');
+                for flag in arg[1..].chars() {
+                    match flag {
+                        'o' => {
+                            i += 1;
+                            let name = args.get(i).cloned().unwrap_or_default();
+                            if !invocation.options.set(&name, on) {
+                                usage_error(&format!("{}: invalid option name", name));
+                            }
+                        }
+                        'c' if on => invocation.command = true,
+                        's' if on => invocation.stdin = true,
+                        'i' if on => invocation.interactive = true,
+                        'l' if on => invocation.login = true,
+                        _ if invocation.options.set_flag(flag, on) => {}
+                        _ => usage_error(&format!("{}{}: invalid option", &arg[..1], flag)),
+                    }
+                }
+            }
+            _ => break,
+        }
+        i += 1;
+    }
+    invocation.operands = args.split_off(i.min(args.len()));
+    if invocation.command && invocation.operands.is_empty() {
+        usage_error("-c: option requires an argument");
+    }
+    invocation
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("rsh: {}", message);
+    eprint!("{}", USAGE);
+    process::exit(2)
+}
 
 fn main() {
-    // TODO: Factor out more helper functions
-    // TODO: Semicolons between commands on a single line
-    // TODO: Pipes and output redirection
-    // TODO: Syntax highlighting
-    let mut shell = ShellState {
-        variables: Envars::load(),
-        history: CircularBuffer::new(10000),
+    let mut invocation = parse_args(env::args().collect());
+    let mut shell = ShellState::new(Envars::load());
+    set_defaults(&mut shell);
+    shell.options = invocation.options.clone();
+
+    // The first operand is the command or script, unless commands come from standard input
+    let mut operands = invocation.operands.drain(..);
+    let source = if invocation.command || !invocation.stdin {
+        operands.next()
+    } else {
+        None
     };
+    // -c takes $0 from the operand after the command, a script is its own $0
+    if invocation.command {
+        if let Some(name) = operands.next() {
+            shell.name = name;
+        }
+    } else if let Some(ref script) = source {
+        shell.name = script.clone();
+    }
+    shell.arguments = operands.collect();
+    shell.interactive = invocation.interactive ||
+        (source.is_none() && unsafe { libc::isatty(0) == 1 && libc::isatty(2) == 1 });
+    if shell.interactive {
+        shell::exec::ignore_interrupts();
+    }
 
-    // login only gives me $HOME, $SHELL, $PATH, $LOGNAME, and $MAIL, so provide defaults here
-    shell.variables.insert(
-        "PROMPT",
-        "{BOLD}{WHITE}╭{RED} ➜ {GREEN}{$USER}@{$HOSTNAME}:{CYAN}{$PWD}{WHITE}\n╰ ➤ ",
-    );
-    shell.variables.insert("HISTSIZE", "1000");
-    shell.variables.insert(
-        "HOSTNAME",
-        get_hostname().unwrap().as_ref(),
-    );
-    let username = shell.variables.get("LOGNAME").unwrap().clone();
-    shell.variables.insert(
-        "USER",
-        username.to_string_lossy().as_ref(),
-    );
-    let home = shell.variables.get("HOME").unwrap().clone();
-    shell.variables.insert(
-        "PWD",
-        home.to_string_lossy().as_ref(),
-    );
+    shell.login = invocation.login;
+    if invocation.login && !invocation.noprofile {
+        shell.read_profile();
+    }
+    if shell.interactive && !invocation.norc {
+        shell.read_config(invocation.rcfile.as_ref().map(Path::new));
+    }
 
-    shell.read_config();
+    let status = match source {
+        Some(ref command) if invocation.command => shell.run_script(command, None),
+        Some(ref script) => {
+            match fs::read_to_string(script) {
+                Ok(contents) => shell.run_script(&contents, Some(script)),
+                Err(e) => {
+                    eprintln!("rsh: {}: {}", script, shell::exec::io_message(&e));
+                    127
+                }
+            }
+        }
+        None if shell.interactive => interactive(&mut shell),
+        None => run_stdin(&mut shell),
+    };
+    shell.exit(status)
+}
 
+// Reads and runs commands from the terminal until the shell exits
+fn interactive(shell: &mut ShellState) -> ! {
     shell.load_history();
+    // Without a terminal to edit lines on, as with -i and a pipe, commands are read as they come
+    if unsafe { libc::isatty(0) } != 1 {
+        let status = run_stdin(shell);
+        shell.exit(status);
+    }
 
     let mut input_buffer = String::new();
     loop {
-        shell.prompt_read(&mut input_buffer);
-        let mut args = input_buffer.split_whitespace();
-        let cmd = args.next().unwrap_or("").to_owned();
-
-        match cmd.as_ref() {
-            "" => print!(""),
-            "cd" => shell.cd(args),
-            "echo" => shell.echo(args),
-            "exit" => {
-                io::stdout().flush().unwrap();
-                shell.save_history();
-                std::process::exit(0)
+        shell.reap_jobs();
+        shell.sync_history();
+        if let Err(e) = shell.prompt_read(&mut input_buffer) {
+            // The end of the input, as when the terminal has closed, ends the shell like ^D
+            if e.kind() != io::ErrorKind::UnexpectedEof {
+                shell.report(&format!("terminal: {}", shell::exec::io_message(&e)));
+            }
+            let status = shell.last_status;
+            shell.exit(status);
+        }
+        let expansion = match shell.expand_history(&input_buffer) {
+            Ok(expansion) => expansion,
+            Err(message) => {
+                shell.report(&message);
+                continue;
             }
-            "history" => shell.print_history(),
-            _ => shell.run_command(&cmd, args),
         };
+        if expansion.changed {
+            let _ = writeln!(io::stdout(), "{}", expansion.line);
+        }
+        let entry = shell.history_entry(&expansion.line);
+        if expansion.print_only {
+            shell.record_history(entry, 0, Duration::from_secs(0));
+            continue;
+        }
+
+        let started = Instant::now();
+        let status = shell.run_line(&expansion.line);
+        shell.last_status = status;
+        shell.last_duration = started.elapsed();
+        if !std::mem::take(&mut shell.history_rerun) {
+            shell.record_history(entry, status, shell.last_duration);
+        }
     }
 }
+
+// Runs commands from standard input, reading it a line at a time so that whatever the commands
+// read is left for them
+fn run_stdin(shell: &mut ShellState) -> i32 {
+    let mut source = String::new();
+    let mut line = Vec::new();
+    loop {
+        let mut byte = 0u8;
+        let read = unsafe { libc::read(0, &mut byte as *mut u8 as *mut libc::c_void, 1) };
+        // A signal like SIGCHLD or SIGWINCH can interrupt the read before anything arrives
+        let error = if read < 0 { Some(io::Error::last_os_error()) } else { None };
+        if error.as_ref().is_some_and(|e| e.kind() == io::ErrorKind::Interrupted) {
+            continue;
+        }
+        if read == 1 && byte != b'\n' {
+            line.push(byte);
+            continue;
+        }
+        if read == 1 {
+            line.push(byte);
+        }
+        source.push_str(&String::from_utf8_lossy(&line));
+        line.clear();
+        // Commands that go on over several lines are run once they are complete
+        if (read < 1 || parser::is_complete(&source)) && !source.is_empty() {
+            shell.run_script(&source, None);
+            shell.flow = None;
+            source.clear();
+        }
+        if let Some(error) = error {
+            shell.report(&format!("stdin: {}", shell::exec::io_message(&error)));
+            return 1;
+        }
+        if read < 1 {
+            return shell.last_status;
+        }
+    }
+}
+
+fn set_defaults(shell: &mut ShellState) {
+    // login only gives me $HOME, $SHELL, $PATH, $LOGNAME, and $MAIL, and other ways of starting
+    // the shell may not even give those, so provide defaults here
+    shell.variables.insert(
+        "PROMPT",
+        "{BOLD}{WHITE}╭{RED} ➜ {GREEN}{$USER}@{$HOSTNAME}:{CYAN}{CWD}\
+         {?status: {RED}[{STATUS}]}{?duration: {YELLOW}{DURATION}}{WHITE}\n╰ ➤ ",
+    );
+    shell.variables.insert("PS2", "  ➤ ");
+    shell.variables.insert("HISTSIZE", "1000");
+    let hostname = get_hostname().unwrap_or_else(|| "localhost".to_owned());
+    shell.variables.insert("HOSTNAME", &hostname);
+    let (passwd_name, passwd_home) = passwd_entry();
+    if shell.variable("HOME").is_none() {
+        let home = passwd_home.unwrap_or_else(|| "/".to_owned());
+        shell.variables.insert("HOME", &home);
+    }
+    if shell.variable("USER").is_none() {
+        let username = shell.variable("LOGNAME").or(passwd_name).unwrap_or_default();
+        shell.variables.insert("USER", &username);
+    }
+    if shell.variable("PATH").is_none() {
+        shell.variables.insert("PATH", "/usr/local/bin:/usr/bin:/bin");
+    }
+    // PWD is kept when it still names the working directory, so paths through symlinks survive
+    let cwd = env::current_dir().ok();
+    let pwd_matches = shell.variable("PWD").is_some_and(|pwd| {
+        Path::new(&pwd).is_absolute() &&
+            Path::new(&pwd).canonicalize().ok() == cwd.as_ref().and_then(|c| c.canonicalize().ok())
+    });
+    if !pwd_matches {
+        let pwd = cwd.map(|c| c.to_string_lossy().into_owned())
+            .or_else(|| shell.variable("HOME"))
+            .unwrap_or_else(|| "/".to_owned());
+        shell.variables.insert("PWD", &pwd);
+    }
+}
+
+// The user's name and home directory from the password database
+fn passwd_entry() -> (Option<String>, Option<String>) {
+    let entry = unsafe { libc::getpwuid(libc::getuid()) };
+    if entry.is_null() {
+        return (None, None);
+    }
+    let field = |ptr: *const libc::c_char| if ptr.is_null() {
+        None
+    } else {
+        Some(unsafe { CStr::from_ptr(ptr) }.to_string_lossy().into_owned())
+    };
+    unsafe { (field((*entry).pw_name), field((*entry).pw_dir)) }
+}
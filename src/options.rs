@@ -0,0 +1,252 @@
+extern crate std;
+
+use std::io;
+use std::io::Write;
+use alias::quote;
+use exec;
+use error::Result;
+use state::ShellState;
+
+// Options that change how commands run, set with set and setopt or on the command line
+#[derive(Clone, Default)]
+pub struct Options {
+    // -e: exit as soon as a command fails
+    pub errexit: bool,
+    // -C: > doesn't overwrite files that exist, >| still does
+    pub noclobber: bool,
+    // -n: read commands without running them, to check a script's syntax
+    pub noexec: bool,
+    // -f: don't expand file name globs
+    pub noglob: bool,
+    // -u: expanding a variable that isn't set is an error
+    pub nounset: bool,
+    // A pipeline fails when any of its commands does, not just the last
+    pub pipefail: bool,
+    // ls and the other structured builtins pass tables along pipelines
+    pub structured: bool,
+    // -v: print commands as they are read
+    pub verbose: bool,
+    // -x: print each command before running it
+    pub xtrace: bool,
+}
+
+// Every option's name and the letter that also sets it, in the order $- lists them
+const OPTIONS: &[(&str, Option<char>)] = &[
+    ("errexit", Some('e')),
+    ("noglob", Some('f')),
+    ("noexec", Some('n')),
+    ("nounset", Some('u')),
+    ("verbose", Some('v')),
+    ("xtrace", Some('x')),
+    ("noclobber", Some('C')),
+    ("pipefail", None),
+    ("structured", None),
+];
+
+impl Options {
+    fn field(&mut self, name: &str) -> Option<&mut bool> {
+        Some(match name {
+            "errexit" => &mut self.errexit,
+            "noclobber" => &mut self.noclobber,
+            "noexec" => &mut self.noexec,
+            "noglob" => &mut self.noglob,
+            "nounset" => &mut self.nounset,
+            "pipefail" => &mut self.pipefail,
+            "structured" => &mut self.structured,
+            "verbose" => &mut self.verbose,
+            "xtrace" => &mut self.xtrace,
+            _ => return None,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> bool {
+        match name {
+            "errexit" => self.errexit,
+            "noclobber" => self.noclobber,
+            "noexec" => self.noexec,
+            "noglob" => self.noglob,
+            "nounset" => self.nounset,
+            "pipefail" => self.pipefail,
+            "structured" => self.structured,
+            "verbose" => self.verbose,
+            "xtrace" => self.xtrace,
+            _ => false,
+        }
+    }
+
+    // Sets an option by name, or gives false if there isn't one
+    pub fn set(&mut self, name: &str, on: bool) -> bool {
+        match self.field(name) {
+            Some(option) => {
+                *option = on;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Sets the option for a single-letter flag like -e, or gives false if there isn't one
+    pub fn set_flag(&mut self, flag: char, on: bool) -> bool {
+        match OPTIONS.iter().find(|&&(_, letter)| letter == Some(flag)) {
+            Some(&(name, _)) => self.set(name, on),
+            None => false,
+        }
+    }
+
+    // The letters of the options that are on
+    pub fn flags(&self) -> String {
+        OPTIONS.iter()
+            .filter(|&&(name, _)| self.get(name))
+            .filter_map(|&(_, letter)| letter)
+            .collect()
+    }
+}
+
+// Reads a zsh option name, where case and underscores don't matter and a no in front turns
+// the option off. Gives the option's name and whether it is being turned on.
+fn zsh_option(name: &str) -> Option<(&'static str, bool)> {
+    let name: String = name.chars().filter(|&c| c != '_').collect::<String>().to_lowercase();
+    let find = |name: &str| OPTIONS.iter().find(|&&(n, _)| n == name).map(|&(n, _)| n);
+    if let Some(option) = find(&name) {
+        return Some((option, true));
+    }
+    if let Some(option) = name.strip_prefix("no").and_then(find) {
+        return Some((option, false));
+    }
+    find(&format!("no{}", name)).map(|option| (option, false))
+}
+
+impl ShellState {
+    // set [-efnuvxC] [-o option] [+efnuvxC] [+o option] [--] [argument ...]
+    pub fn set(&mut self, args: &[String]) -> Result<i32> {
+        if args.is_empty() {
+            for name in self.variables.names() {
+                writeln!(
+                    exec::output(),
+                    "{}={}",
+                    name,
+                    quote(&self.variable(&name).unwrap_or_default())
+                )?;
+            }
+            return Ok(0);
+        }
+        let mut i = 0;
+        while i < args.len() {
+            let arg = &args[i];
+            if arg == "--" || arg == "-" {
+                i += 1;
+                break;
+            }
+            let on = arg.starts_with('-');
+            if arg.len() < 2 || !(on || arg.starts_with('+')) {
+                break;
+            }
+            for flag in arg[1..].chars() {
+                if flag == 'o' {
+                    i += 1;
+                    match args.get(i) {
+                        Some(name) => {
+                            if !self.options.set(name, on) {
+                                self.report(&format!("set: {}: invalid option name", name));
+                                return Ok(2);
+                            }
+                        }
+                        None => self.print_options(on)?,
+                    }
+                } else if !self.options.set_flag(flag, on) {
+                    self.report(&format!("set: {}{}: invalid option", &arg[..1], flag));
+                    return Ok(2);
+                }
+            }
+            i += 1;
+        }
+        // Anything after the options, or after --, replaces the positional parameters
+        if i < args.len() || args.get(i.wrapping_sub(1)).is_some_and(|a| a == "--") {
+            self.arguments = args[i..].to_vec();
+        }
+        Ok(0)
+    }
+
+    // set -o lists the options, set +o prints the commands that would set them again
+    fn print_options(&self, on: bool) -> Result<()> {
+        for &(name, _) in OPTIONS {
+            let set = self.options.get(name);
+            if on {
+                writeln!(
+                    exec::output(),
+                    "{:<15}{}",
+                    name,
+                    if set { "on" } else { "off" }
+                )?;
+            } else {
+                writeln!(
+                    exec::output(),
+                    "set {}o {}",
+                    if set { '-' } else { '+' },
+                    name
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    // setopt and unsetopt, which take zsh's option names and list the options that are on
+    pub fn setopt(&mut self, args: &[String], on: bool) -> Result<i32> {
+        if args.is_empty() {
+            for &(name, _) in OPTIONS {
+                if self.options.get(name) {
+                    writeln!(exec::output(), "{}", name)?;
+                }
+            }
+            return Ok(0);
+        }
+        let mut status = 0;
+        for arg in args {
+            match zsh_option(arg) {
+                Some((name, value)) => {
+                    self.options.set(name, value == on);
+                }
+                None => {
+                    let command = if on { "setopt" } else { "unsetopt" };
+                    self.report(&format!("{}: no such option: {}", command, arg));
+                    status = 1;
+                }
+            }
+        }
+        Ok(status)
+    }
+
+    // $-, the letters of the options that are on, and i when the shell is interactive
+    pub fn flags(&self) -> String {
+        let mut flags = self.options.flags();
+        if self.interactive {
+            flags.push('i');
+        }
+        flags
+    }
+
+    // Prints a command about to run for -x, after $PS4, with its words quoted where they need
+    // it
+    pub fn trace(&mut self, values: &[(String, String)], arguments: &[String]) {
+        if !self.options.xtrace {
+            return;
+        }
+        let prompt = match self.variable("PS4") {
+            Some(ps4) => self.expand_word(&ps4),
+            None => "+ ".to_owned(),
+        };
+        let mut words: Vec<String> = values.iter()
+            .map(|(name, value)| format!("{}={}", name, trace_word(value)))
+            .collect();
+        words.extend(arguments.iter().map(|a| trace_word(a)));
+        let _ = writeln!(io::stderr(), "{}{}", prompt, words.join(" "));
+    }
+}
+
+fn trace_word(word: &str) -> String {
+    if !word.is_empty() && word.chars().all(|c| c.is_alphanumeric() || "-_./=:,+@%".contains(c)) {
+        word.to_owned()
+    } else {
+        quote(word)
+    }
+}
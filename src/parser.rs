@@ -0,0 +1,1164 @@
+extern crate std;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use exec;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind {
+    Word,
+    Operator,
+    Newline,
+    Comment,
+    // The lines of a here-document, which come right after the word that ends them
+    HereDocument,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+    // Byte offsets into the input, so callers can map tokens back onto the line
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    // The input ended while something was still open; more lines may fix it
+    Incomplete(&'static str),
+    Unexpected(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::Incomplete(what) => write!(f, "unexpected end of file in {}", what),
+            ParseError::Unexpected(ref message) => write!(f, "{}", message),
+        }
+    }
+}
+
+// A redirection like 2>&1 or >>file, with the target still to be expanded. For << and <<-
+// the target is the delimiter as written, and the lines before it are the here-document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redirect {
+    pub fd: Option<i32>,
+    pub operator: String,
+    pub target: String,
+    pub here_document: Option<String>,
+}
+
+// Words are kept the way they were written, quotes and all, and only expanded when the command
+// runs
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Simple {
+        assignments: Vec<String>,
+        words: Vec<String>,
+        redirects: Vec<Redirect>,
+        line: usize,
+    },
+    Pipeline {
+        stages: Vec<Command>,
+        negated: bool,
+    },
+    And(Box<Command>, Box<Command>),
+    Or(Box<Command>, Box<Command>),
+    List(Vec<Command>),
+    // Along with the text of the command, for the job list
+    Background(Box<Command>, String),
+    If {
+        branches: Vec<(Command, Command)>,
+        otherwise: Option<Box<Command>>,
+    },
+    While {
+        condition: Box<Command>,
+        body: Box<Command>,
+        until: bool,
+    },
+    For {
+        variable: String,
+        words: Option<Vec<String>>,
+        body: Box<Command>,
+    },
+    Case {
+        word: String,
+        arms: Vec<(Vec<String>, Command)>,
+    },
+    // { ...; }, run in this shell
+    Group(Box<Command>),
+    // ( ... ), run in a copy of the shell
+    Subshell(Box<Command>),
+    // [[ expression ]], as its words and operators for the shell to evaluate
+    Conditional {
+        words: Vec<String>,
+        line: usize,
+    },
+    Redirected(Box<Command>, Vec<Redirect>),
+    // = expression, a line to work out and print
+    Expression {
+        text: String,
+        line: usize,
+    },
+    Function {
+        name: String,
+        body: Rc<Command>,
+    },
+}
+
+// How deeply blocks, subshells and function definitions can nest. Parsing and running them
+// recurse, so they also stop short of the end of the stack.
+const MAX_NESTING: usize = 1000;
+
+const OPERATORS: &[&str] = &[
+    "&&", "||", ";;", "<<-", "<<", ">>", "<&", ">&", ">|", "<>", "&>", "|", "&", ";", "<", ">",
+    "(", ")",
+];
+
+pub fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
+    match tokenize_partial(input) {
+        (tokens, None) => Ok(tokens),
+        (_, Some(error)) => Err(error),
+    }
+}
+
+// Splits as much of `input` into tokens as it can. Whatever is left open, like a quote that
+// never ends, becomes the last word, so the line editor can still color a line being typed.
+pub fn tokenize_partial(input: &str) -> (Vec<Token>, Option<ParseError>) {
+    let mut tokens = Vec::new();
+    let mut word_start: Option<usize> = None;
+    let error = split_tokens(input, &mut tokens, &mut word_start).err();
+    finish_word(input, &mut tokens, &mut word_start, input.len());
+    (tokens, error)
+}
+
+fn split_tokens(
+    input: &str,
+    tokens: &mut Vec<Token>,
+    word_start: &mut Option<usize>,
+) -> Result<(), ParseError> {
+    let bytes = input.as_bytes();
+    let mut i = 0;
+    // Where the tokens of the current line start
+    let mut line_start = 0;
+
+    while i < bytes.len() {
+        let c = bytes[i];
+        match c {
+            b' ' | b'\t' => {
+                finish_word(input, tokens, word_start, i);
+                i += 1;
+            }
+            b'\n' => {
+                finish_word(input, tokens, word_start, i);
+                tokens.push(Token {
+                    kind: TokenKind::Newline,
+                    text: "\n".to_owned(),
+                    start: i,
+                    end: i + 1,
+                });
+                i += 1;
+                // The here-documents the line started are on the lines after it
+                i = here_documents(input, i, tokens, line_start)?;
+                line_start = tokens.len();
+            }
+            b'#' if word_start.is_none() => {
+                let end = input[i..].find('\n').map(|n| i + n).unwrap_or(input.len());
+                tokens.push(Token {
+                    kind: TokenKind::Comment,
+                    text: input[i..end].to_owned(),
+                    start: i,
+                    end,
+                });
+                i = end;
+            }
+            b'\\' => {
+                if i + 1 >= bytes.len() {
+                    word_start.get_or_insert(i);
+                    return Err(ParseError::Incomplete("\\"));
+                }
+                if bytes[i + 1] == b'\n' {
+                    // Line continuation: ends nothing, the word carries on on the next line
+                    i += 2;
+                } else {
+                    word_start.get_or_insert(i);
+                    i += 1 + char_len(input, i + 1);
+                }
+            }
+            b'\'' | b'"' | b'`' => {
+                word_start.get_or_insert(i);
+                i = skip_quoted(input, i)?;
+            }
+            b'$' if bytes.get(i + 1) == Some(&b'(') || bytes.get(i + 1) == Some(&b'{') => {
+                word_start.get_or_insert(i);
+                i = skip_nested(input, i + 1)?;
+            }
+            // @( expression ), which like $( ) can have spaces and operators inside
+            b'@' if bytes.get(i + 1) == Some(&b'(') => {
+                word_start.get_or_insert(i);
+                i = skip_nested(input, i + 1)?;
+            }
+            // A line starting with = and a space is an expression, all the way to its end
+            b'=' if word_start.is_none() && tokens.len() == line_start &&
+                    matches!(bytes.get(i + 1), Some(b' ') | Some(b'\t')) => {
+                let end = input[i..].find('\n').map_or(input.len(), |n| i + n);
+                tokens.push(Token {
+                    kind: TokenKind::Word,
+                    text: input[i..end].to_owned(),
+                    start: i,
+                    end,
+                });
+                i = end;
+            }
+            // Process substitution is a word, not a redirection
+            b'<' | b'>' if bytes.get(i + 1) == Some(&b'(') => {
+                word_start.get_or_insert(i);
+                i = skip_nested(input, i + 1)?;
+            }
+            _ => {
+                if let Some(op) = OPERATORS.iter().find(|op| input[i..].starts_with(*op)) {
+                    finish_word(input, tokens, word_start, i);
+                    tokens.push(Token {
+                        kind: TokenKind::Operator,
+                        text: (*op).to_owned(),
+                        start: i,
+                        end: i + op.len(),
+                    });
+                    i += op.len();
+                } else {
+                    word_start.get_or_insert(i);
+                    i += char_len(input, i);
+                }
+            }
+        }
+    }
+    // A here-document started on the last line still needs its lines
+    finish_word(input, tokens, word_start, i);
+    here_documents(input, i, tokens, line_start).map(|_| ())
+}
+
+// Reads the bodies of the here-documents that the tokens from `line_start` on redirect from,
+// starting at `start`, and puts each after its delimiter. Returns where the input carries on.
+fn here_documents(
+    input: &str,
+    start: usize,
+    tokens: &mut Vec<Token>,
+    line_start: usize,
+) -> Result<usize, ParseError> {
+    let mut pending = Vec::new();
+    for i in line_start..tokens.len().saturating_sub(1) {
+        let (operator, word) = (&tokens[i], &tokens[i + 1]);
+        if operator.kind == TokenKind::Operator && operator.text.starts_with("<<") &&
+           word.kind == TokenKind::Word {
+            pending.push((i + 2, here_delimiter(&word.text), operator.text == "<<-"));
+        }
+    }
+    let mut bodies = Vec::new();
+    let mut i = start;
+    let mut result = Ok(());
+    for (position, delimiter, strip_tabs) in pending {
+        match read_here_document(input, i, &delimiter, strip_tabs) {
+            Some((body, end)) => {
+                bodies.push((position, Token {
+                    kind: TokenKind::HereDocument,
+                    text: body,
+                    start: i,
+                    end,
+                }));
+                i = end;
+            }
+            None => {
+                // What there is so far, for the line editor to color
+                bodies.push((position, Token {
+                    kind: TokenKind::HereDocument,
+                    text: input[i..].to_owned(),
+                    start: i,
+                    end: input.len(),
+                }));
+                result = Err(ParseError::Incomplete("heredoc"));
+                break;
+            }
+        }
+    }
+    for (position, token) in bodies.into_iter().rev() {
+        tokens.insert(position, token);
+    }
+    result.map(|_| i)
+}
+
+// The lines from `start` up to one that is just the delimiter, and where the input goes on
+// after that line. <<- strips tabs from the start of every line, the delimiter's included.
+fn read_here_document(
+    input: &str,
+    start: usize,
+    delimiter: &str,
+    strip_tabs: bool,
+) -> Option<(String, usize)> {
+    let mut body = String::new();
+    let mut i = start;
+    while i < input.len() {
+        let line_end = input[i..].find('\n').map(|n| i + n);
+        let next = line_end.map_or(input.len(), |end| end + 1);
+        let line = &input[i..line_end.unwrap_or(input.len())];
+        let line = if strip_tabs { line.trim_start_matches('\t') } else { line };
+        if line == delimiter {
+            return Some((body, next));
+        }
+        // The last line may still be being typed
+        line_end?;
+        body.push_str(line);
+        body.push('\n');
+        i = next;
+    }
+    None
+}
+
+// The word that ends a here-document, with its quotes removed
+pub fn here_delimiter(word: &str) -> String {
+    let mut delimiter = String::new();
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' | '"' => {}
+            '\\' => delimiter.extend(chars.next()),
+            _ => delimiter.push(c),
+        }
+    }
+    delimiter
+}
+
+fn finish_word(input: &str, tokens: &mut Vec<Token>, word_start: &mut Option<usize>, end: usize) {
+    if let Some(start) = word_start.take() {
+        tokens.push(Token {
+            kind: TokenKind::Word,
+            text: input[start..end].to_owned(),
+            start,
+            end,
+        });
+    }
+}
+
+fn char_len(input: &str, i: usize) -> usize {
+    input[i..].chars().next().map(|c| c.len_utf8()).unwrap_or(1)
+}
+
+// Returns the index just past the closing quote that matches the one at `start`
+pub fn skip_quoted(input: &str, start: usize) -> Result<usize, ParseError> {
+    let bytes = input.as_bytes();
+    let quote = bytes[start];
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            c if c == quote => return Ok(i + 1),
+            b'\\' if quote != b'\'' => i += 2,
+            b'$' if quote == b'"'
+                && (bytes.get(i + 1) == Some(&b'(') || bytes.get(i + 1) == Some(&b'{')) =>
+            {
+                i = skip_nested(input, i + 1)?;
+            }
+            _ => i += 1,
+        }
+    }
+    Err(match quote {
+        b'\'' => ParseError::Incomplete("quote"),
+        b'"' => ParseError::Incomplete("dquote"),
+        _ => ParseError::Incomplete("bquote"),
+    })
+}
+
+// Skips a balanced $( ... ) or ${ ... } whose opening bracket is at `start`
+pub fn skip_nested(input: &str, start: usize) -> Result<usize, ParseError> {
+    let bytes = input.as_bytes();
+    let (open, close) = if bytes[start] == b'(' {
+        (b'(', b')')
+    } else {
+        (b'{', b'}')
+    };
+    let mut depth = 0;
+    let mut i = start;
+    while i < bytes.len() {
+        match bytes[i] {
+            c if c == open => {
+                depth += 1;
+                i += 1;
+            }
+            c if c == close => {
+                depth -= 1;
+                i += 1;
+                if depth == 0 {
+                    return Ok(i);
+                }
+            }
+            b'\\' => i += 2,
+            b'\'' | b'"' | b'`' => i = skip_quoted(input, i)?,
+            _ => i += 1,
+        }
+    }
+    Err(ParseError::Incomplete(if open == b'(' {
+        "cmdsubst"
+    } else {
+        "braceparam"
+    }))
+}
+
+const REDIRECTIONS: &[&str] = &["<<-", "<<", ">>", "<&", ">&", ">|", "<>", "&>", "<", ">"];
+
+// Words that end the list of commands inside a block
+const TERMINATORS: &[&str] = &["then", "elif", "else", "fi", "do", "done", "esac", "}"];
+
+// Commands whose arguments can be assignments, array literals included
+pub const DECLARATIONS: &[&str] = &["declare", "typeset"];
+
+// Where the = is in a NAME=value word, which can also be NAME+=value to append or
+// NAME[subscript]=value to set an array element
+pub fn assignment(word: &str) -> Option<usize> {
+    let name = word.bytes().take_while(|c| c.is_ascii_alphanumeric() || *c == b'_').count();
+    if !is_name(&word[..name]) {
+        return None;
+    }
+    let mut end = name;
+    if word[end..].starts_with('[') {
+        end += word[end..].find(']')? + 1;
+    }
+    if word[end..].starts_with("+=") {
+        end += 1;
+    }
+    if word[end..].starts_with('=') {
+        Some(end)
+    } else {
+        None
+    }
+}
+
+// The word the tokenizer makes of a line like `= 1 + 2`, which no other word can look like
+pub fn is_expression(word: &str) -> bool {
+    word.starts_with("= ") || word.starts_with("=\t")
+}
+
+pub fn is_name(word: &str) -> bool {
+    word.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') &&
+        word.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Words that open a block and the word that closes it
+pub fn block_closer(word: &str) -> Option<&'static str> {
+    match word {
+        "if" => Some("fi"),
+        "case" => Some("esac"),
+        "do" => Some("done"),
+        "{" => Some("}"),
+        _ => None,
+    }
+}
+
+// Words after which the next word is again in command position
+pub fn is_command_prefix(word: &str) -> bool {
+    matches!(
+        word,
+        "then" | "else" | "elif" | "do" | "if" | "while" | "until" | "{" | "!" | "time"
+    )
+}
+
+const LOOPS: &[&str] = &["for", "while", "until"];
+
+// Checks whether `input` forms a whole command, or whether the line editor should keep
+// reading more lines. Returns the construct that is still open, like zsh's PS2 context.
+pub fn incomplete_context(input: &str) -> Option<&'static str> {
+    let tokens = match tokenize(input) {
+        Ok(tokens) => tokens,
+        Err(ParseError::Incomplete(what)) => return Some(what),
+        Err(ParseError::Unexpected(_)) => return None,
+    };
+
+    let mut open: Vec<&'static str> = Vec::new();
+    let mut command_position = true;
+    let mut last: Option<&Token> = None;
+    for token in tokens.iter().filter(|t| t.kind != TokenKind::Comment) {
+        match token.kind {
+            TokenKind::Word => {
+                if command_position {
+                    // Loops wait for their do, which then waits for done
+                    let text = token.text.as_str();
+                    if let Some(&keyword) = LOOPS.iter().find(|&&k| k == text) {
+                        open.push(keyword);
+                    } else if text == "do" && open.last().is_some_and(|o| LOOPS.contains(o)) {
+                        open.pop();
+                        open.push("done");
+                    } else if let Some(closer) = block_closer(&token.text) {
+                        open.push(closer);
+                    } else if open.last() == Some(&token.text.as_str()) {
+                        open.pop();
+                    }
+                    command_position = is_command_prefix(&token.text);
+                }
+            }
+            TokenKind::Operator => {
+                match token.text.as_str() {
+                    "(" => open.push(")"),
+                    ")" if open.last() == Some(&")") => {
+                        open.pop();
+                    }
+                    _ => {}
+                }
+                command_position = matches!(
+                    token.text.as_str(),
+                    "|" | "||" | "&&" | "&" | ";" | ";;" | "(" | ")"
+                );
+            }
+            TokenKind::Newline => command_position = true,
+            TokenKind::Comment | TokenKind::HereDocument => {}
+        }
+        if token.kind != TokenKind::Newline {
+            last = Some(token);
+        }
+    }
+
+    if let Some(token) = last {
+        if token.kind == TokenKind::Operator {
+            match token.text.as_str() {
+                "|" => return Some("pipe"),
+                "&&" => return Some("cmdand"),
+                "||" => return Some("cmdor"),
+                _ => {}
+            }
+        }
+    }
+
+    open.last().map(|closer| match *closer {
+        "fi" => "if",
+        "esac" => "case",
+        "done" => "do",
+        "for" | "while" | "until" => closer,
+        "}" => "cursh",
+        _ => "subsh",
+    })
+}
+
+pub fn is_complete(input: &str) -> bool {
+    incomplete_context(input).is_none()
+}
+
+// Removes backslash-newline pairs outside of single quotes
+pub fn join_continuations(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+    let mut in_single_quotes = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_single_quotes = !in_single_quotes;
+                output.push(c);
+            }
+            '\\' if !in_single_quotes => {
+                if chars.peek() == Some(&'\n') {
+                    chars.next();
+                } else {
+                    output.push(c);
+                    if let Some(next) = chars.next() {
+                        output.push(next);
+                    }
+                }
+            }
+            _ => output.push(c),
+        }
+    }
+    output
+}
+
+// Turns tokens into commands, one line's worth at a time, so that an alias defined by one
+// command applies to the commands after it
+pub struct Parser<'a> {
+    input: &'a str,
+    tokens: Vec<Token>,
+    position: usize,
+    aliases: HashMap<String, String>,
+    // Something in the input that never ends, like a quote that is never closed, and where the
+    // line it is on starts. The tokens stop before that line.
+    broken: Option<(ParseError, usize)>,
+    // How many commands the one being parsed is inside
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    // The commands before a line that can't be split into tokens still parse, and that line
+    // is the error once they have been given out, the way sh reads a script as it goes
+    pub fn new(input: &'a str) -> Parser<'a> {
+        let (mut tokens, error) = tokenize_partial(input);
+        let broken = error.map(|error| {
+            // The word or here-document left open is the one that goes on to the end
+            let open = tokens.iter().position(|t| t.end == input.len()).unwrap_or(tokens.len());
+            let line_start = tokens[..open]
+                .iter()
+                .rposition(|t| t.kind == TokenKind::Newline)
+                .map_or(0, |n| n + 1);
+            let offset = tokens.get(line_start).map_or(input.len(), |t| t.start);
+            tokens.truncate(line_start);
+            (error, offset)
+        });
+        tokens.retain(|t| t.kind != TokenKind::Comment);
+        Parser {
+            input,
+            tokens,
+            position: 0,
+            aliases: HashMap::new(),
+            broken,
+            depth: 0,
+        }
+    }
+
+    // Line number of the token the parser is at, counting from 1
+    pub fn line(&self) -> usize {
+        let end = self.broken.as_ref().map_or(self.input.len(), |&(_, offset)| offset);
+        let offset = self.tokens.get(self.position).map(|t| t.start).unwrap_or(end);
+        self.line_at(offset)
+    }
+
+    // Where the tokens that were parsed end, here-documents included
+    pub fn consumed(&self) -> usize {
+        self.tokens.iter().take(self.position).map(|t| t.end).max().unwrap_or(0)
+    }
+
+    fn line_at(&self, offset: usize) -> usize {
+        self.input[..offset.min(self.input.len())].matches('\n').count() + 1
+    }
+
+    // Parses the commands up to the end of the next line, or gives None at the end of the input
+    pub fn next_command(
+        &mut self,
+        aliases: &HashMap<String, String>,
+    ) -> Result<Option<Command>, ParseError> {
+        self.aliases = aliases.clone();
+        self.skip_newlines();
+        let broken = self.broken.as_ref().map(|(error, _)| error.clone());
+        if self.peek().is_none() {
+            return broken.map_or(Ok(None), Err);
+        }
+        let command = match (self.list(false), broken) {
+            // A command that carries on into the line that can't be split
+            (Err(ParseError::Incomplete(_)), Some(error)) => return Err(error),
+            (command, _) => command?,
+        };
+        match self.peek() {
+            None => {}
+            Some(token) if token.kind == TokenKind::Newline => self.position += 1,
+            Some(_) => return Err(self.unexpected()),
+        }
+        Ok(Some(command))
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn peek_word(&self) -> Option<&str> {
+        self.peek().filter(|t| t.kind == TokenKind::Word).map(|t| t.text.as_str())
+    }
+
+    fn peek_operator(&self, operator: &str) -> bool {
+        self.peek().is_some_and(|t| t.kind == TokenKind::Operator && t.text == operator)
+    }
+
+    fn eat_operator(&mut self, operator: &str) -> bool {
+        let found = self.peek_operator(operator);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn peek_newline(&self) -> bool {
+        self.peek().is_some_and(|t| t.kind == TokenKind::Newline)
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek_newline() {
+            self.position += 1;
+        }
+    }
+
+    fn unexpected(&self) -> ParseError {
+        match self.peek() {
+            Some(token) if token.kind == TokenKind::Newline => {
+                ParseError::Unexpected("unexpected end of line".to_owned())
+            }
+            Some(token) => ParseError::Unexpected(format!("unexpected `{}'", token.text)),
+            None => ParseError::Incomplete("command"),
+        }
+    }
+
+    // Consumes a reserved word, or fails saying which block was left open
+    fn expect_word(&mut self, word: &str, context: &'static str) -> Result<(), ParseError> {
+        match self.peek() {
+            Some(token) if token.kind == TokenKind::Word && token.text == word => {
+                self.position += 1;
+                Ok(())
+            }
+            None => Err(ParseError::Incomplete(context)),
+            Some(_) => Err(self.unexpected()),
+        }
+    }
+
+    fn next_word(&mut self, context: &'static str) -> Result<String, ParseError> {
+        match self.peek() {
+            Some(token) if token.kind == TokenKind::Word => {
+                self.position += 1;
+                Ok(self.tokens[self.position - 1].text.clone())
+            }
+            None => Err(ParseError::Incomplete(context)),
+            Some(_) => Err(self.unexpected()),
+        }
+    }
+
+    fn at_list_end(&self) -> bool {
+        match self.peek() {
+            None => true,
+            Some(token) if token.kind == TokenKind::Operator => {
+                token.text == ")" || token.text == ";;"
+            }
+            Some(token) => token.kind == TokenKind::Word && TERMINATORS.contains(&&*token.text),
+        }
+    }
+
+    // Commands separated by ; and &, and by newlines inside blocks
+    fn list(&mut self, multiline: bool) -> Result<Command, ParseError> {
+        let mut items = Vec::new();
+        loop {
+            if multiline {
+                self.skip_newlines();
+            }
+            if self.at_list_end() {
+                break;
+            }
+            let start = self.peek().map(|t| t.start).unwrap_or(0);
+            let mut item = self.and_or()?;
+            let separated = if self.eat_operator(";") {
+                true
+            } else if self.eat_operator("&") {
+                let end = self.tokens[self.position - 1].start;
+                let text = self.input[start..end.max(start)].trim().to_owned();
+                item = Command::Background(Box::new(item), text);
+                true
+            } else {
+                false
+            };
+            items.push(item);
+            if self.peek_newline() {
+                if !multiline {
+                    break;
+                }
+            } else if !separated {
+                break;
+            }
+        }
+        match items.len() {
+            0 => Err(self.unexpected()),
+            1 => Ok(items.pop().unwrap()),
+            _ => Ok(Command::List(items)),
+        }
+    }
+
+    fn and_or(&mut self) -> Result<Command, ParseError> {
+        let mut command = self.pipeline()?;
+        loop {
+            if self.eat_operator("&&") {
+                self.skip_newlines();
+                command = Command::And(Box::new(command), Box::new(self.pipeline()?));
+            } else if self.eat_operator("||") {
+                self.skip_newlines();
+                command = Command::Or(Box::new(command), Box::new(self.pipeline()?));
+            } else {
+                return Ok(command);
+            }
+        }
+    }
+
+    fn pipeline(&mut self) -> Result<Command, ParseError> {
+        let negated = self.peek_word() == Some("!");
+        if negated {
+            self.position += 1;
+        }
+        let mut stages = vec![self.command()?];
+        while self.eat_operator("|") {
+            self.skip_newlines();
+            stages.push(self.command()?);
+        }
+        if stages.len() == 1 && !negated {
+            return Ok(stages.pop().unwrap());
+        }
+        Ok(Command::Pipeline { stages, negated })
+    }
+
+    // Replaces an alias at the start of a command with the tokens of its value. An alias that
+    // expands to itself, like ls='ls -F', stops there.
+    fn expand_alias(&mut self) {
+        let mut expanded: Vec<String> = Vec::new();
+        while let Some(token) = self.peek().filter(|t| t.kind == TokenKind::Word).cloned() {
+            if expanded.contains(&token.text) {
+                break;
+            }
+            let value = match self.aliases.get(&token.text) {
+                Some(value) => value.clone(),
+                None => break,
+            };
+            let replacement: Vec<Token> = tokenize_partial(&value)
+                .0
+                .into_iter()
+                .filter(|t| t.kind != TokenKind::Comment)
+                .map(|t| Token {
+                    start: token.start,
+                    end: token.end,
+                    ..t
+                })
+                .collect();
+            self.tokens.splice(self.position..self.position + 1, replacement);
+            expanded.push(token.text);
+        }
+    }
+
+    fn command(&mut self) -> Result<Command, ParseError> {
+        if self.depth >= MAX_NESTING || exec::stack_low() {
+            return Err(ParseError::Unexpected("commands nested too deeply".to_owned()));
+        }
+        self.depth += 1;
+        let command = self.single_command();
+        self.depth -= 1;
+        command
+    }
+
+    fn single_command(&mut self) -> Result<Command, ParseError> {
+        self.expand_alias();
+        let command = match self.peek_word() {
+            None if self.peek_operator("(") => {
+                self.position += 1;
+                let body = self.list(true)?;
+                if !self.eat_operator(")") {
+                    return Err(match self.peek() {
+                        None => ParseError::Incomplete("subsh"),
+                        Some(_) => self.unexpected(),
+                    });
+                }
+                Command::Subshell(Box::new(body))
+            }
+            Some("if") => self.if_clause()?,
+            Some("while") => self.while_clause(false)?,
+            Some("until") => self.while_clause(true)?,
+            Some("for") => self.for_clause()?,
+            Some("case") => self.case_clause()?,
+            Some("[[") => self.conditional()?,
+            Some(word) if is_expression(word) => {
+                let line = self.line();
+                let text = word[1..].trim().to_owned();
+                self.position += 1;
+                Command::Expression { text, line }
+            }
+            Some("{") => {
+                self.position += 1;
+                let body = self.list(true)?;
+                self.expect_word("}", "cursh")?;
+                Command::Group(Box::new(body))
+            }
+            Some("function") => {
+                self.position += 1;
+                let name = self.next_word("function")?;
+                if self.eat_operator("(") && !self.eat_operator(")") {
+                    return Err(self.unexpected());
+                }
+                return self.function_body(name);
+            }
+            Some(word) if TERMINATORS.contains(&word) => return Err(self.unexpected()),
+            Some(word) if is_name(word) && self.tokens.get(self.position + 1).is_some_and(|t| {
+                t.kind == TokenKind::Operator && t.text == "("
+            }) => {
+                let name = word.to_owned();
+                self.position += 2;
+                if !self.eat_operator(")") {
+                    return Err(self.unexpected());
+                }
+                return self.function_body(name);
+            }
+            _ => return self.simple_command(),
+        };
+        let mut redirects = Vec::new();
+        while let Some(redirect) = self.redirect()? {
+            redirects.push(redirect);
+        }
+        if redirects.is_empty() {
+            Ok(command)
+        } else {
+            Ok(Command::Redirected(Box::new(command), redirects))
+        }
+    }
+
+    // [[ expression ]]. The words stay as they are written, and of the operators only the ones
+    // that group and compare can appear. The right side of =~ is a regular expression, which
+    // goes on until a space, even through ( ) and |.
+    fn conditional(&mut self) -> Result<Command, ParseError> {
+        let line = self.line();
+        self.position += 1;
+        let mut words: Vec<String> = Vec::new();
+        loop {
+            self.skip_newlines();
+            let token = match self.peek() {
+                Some(token) => token.clone(),
+                None => return Err(ParseError::Incomplete("cond")),
+            };
+            if token.kind == TokenKind::Word && token.text == "]]" {
+                self.position += 1;
+                break;
+            }
+            if words.last().is_some_and(|w| w == "=~") {
+                let mut end = token.end;
+                self.position += 1;
+                while let Some(next) = self.peek() {
+                    if next.start != end || next.kind == TokenKind::Newline || next.text == "]]" {
+                        break;
+                    }
+                    end = next.end;
+                    self.position += 1;
+                }
+                words.push(self.input[token.start..end].to_owned());
+                continue;
+            }
+            if token.kind == TokenKind::Operator &&
+                !matches!(token.text.as_str(), "&&" | "||" | "(" | ")" | "<" | ">")
+            {
+                return Err(self.unexpected());
+            }
+            words.push(token.text);
+            self.position += 1;
+        }
+        if words.is_empty() {
+            return Err(ParseError::Unexpected("unexpected `]]'".to_owned()));
+        }
+        Ok(Command::Conditional { words, line })
+    }
+
+    fn function_body(&mut self, name: String) -> Result<Command, ParseError> {
+        self.skip_newlines();
+        if self.peek().is_none() {
+            return Err(ParseError::Incomplete("function"));
+        }
+        let body = Rc::new(self.command()?);
+        Ok(Command::Function { name, body })
+    }
+
+    fn if_clause(&mut self) -> Result<Command, ParseError> {
+        self.position += 1;
+        let mut branches = Vec::new();
+        let mut otherwise = None;
+        loop {
+            let condition = self.list(true)?;
+            self.expect_word("then", "if")?;
+            branches.push((condition, self.list(true)?));
+            match self.peek_word() {
+                Some("elif") => self.position += 1,
+                Some("else") => {
+                    self.position += 1;
+                    otherwise = Some(Box::new(self.list(true)?));
+                    self.expect_word("fi", "if")?;
+                    break;
+                }
+                _ => {
+                    self.expect_word("fi", "if")?;
+                    break;
+                }
+            }
+        }
+        Ok(Command::If { branches, otherwise })
+    }
+
+    fn while_clause(&mut self, until: bool) -> Result<Command, ParseError> {
+        self.position += 1;
+        let condition = Box::new(self.list(true)?);
+        self.expect_word("do", "do")?;
+        let body = Box::new(self.list(true)?);
+        self.expect_word("done", "do")?;
+        Ok(Command::While {
+            condition,
+            body,
+            until,
+        })
+    }
+
+    fn for_clause(&mut self) -> Result<Command, ParseError> {
+        self.position += 1;
+        let variable = self.next_word("for")?;
+        if !is_name(&variable) {
+            return Err(ParseError::Unexpected(format!("`{}' is not a valid name", variable)));
+        }
+        self.skip_newlines();
+        let mut words = None;
+        if self.peek_word() == Some("in") {
+            self.position += 1;
+            let mut list = Vec::new();
+            while let Some(word) = self.peek_word() {
+                list.push(word.to_owned());
+                self.position += 1;
+            }
+            words = Some(list);
+            if !self.eat_operator(";") && !self.peek_newline() {
+                return Err(if self.peek().is_none() {
+                    ParseError::Incomplete("for")
+                } else {
+                    self.unexpected()
+                });
+            }
+        } else {
+            self.eat_operator(";");
+        }
+        self.skip_newlines();
+        self.expect_word("do", "for")?;
+        let body = Box::new(self.list(true)?);
+        self.expect_word("done", "for")?;
+        Ok(Command::For {
+            variable,
+            words,
+            body,
+        })
+    }
+
+    fn case_clause(&mut self) -> Result<Command, ParseError> {
+        self.position += 1;
+        let word = self.next_word("case")?;
+        self.skip_newlines();
+        self.expect_word("in", "case")?;
+        let mut arms = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.peek_word() == Some("esac") {
+                self.position += 1;
+                break;
+            }
+            self.eat_operator("(");
+            let mut patterns = vec![self.next_word("case")?];
+            while self.eat_operator("|") {
+                patterns.push(self.next_word("case")?);
+            }
+            if !self.eat_operator(")") {
+                return Err(if self.peek().is_none() {
+                    ParseError::Incomplete("case")
+                } else {
+                    self.unexpected()
+                });
+            }
+            self.skip_newlines();
+            let body = if self.peek_operator(";;") || self.peek_word() == Some("esac") {
+                Command::List(Vec::new())
+            } else {
+                self.list(true)?
+            };
+            arms.push((patterns, body));
+            if !self.eat_operator(";;") {
+                self.skip_newlines();
+                self.expect_word("esac", "case")?;
+                break;
+            }
+        }
+        Ok(Command::Case { word, arms })
+    }
+
+    // A redirection, along with the file descriptor number written right before it
+    fn redirect(&mut self) -> Result<Option<Redirect>, ParseError> {
+        let (fd, operator_position) = match self.peek() {
+            Some(token) if token.kind == TokenKind::Word &&
+                               token.text.bytes().all(|b| b.is_ascii_digit()) => {
+                match self.tokens.get(self.position + 1) {
+                    Some(next) if next.start == token.end => {
+                        (token.text.parse().ok(), self.position + 1)
+                    }
+                    _ => return Ok(None),
+                }
+            }
+            _ => (None, self.position),
+        };
+        let operator = match self.tokens.get(operator_position) {
+            Some(token) if token.kind == TokenKind::Operator &&
+                               REDIRECTIONS.contains(&&*token.text) => token.text.clone(),
+            _ => return Ok(None),
+        };
+        self.position = operator_position + 1;
+        let target = self.next_word("redirection")?;
+        let mut here_document = None;
+        if operator.starts_with("<<") {
+            match self.peek() {
+                Some(token) if token.kind == TokenKind::HereDocument => {
+                    here_document = Some(token.text.clone());
+                    self.position += 1;
+                }
+                _ => return Err(ParseError::Incomplete("heredoc")),
+            }
+        }
+        Ok(Some(Redirect {
+            fd,
+            operator,
+            target,
+            here_document,
+        }))
+    }
+
+    // An assignment, along with the (...) right after its = for an array. The elements are
+    // kept as written, separated by spaces, to be split up again when they are expanded.
+    fn assignment_word(&mut self) -> Result<String, ParseError> {
+        let token = self.tokens[self.position].clone();
+        self.position += 1;
+        let literal = self.peek().is_some_and(|t| {
+            t.kind == TokenKind::Operator && t.text == "(" && t.start == token.end
+        });
+        if !token.text.ends_with('=') || !literal {
+            return Ok(token.text);
+        }
+        self.position += 1;
+        let mut elements = Vec::new();
+        loop {
+            self.skip_newlines();
+            match self.peek() {
+                Some(t) if t.kind == TokenKind::Word => elements.push(t.text.clone()),
+                Some(t) if t.kind == TokenKind::Operator && t.text == ")" => break,
+                Some(_) => return Err(self.unexpected()),
+                None => return Err(ParseError::Incomplete("array")),
+            }
+            self.position += 1;
+        }
+        self.position += 1;
+        Ok(format!("{}({})", token.text, elements.join(" ")))
+    }
+
+    fn simple_command(&mut self) -> Result<Command, ParseError> {
+        let line = self.line();
+        let mut assignments = Vec::new();
+        let mut words: Vec<String> = Vec::new();
+        let mut redirects = Vec::new();
+        loop {
+            if let Some(redirect) = self.redirect()? {
+                redirects.push(redirect);
+                continue;
+            }
+            let declaration = words.first().is_some_and(|w| DECLARATIONS.contains(&w.as_str()));
+            match self.peek_word() {
+                Some(word) if (words.is_empty() || declaration) && assignment(word).is_some() => {
+                    let word = self.assignment_word()?;
+                    if words.is_empty() {
+                        assignments.push(word);
+                    } else {
+                        words.push(word);
+                    }
+                    continue;
+                }
+                Some(word) => words.push(word.to_owned()),
+                None => break,
+            }
+            self.position += 1;
+        }
+        if assignments.is_empty() && words.is_empty() && redirects.is_empty() {
+            return Err(self.unexpected());
+        }
+        Ok(Command::Simple {
+            assignments,
+            words,
+            redirects,
+            line,
+        })
+    }
+}
@@ -0,0 +1,422 @@
+extern crate std;
+extern crate termcolor;
+
+use std::io;
+use std::io::Write;
+use std::iter::Peekable;
+use std::str::Chars;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use self::termcolor::{Buffer, Color, ColorSpec, WriteColor};
+use state::{PromptState, ShellState};
+use history;
+use git;
+use git::GitStatus;
+use render;
+
+const COLOR_NAMES: &[&str] = &[
+    "BLACK", "RED", "GREEN", "YELLOW", "BLUE", "MAGENTA", "CYAN", "WHITE",
+];
+
+// A parsed piece of a prompt template
+enum Node {
+    Text(String),
+    // {$NAME}
+    Variable(String),
+    // {NAME} or {NAME:argument}, either a style or a segment
+    Tag(String, Option<String>),
+    // {?name:...} renders its body only when the segment or variable is non-empty,
+    // {!name:...} only when it is empty
+    Conditional {
+        name: String,
+        negated: bool,
+        body: Vec<Node>,
+    },
+}
+
+// Parses a template up to the end of input, or up to the } that closes a conditional
+fn parse_template(chars: &mut Peekable<Chars>, nested: bool) -> Vec<Node> {
+    let mut nodes = Vec::new();
+    let mut text = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if matches!(chars.peek(), Some(&'{') | Some(&'}') | Some(&'\\')) => {
+                text.push(chars.next().unwrap());
+            }
+            '}' if nested => break,
+            '{' => {
+                if !text.is_empty() {
+                    nodes.push(Node::Text(text.split_off(0)));
+                }
+                match parse_tag(chars) {
+                    Some(node) => nodes.push(node),
+                    None => text.push('{'),
+                }
+            }
+            _ => text.push(c),
+        }
+    }
+    if !text.is_empty() {
+        nodes.push(Node::Text(text));
+    }
+    nodes
+}
+
+// Parses what follows a {, leaving `chars` untouched if it isn't a well-formed tag
+fn parse_tag(chars: &mut Peekable<Chars>) -> Option<Node> {
+    let mut lookahead = chars.clone();
+    let mut name = String::new();
+    let mut argument = None;
+    loop {
+        match lookahead.next()? {
+            '}' => break,
+            '{' => return None,
+            ':' if name.starts_with('?') || name.starts_with('!') => {
+                let negated = name.starts_with('!');
+                name.remove(0);
+                let body = parse_template(&mut lookahead, true);
+                *chars = lookahead;
+                return Some(Node::Conditional {
+                    name,
+                    negated,
+                    body,
+                });
+            }
+            ':' if argument.is_none() && !name.starts_with('$') => argument = Some(String::new()),
+            c => {
+                match argument {
+                    Some(ref mut argument) => argument.push(c),
+                    None => name.push(c),
+                }
+            }
+        }
+    }
+    *chars = lookahead;
+    Some(match name.strip_prefix('$') {
+        Some(variable) => Node::Variable(variable.to_owned()),
+        None => Node::Tag(name, argument),
+    })
+}
+
+// A color by name, as a 256-color palette number or as #rrggbb
+fn parse_color(name: &str) -> Option<Color> {
+    let name = name.to_uppercase();
+    if let Some(hex) = name.strip_prefix('#') {
+        let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+        return match hex.len() {
+            6 => Some(Color::Rgb(channel(0)?, channel(2)?, channel(4)?)),
+            _ => None,
+        };
+    }
+    if let Ok(n) = name.parse::<u8>() {
+        return Some(Color::Ansi256(n));
+    }
+    let (bright, base) = match name.strip_prefix("BRIGHT_") {
+        Some(base) => (true, base),
+        None => (false, name.as_str()),
+    };
+    let index = COLOR_NAMES.iter().position(|c| *c == base)?;
+    Some(if bright {
+        Color::Ansi256(index as u8 + 8)
+    } else {
+        [
+            Color::Black,
+            Color::Red,
+            Color::Green,
+            Color::Yellow,
+            Color::Blue,
+            Color::Magenta,
+            Color::Cyan,
+            Color::White,
+        ][index]
+    })
+}
+
+fn uses_git(nodes: &[Node]) -> bool {
+    nodes.iter().any(|node| match *node {
+        Node::Tag(ref name, _) => name.to_uppercase().starts_with("GIT"),
+        Node::Conditional { ref name, ref body, .. } => {
+            name.to_uppercase().starts_with("GIT") || uses_git(body)
+        }
+        _ => false,
+    })
+}
+
+// Replaces a leading $HOME with ~ and keeps only the last `components` directories
+fn abbreviate_path(path: &str, home: Option<&str>, components: Option<usize>) -> String {
+    let path = match home {
+        Some(home) if !home.is_empty() && path == home => "~".to_owned(),
+        Some(home) if !home.is_empty() && path.starts_with(&format!("{}/", home)) => {
+            format!("~{}", &path[home.len()..])
+        }
+        _ => path.to_owned(),
+    };
+    let components = match components {
+        Some(components) => components,
+        None => return path,
+    };
+    let parts: Vec<&str> = path.split('/').filter(|p| !p.is_empty() && *p != "~").collect();
+    if parts.len() <= components {
+        return path;
+    }
+    format!("…/{}", parts[parts.len() - components..].join("/"))
+}
+
+impl ShellState {
+    pub fn variable(&self, name: &str) -> Option<String> {
+        self.variables.get(name).map(|v| v.to_string_lossy().into_owned())
+    }
+
+    // The text of a segment, which is empty when there is nothing to show, or None when
+    // `name` isn't a segment
+    fn prompt_segment(
+        &self,
+        name: &str,
+        argument: Option<&str>,
+        git: Option<&GitStatus>,
+    ) -> Option<String> {
+        let now = || {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        };
+        let cwd = || self.variable("PWD").unwrap_or_default();
+        Some(match name.to_uppercase().as_str() {
+            "STATUS" if self.last_status != 0 => self.last_status.to_string(),
+            "STATUS" => String::new(),
+            "JOBS" if !self.jobs.is_empty() => self.jobs.len().to_string(),
+            "JOBS" => String::new(),
+            // Only shown for commands that took at least the given number of seconds, 1 by default
+            "DURATION" => {
+                let threshold = argument.and_then(|a| a.parse::<f64>().ok()).unwrap_or(1.0);
+                let millis = self.last_duration.as_millis() as u64;
+                if millis as f64 >= threshold * 1000.0 {
+                    history::format_duration(millis)
+                } else {
+                    String::new()
+                }
+            }
+            "TIME" => history::format_time(now(), argument.unwrap_or("%H:%M:%S")),
+            "DATE" => history::format_time(now(), argument.unwrap_or("%Y-%m-%d")),
+            "CWD" => {
+                let components = argument.and_then(|a| a.parse().ok());
+                abbreviate_path(&cwd(), self.variable("HOME").as_deref(), components)
+            }
+            "DIR" => {
+                let cwd = abbreviate_path(&cwd(), self.variable("HOME").as_deref(), None);
+                match cwd.rfind('/') {
+                    Some(i) if cwd.len() > 1 => cwd[i + 1..].to_owned(),
+                    _ => cwd,
+                }
+            }
+            "USER" => self.variable("USER").unwrap_or_default(),
+            "HOST" => {
+                let host = self.variable("HOSTNAME").unwrap_or_default();
+                host.split('.').next().unwrap_or("").to_owned()
+            }
+            // Repository segments, all empty outside of a repository
+            "GIT" => git.map(|g| g.summary()).unwrap_or_default(),
+            "GIT_BRANCH" => git.map(|g| g.branch.clone()).unwrap_or_default(),
+            "GIT_AHEAD" => git.filter(|g| g.ahead > 0).map(|g| g.ahead.to_string()).unwrap_or_default(),
+            "GIT_BEHIND" => {
+                git.filter(|g| g.behind > 0).map(|g| g.behind.to_string()).unwrap_or_default()
+            }
+            "GIT_STAGED" => git.filter(|g| g.staged).map(|_| "+".to_owned()).unwrap_or_default(),
+            "GIT_DIRTY" => git.filter(|g| g.dirty).map(|_| "!".to_owned()).unwrap_or_default(),
+            "GIT_UNTRACKED" => {
+                git.filter(|g| g.untracked).map(|_| "?".to_owned()).unwrap_or_default()
+            }
+            "GIT_STATE" => git.and_then(|g| g.state.clone()).unwrap_or_default(),
+            _ => return None,
+        })
+    }
+
+    // Applies a style tag to `spec`, returning false if `name` isn't a style
+    fn prompt_style(spec: &mut ColorSpec, name: &str, argument: Option<&str>) -> bool {
+        let name = name.to_uppercase();
+        match (name.as_str(), argument) {
+            ("BOLD", None) => {
+                spec.set_bold(true);
+            }
+            ("DIM", None) => {
+                spec.set_dimmed(true);
+            }
+            ("ITALIC", None) => {
+                spec.set_italic(true);
+            }
+            ("UNDERLINE", None) => {
+                spec.set_underline(true);
+            }
+            ("RESET", None) => *spec = ColorSpec::new(),
+            ("DEFAULT", None) => {
+                spec.set_fg(None);
+            }
+            ("BG_DEFAULT", None) => {
+                spec.set_bg(None);
+            }
+            ("FG", Some(color)) => {
+                match parse_color(color) {
+                    Some(color) => spec.set_fg(Some(color)),
+                    None => return false,
+                };
+            }
+            ("BG", Some(color)) => {
+                match parse_color(color) {
+                    Some(color) => spec.set_bg(Some(color)),
+                    None => return false,
+                };
+            }
+            (name, None) => {
+                if let Some(color) = name.strip_prefix("BG_").and_then(parse_color) {
+                    spec.set_bg(Some(color));
+                } else if let Some(color) = parse_color(name) {
+                    spec.set_fg(Some(color));
+                } else {
+                    return false;
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    // Whether the segment or $VARIABLE a conditional refers to has anything to show
+    fn prompt_condition(&self, name: &str, git: Option<&GitStatus>) -> bool {
+        match name.strip_prefix('$') {
+            Some(variable) => self.variable(variable).is_some_and(|v| !v.is_empty()),
+            None => self.prompt_segment(name, None, git).is_some_and(|s| !s.is_empty()),
+        }
+    }
+
+    fn render_nodes(
+        &self,
+        nodes: &[Node],
+        git: Option<&GitStatus>,
+        buffer: &mut Buffer,
+        spec: &mut ColorSpec,
+    ) -> io::Result<()> {
+        for node in nodes {
+            match *node {
+                // The style is repeated on every line so each line can be redrawn on its own
+                Node::Text(ref text) => {
+                    for (i, line) in text.split('\n').enumerate() {
+                        if i > 0 {
+                            buffer.reset()?;
+                            writeln!(buffer)?;
+                            buffer.set_color(spec)?;
+                        }
+                        write!(buffer, "{}", line)?;
+                    }
+                }
+                Node::Variable(ref name) => {
+                    write!(buffer, "{}", self.variable(name).unwrap_or_default())?
+                }
+                Node::Tag(ref name, ref argument) => {
+                    if let Some(text) = self.prompt_segment(name, argument.as_deref(), git) {
+                        write!(buffer, "{}", text)?;
+                    } else if ShellState::prompt_style(spec, name, argument.as_deref()) {
+                        buffer.set_color(spec)?;
+                    }
+                }
+                Node::Conditional {
+                    ref name,
+                    negated,
+                    ref body,
+                } => {
+                    if self.prompt_condition(name, git) != negated {
+                        self.render_nodes(body, git, buffer, spec)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Colors are left out when NO_COLOR is set or the terminal is dumb
+    fn colors_disabled(&self) -> bool {
+        self.variables.get("NO_COLOR").is_some() ||
+            self.variable("TERM").is_some_and(|t| t == "dumb")
+    }
+
+    // Expands a prompt template into text with ANSI escapes, or without them when colors are
+    // disabled. `git` is what the repository segments show.
+    pub fn render_prompt(&self, template: &str, git: Option<&GitStatus>) -> String {
+        let mut buffer = if self.colors_disabled() {
+            Buffer::no_color()
+        } else {
+            Buffer::ansi()
+        };
+        let nodes = parse_template(&mut template.chars().peekable(), false);
+        let mut spec = ColorSpec::new();
+        let _ = self.render_nodes(&nodes, git, &mut buffer, &mut spec);
+        let _ = buffer.reset();
+        String::from_utf8_lossy(buffer.as_slice()).into_owned()
+    }
+
+    // The status of the repository for the prompt templates, looked at once for all of them
+    // and only when one of them asks for it, for no longer than PROMPT_GIT_TIMEOUT milliseconds
+    pub fn prompt_git_status(&self, templates: &[&str]) -> Option<GitStatus> {
+        let wanted = templates
+            .iter()
+            .any(|template| uses_git(&parse_template(&mut template.chars().peekable(), false)));
+        if !wanted {
+            return None;
+        }
+        let timeout = self.variable("PROMPT_GIT_TIMEOUT")
+            .and_then(|t| t.parse().ok())
+            .unwrap_or(100);
+        let cwd = self.variable("PWD").unwrap_or_default();
+        git::git_status(Path::new(&cwd), Duration::from_millis(timeout))
+    }
+
+    // Expands a template of style tags, like {BOLD}{RED}, into the escapes that switch to
+    // that style. Anything that isn't a style tag is left out.
+    pub fn render_style(&self, template: &str) -> String {
+        let mut spec = ColorSpec::new();
+        for node in parse_template(&mut template.chars().peekable(), false) {
+            if let Node::Tag(ref name, ref argument) = node {
+                ShellState::prompt_style(&mut spec, name, argument.as_deref());
+            }
+        }
+        if self.colors_disabled() || spec.is_none() {
+            return String::new();
+        }
+        let mut buffer = Buffer::ansi();
+        let _ = buffer.set_color(&spec);
+        String::from_utf8_lossy(buffer.as_slice()).into_owned()
+    }
+
+    // Prints PROMPT, leaving its last line, RPROMPT and PS2 to be drawn along with the input
+    pub fn prompt(&self, state: &mut PromptState) {
+        let template = self.variable("PROMPT").unwrap_or_default();
+        let rprompt = self.variable("RPROMPT").unwrap_or_default();
+        let continuation = self.variable("PS2").unwrap_or_else(|| "> ".to_owned());
+        let transient = self.variable("TRANSIENT_PROMPT").unwrap_or_default();
+        state.git = self.prompt_git_status(&[&template, &rprompt, &continuation, &transient]);
+        let git = state.git.as_ref();
+
+        let prompt = self.render_prompt(&template, git);
+        let (above, line) = match prompt.rfind('\n') {
+            Some(i) => prompt.split_at(i + 1),
+            None => ("", prompt.as_str()),
+        };
+        render::draw(&format!("\r{}", above.replace('\n', "\n\r")));
+        state.prompt_line = line.to_owned();
+        state.prompt_above = above.to_owned();
+        state.rprompt = self.render_prompt(&rprompt, git);
+        state.continuation = self.render_prompt(&continuation, git);
+        state.highlighter = self.highlighter();
+        state.redraw();
+    }
+
+    // With TRANSIENT_PROMPT set, a prompt that has been answered is replaced by that shorter
+    // one, so the scrollback only shows the full prompt once. It shows the repository as the
+    // prompt it replaces did.
+    pub fn transient_prompt(&self, state: &mut PromptState) {
+        if let Some(transient) = self.variable("TRANSIENT_PROMPT") {
+            let transient = self.render_prompt(&transient, state.git.as_ref());
+            state.collapse(transient);
+        }
+    }
+}
@@ -1,24 +1,151 @@
 extern crate std;
 
-use std::io::{BufReader, BufRead};
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use error;
+use error::ShellError;
+use exec::Flow;
 use state::ShellState;
-use std::path::Path;
+
+// Startup files that apply to every user
+const SYSTEM_PROFILE: &str = "/etc/rsh/profile";
+const SYSTEM_RC: &str = "/etc/rsh/rshrc";
 
 impl ShellState {
-    pub fn read_config(&mut self) {
-        let config_path = Path::new(self.variables.get("HOME").unwrap()).join(Path::new(".rshrc"));
-        if let Ok(f) = std::fs::File::open(config_path) {
-            let reader = BufReader::new(f);
-            for line in reader.lines().filter_map(|l| l.ok()).filter(
-                |l| l.contains('='),
-            )
+    // Where rsh looks for its config files: $XDG_CONFIG_HOME/rsh, or ~/.config/rsh
+    pub fn config_dir(&self) -> Option<PathBuf> {
+        xdg_dir(self.variable("XDG_CONFIG_HOME"), self.variable("HOME"), ".config")
+    }
+
+    // Where rsh keeps its data, like the history: $XDG_DATA_HOME/rsh, or ~/.local/share/rsh
+    pub fn data_dir(&self) -> Option<PathBuf> {
+        xdg_dir(self.variable("XDG_DATA_HOME"), self.variable("HOME"), ".local/share")
+    }
+
+    // A user's startup file, `name` in the config directory if it is there, or else the dotfile
+    // of the same name in their home directory
+    fn user_file(&self, name: &str) -> Option<PathBuf> {
+        if let Some(path) = self.config_dir().map(|dir| dir.join(name)) {
+            if path.exists() {
+                return Some(path);
+            }
+        }
+        self.variable("HOME").map(|home| Path::new(&home).join(format!(".rsh_{}", name)))
+    }
+
+    // Runs the startup scripts of a login shell, before the ones every interactive shell runs
+    pub fn read_profile(&mut self) {
+        self.read_startup_file(Path::new(SYSTEM_PROFILE), false);
+        if let Some(path) = self.user_file("profile") {
+            self.read_startup_file(&path, false);
+        }
+    }
+
+    // Runs the startup scripts, /etc/rsh/rshrc and then the user's rshrc unless another file
+    // is given
+    pub fn read_config(&mut self, rcfile: Option<&Path>) {
+        self.read_startup_file(Path::new(SYSTEM_RC), false);
+        match rcfile {
+            Some(path) => self.read_startup_file(path, true),
+            None => {
+                let path = match self.config_dir().map(|dir| dir.join("rshrc")) {
+                    Some(ref path) if path.exists() => Some(path.clone()),
+                    _ => self.variable("HOME").map(|home| Path::new(&home).join(".rshrc")),
+                };
+                if let Some(path) = path {
+                    self.read_startup_file(&path, false);
+                }
+            }
+        }
+    }
+
+    // exit, and -e or a failed expansion stopping a script. A shell embedded in another program
+    // only stops what it is running, since exiting would take the program with it.
+    pub fn leave(&mut self, status: i32) -> i32 {
+        if !self.embedded {
+            self.exit(status);
+        }
+        self.flow = Some(Flow::Exit);
+        status
+    }
+
+    // Leaves the shell, running the logout scripts first if this is a login shell
+    pub fn exit(&mut self, status: i32) -> ! {
+        if self.login {
+            self.login = false;
+            if let Some(path) = self.user_file("logout") {
+                self.read_startup_file(&path, false);
+            }
+        }
+        let _ = io::stdout().flush();
+        std::process::exit(status)
+    }
+
+    fn read_startup_file(&mut self, path: &Path, required: bool) {
+        if let Err(e) = self.source_file(path) {
+            // Startup files are optional, but one asked for by name has to be there
+            if required || e.kind() != io::ErrorKind::NotFound {
+                self.report(&format!("{}: {}", path.to_string_lossy(), e));
+            }
+        }
+    }
+
+    // Runs the commands in a file in this shell
+    pub fn source_file(&mut self, path: &Path) -> io::Result<i32> {
+        let source = fs::read_to_string(path)?;
+        let status = self.run_script(&source, Some(&path.to_string_lossy()));
+        // return ends the file early
+        if self.flow == Some(Flow::Return) {
+            self.flow = None;
+        }
+        Ok(status)
+    }
+
+    // source file [arguments], also called .
+    pub fn source(&mut self, args: &[String]) -> error::Result<i32> {
+        let file = match args.first() {
+            Some(file) => file,
+            None => {
+                self.report("source: filename argument required");
+                return Ok(2);
+            }
+        };
+        let path = self.source_path(file);
+        let arguments = if args.len() > 1 {
+            Some(std::mem::replace(&mut self.arguments, args[1..].to_vec()))
+        } else {
+            None
+        };
+        let result = self.source_file(&path).map_err(|e| ShellError::io(file, e));
+        if let Some(arguments) = arguments {
+            self.arguments = arguments;
+        }
+        result
+    }
+
+    // Names without a slash are looked for in PATH, then in the working directory
+    fn source_path(&self, file: &str) -> PathBuf {
+        let cwd = self.variable("PWD").unwrap_or_default();
+        if !file.contains('/') {
+            let path = self.variable("PATH").unwrap_or_default();
+            if let Some(found) = path.split(':')
+                .map(|dir| Path::new(dir).join(file))
+                .find(|candidate| candidate.is_file())
             {
-                let mut split = line.as_str().split('=');
-                let key = split.next().unwrap();
-                let value = split.next().unwrap_or("").to_owned();
-                let real_value = value.replace("\\n", "\n");
-                self.variables.insert(key, real_value.as_ref());
+                return found;
             }
         }
+        Path::new(&cwd).join(file)
+    }
+}
+
+// $XDG_..._HOME/rsh, falling back to the default under the home directory when the variable is
+// unset or not an absolute path, as the spec asks
+fn xdg_dir(variable: Option<String>, home: Option<String>, default: &str) -> Option<PathBuf> {
+    match variable {
+        Some(ref dir) if Path::new(dir).is_absolute() => Some(Path::new(dir).join("rsh")),
+        _ => home.map(|home| Path::new(&home).join(default).join("rsh")),
     }
 }
@@ -0,0 +1,325 @@
+extern crate std;
+extern crate libc;
+
+use std::env;
+use std::io;
+use std::io::Write;
+use std::sync::Once;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Set by SIGWINCH, cleared once the line editor has redrawn for the new size
+static RESIZED: AtomicBool = AtomicBool::new(false);
+static WATCH_RESIZE: Once = Once::new();
+
+extern "C" fn on_resize(_: libc::c_int) {
+    RESIZED.store(true, Ordering::SeqCst);
+}
+
+// Installs the SIGWINCH handler, without SA_RESTART so that a blocked read returns
+pub fn watch_resize() {
+    WATCH_RESIZE.call_once(|| unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_resize as *const () as libc::sighandler_t;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGWINCH, &action, std::ptr::null_mut());
+    });
+}
+
+// Whether the terminal has been resized since the last call
+pub fn take_resize() -> bool {
+    RESIZED.swap(false, Ordering::SeqCst)
+}
+
+// Draws on the terminal. A terminal that has gone away is found out about by the next read,
+// so failing to write to it is no reason to stop.
+pub fn draw(text: &str) {
+    let mut out = io::stdout();
+    let _ = out.write_all(text.as_bytes()).and_then(|_| out.flush());
+}
+
+// Width of the terminal in columns, from the terminal itself or $COLUMNS
+pub fn terminal_width() -> usize {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(1, libc::TIOCGWINSZ, &mut size) } == 0 && size.ws_col > 0 {
+        return size.ws_col as usize;
+    }
+    env::var("COLUMNS")
+        .ok()
+        .and_then(|c| c.parse().ok())
+        .filter(|&c| c > 0)
+        .unwrap_or(80)
+}
+
+// Columns a character takes up: 0 for combining marks, 2 for wide East Asian characters and
+// emoji
+pub fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x0300..=0x036F | 0x0483..=0x0489 | 0x0591..=0x05BD | 0x0610..=0x061A |
+        0x064B..=0x065F | 0x0E31 | 0x0E34..=0x0E3A | 0x1AB0..=0x1AFF | 0x1DC0..=0x1DFF |
+        0x200B..=0x200F | 0x20D0..=0x20FF | 0xFE00..=0xFE0F | 0xFE20..=0xFE2F |
+        0xE0100..=0xE01EF => 0,
+        0x1100..=0x115F | 0x2E80..=0x303E | 0x3041..=0x33FF | 0x3400..=0x4DBF |
+        0x4E00..=0x9FFF | 0xA000..=0xA4CF | 0xAC00..=0xD7A3 | 0xF900..=0xFAFF |
+        0xFE30..=0xFE4F | 0xFF00..=0xFF60 | 0xFFE0..=0xFFE6 | 0x1F300..=0x1F64F |
+        0x1F900..=0x1F9FF | 0x1FA70..=0x1FAFF | 0x20000..=0x2FFFD | 0x30000..=0x3FFFD => 2,
+        _ if c.is_control() => 0,
+        _ => 1,
+    }
+}
+
+// Number of columns text takes up on the terminal, leaving out escape sequences
+pub fn visible_width(text: &str) -> usize {
+    cells(text, "").iter().map(|c| c.width).sum()
+}
+
+// Number of terminal rows the lines of `text` take up once they wrap
+pub fn text_rows(text: &str, columns: usize) -> usize {
+    let columns = columns.max(1);
+    text.lines()
+        .map(|line| visible_width(line).div_ceil(columns))
+        .map(|rows| rows.max(1))
+        .sum()
+}
+
+// One character on the screen along with the escapes that style it
+#[derive(Clone, PartialEq)]
+pub struct Cell {
+    pub text: String,
+    pub style: String,
+    pub width: usize,
+}
+
+pub type Row = Vec<Cell>;
+
+// Splits text containing SGR escapes into cells. Other escape sequences are dropped, and
+// combining characters stay with the character before them.
+pub fn cells(text: &str, style: &str) -> Vec<Cell> {
+    let mut cells: Vec<Cell> = Vec::new();
+    let mut style = style.to_owned();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            if chars.peek() == Some(&'[') {
+                let mut sequence = String::from("\x1b");
+                for c in chars.by_ref() {
+                    sequence.push(c);
+                    if c.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+                if sequence == "\x1b[0m" || sequence == "\x1b[m" {
+                    style.clear();
+                } else if sequence.ends_with('m') {
+                    style.push_str(&sequence);
+                }
+            }
+            continue;
+        }
+        let c = if c == '\t' { ' ' } else { c };
+        match char_width(c) {
+            0 if c.is_control() => {}
+            0 => {
+                if let Some(last) = cells.last_mut() {
+                    last.text.push(c);
+                }
+            }
+            width => {
+                cells.push(Cell {
+                    text: c.to_string(),
+                    style: style.clone(),
+                    width,
+                })
+            }
+        }
+    }
+    cells
+}
+
+// Lays cells out into rows of the terminal width, wrapping long lines, and works out where
+// the cursor goes
+pub struct Layout {
+    columns: usize,
+    pub rows: Vec<Row>,
+    column: usize,
+    cursor: Option<(usize, usize)>,
+    // The cursor goes wherever the next cell lands, which is on the next row if it wraps
+    cursor_next: bool,
+}
+
+impl Layout {
+    pub fn new(columns: usize) -> Layout {
+        Layout {
+            columns: columns.max(1),
+            rows: vec![Vec::new()],
+            column: 0,
+            cursor: None,
+            cursor_next: false,
+        }
+    }
+
+    pub fn push(&mut self, cells: Vec<Cell>) {
+        for cell in cells {
+            if self.column + cell.width > self.columns && self.column > 0 {
+                self.new_row();
+            }
+            if self.cursor_next {
+                self.cursor = Some((self.rows.len() - 1, self.column));
+                self.cursor_next = false;
+            }
+            self.column += cell.width;
+            self.rows.last_mut().unwrap().push(cell);
+        }
+    }
+
+    pub fn mark_cursor(&mut self) {
+        self.cursor_next = true;
+    }
+
+    // Ends a line of text. A cursor after the last cell of a line that filled its last row
+    // gets a row of its own, the way the terminal would wrap it.
+    pub fn end_line(&mut self) {
+        if self.cursor_next {
+            if self.column >= self.columns {
+                self.new_row();
+            }
+            self.cursor = Some((self.rows.len() - 1, self.column));
+            self.cursor_next = false;
+        }
+    }
+
+    pub fn new_row(&mut self) {
+        self.rows.push(Vec::new());
+        self.column = 0;
+    }
+
+    // Puts cells at the right edge of a row, leaving the last column free so the terminal
+    // doesn't wrap, if there is room for them after a gap
+    pub fn right_align(&mut self, row: usize, cells: Vec<Cell>) -> bool {
+        let used: usize = self.rows[row].iter().map(|c| c.width).sum();
+        let width: usize = cells.iter().map(|c| c.width).sum();
+        if width == 0 || used + 1 + width >= self.columns {
+            return false;
+        }
+        for _ in used..self.columns - width - 1 {
+            self.rows[row].push(Cell {
+                text: " ".to_owned(),
+                style: String::new(),
+                width: 1,
+            });
+        }
+        self.rows[row].extend(cells);
+        true
+    }
+
+    pub fn finish(mut self) -> (Vec<Row>, (usize, usize)) {
+        self.end_line();
+        let cursor = self.cursor.unwrap_or((0, 0));
+        (self.rows, cursor)
+    }
+}
+
+fn move_to(output: &mut String, from: (usize, usize), to: (usize, usize)) {
+    if to.0 < from.0 {
+        output.push_str(&format!("\x1b[{}A", from.0 - to.0));
+    } else if to.0 > from.0 {
+        output.push_str(&format!("\x1b[{}B", to.0 - from.0));
+    }
+    output.push('\r');
+    if to.1 > 0 {
+        output.push_str(&format!("\x1b[{}C", to.1));
+    }
+}
+
+fn write_cells(output: &mut String, cells: &[Cell]) {
+    let mut style = "";
+    for cell in cells {
+        if cell.style != style {
+            output.push_str("\x1b[0m");
+            output.push_str(&cell.style);
+            style = &cell.style;
+        }
+        output.push_str(&cell.text);
+    }
+    if !style.is_empty() {
+        output.push_str("\x1b[0m");
+    }
+}
+
+// What is on the terminal below the rows that are printed once, kept so that a redraw only
+// has to write what changed. Rows and the cursor are counted from the top of the frame.
+#[derive(Default)]
+pub struct Screen {
+    frame: Vec<Row>,
+    cursor: (usize, usize),
+}
+
+impl Screen {
+    // Escapes that turn the current frame into `rows` and put the cursor at `cursor`
+    pub fn draw(&mut self, rows: Vec<Row>, cursor: (usize, usize), columns: usize) -> String {
+        let mut output = String::new();
+        let mut position = self.cursor;
+        let first = (0..rows.len()).find(|&r| self.frame.get(r) != Some(&rows[r]));
+        if let Some(first) = first {
+            let old: &[Cell] = self.frame.get(first).map(|r| r.as_slice()).unwrap_or(&[]);
+            let same = rows[first].iter().zip(old).take_while(|&(a, b)| a == b).count();
+            let column = rows[first][..same].iter().map(|c| c.width).sum();
+            if first < self.frame.len().max(1) {
+                move_to(&mut output, position, (first, column));
+            } else {
+                // A row below everything drawn so far, which may scroll the terminal
+                move_to(&mut output, position, (first - 1, 0));
+                output.push_str("\r\n");
+            }
+            for (r, row) in rows.iter().enumerate().skip(first) {
+                if r > first {
+                    output.push_str("\r\n");
+                }
+                let start = if r == first { same } else { 0 };
+                write_cells(&mut output, &row[start..]);
+                if row.iter().map(|c| c.width).sum::<usize>() < columns {
+                    output.push_str("\x1b[K");
+                }
+            }
+            position = (rows.len() - 1, 0);
+        }
+        if self.frame.len() > rows.len() {
+            move_to(&mut output, position, (rows.len(), 0));
+            output.push_str("\x1b[J");
+            position = (rows.len(), 0);
+        }
+        move_to(&mut output, position, cursor);
+        self.frame = rows;
+        self.cursor = cursor;
+        output
+    }
+
+    // Moves below the frame and forgets it, so the next draw starts on a fresh row
+    pub fn leave(&mut self) -> String {
+        let mut output = String::new();
+        let last = self.frame.len().saturating_sub(1);
+        move_to(&mut output, self.cursor, (last, 0));
+        if self.frame.get(last).is_some_and(|row| !row.is_empty()) {
+            output.push_str("\r\n");
+        }
+        self.reset();
+        output
+    }
+
+    // Clears the frame along with `above` rows printed before it, leaving the cursor where
+    // the first of them was
+    pub fn erase(&mut self, above: usize) -> String {
+        let mut output = String::new();
+        let rows = self.cursor.0 + above;
+        if rows > 0 {
+            output.push_str(&format!("\x1b[{}A", rows));
+        }
+        output.push_str("\r\x1b[J");
+        self.reset();
+        output
+    }
+
+    pub fn reset(&mut self) {
+        self.frame.clear();
+        self.cursor = (0, 0);
+    }
+}
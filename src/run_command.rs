@@ -1,82 +1,167 @@
 extern crate std;
-extern crate hostname;
-extern crate glob;
+extern crate libc;
 
-use std::str;
-use std::ffi::OsString; // Probably want OsStr in a few places
-use std::path::Path;
+use std::ffi::OsString;
+use std::io;
+use std::io::Write;
+use std::rc::Rc;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
 use std::fs;
+use error;
 use state::ShellState;
+use exec;
+
+// Commands the shell runs itself instead of looking them up in PATH
+pub const BUILTINS: &[&str] = &[
+    "cd", "echo", "exit", "history", "fc", "source", ".", "alias", "unalias", "bind", "complete",
+    "return", "break", "continue", "true", "false", ":", "export", "unset", "shift",
+    "set", "setopt", "unsetopt", "test", "[", "declare", "typeset",
+];
+
+// A builtin added by a program embedding the shell, given its arguments without the name and
+// returning the exit status
+pub type Builtin = Rc<dyn Fn(&mut ShellState, &[String]) -> i32>;
 
 impl ShellState {
-    pub fn run_command(&self, command: &str, args: std::str::SplitWhitespace) {
+    // Runs a line of input and returns its exit status
+    pub fn run_line(&mut self, line: &str) -> i32 {
+        let status = self.run_script(line, None);
+        // break or return outside of anything they could leave does nothing
+        self.flow = None;
+        status
+    }
 
-        // Very crude glob support
-        let mut expanded_args = Vec::new();
-        for arg in args {
-            if !arg.contains('*') {
-                expanded_args.push(OsString::from(arg));
-                continue;
+    pub fn is_builtin(&self, name: &str) -> bool {
+        BUILTINS.contains(&name) || self.is_structured(name) || self.custom_builtins.contains_key(name)
+    }
+
+    // Runs a builtin, or gives None if there is no builtin called `name`
+    pub fn run_builtin(&mut self, name: &str, args: &[String]) -> Option<i32> {
+        let result = match name {
+            _ if self.custom_builtins.contains_key(name) => {
+                let builtin = Rc::clone(&self.custom_builtins[name]);
+                Ok(builtin(self, args))
             }
+            _ if self.is_structured(name) => self.run_structured(name, args),
+            "cd" => self.cd(args),
+            "echo" => self.echo(args),
+            "exit" => {
+                let status = match args.first() {
+                    Some(status) => status.parse().unwrap_or(2),
+                    None => self.last_status,
+                };
+                Ok(self.leave(status))
+            }
+            "history" => self.history_builtin(args),
+            "fc" => self.fc(args),
+            "source" | "." => self.source(args),
+            "alias" => self.alias(args),
+            "unalias" => self.unalias(args),
+            "bind" => self.bind(args),
+            "complete" => self.complete(args),
+            "return" | "break" | "continue" => self.control_flow(name, args),
+            "true" | ":" => Ok(0),
+            "false" => Ok(1),
+            "export" => self.export(args),
+            "unset" => self.unset(args),
+            "shift" => self.shift(args),
+            "set" => self.set(args),
+            "setopt" => self.setopt(args, true),
+            "unsetopt" => self.setopt(args, false),
+            "test" | "[" => self.test(name, args),
+            "declare" | "typeset" => self.declare(args, Vec::new()),
+            _ => return None,
+        };
+        Some(self.builtin_status(name, result))
+    }
 
-            let mut pattern = self.variables.get("PWD").unwrap().clone();
-            pattern.push(arg);
-            match glob::glob(pattern.to_str().unwrap()) {
-                Ok(result_iter) => {
-                    for entry in result_iter.filter_map(|e| e.ok()) {
-                        expanded_args.push(entry.as_os_str().to_owned());
-                    }
-                }
-                Err(..) => expanded_args.push(OsString::from(arg)),
+    // The status of a builtin that has finished, reporting what went wrong if it failed
+    pub fn builtin_status(&self, name: &str, result: error::Result<i32>) -> i32 {
+        match result {
+            Ok(status) => status,
+            Err(error) => {
+                self.report(&format!("{}: {}", name, error));
+                1
             }
         }
+    }
 
-        if command == "ls" || command == "grep" {
-            expanded_args.push(OsString::from("--color=auto"));
+    // Full path of the program a command name refers to
+    pub fn find_command(&self, command: &str) -> Option<PathBuf> {
+        if command.contains('/') {
+            let path = Path::new(&self.variable("PWD").unwrap_or_default()).join(command);
+            return Some(path).filter(|p| p.is_file());
         }
+        let path = self.variable("PATH").unwrap_or_default();
+        path.split(':')
+            .map(|dir| Path::new(dir).join(command))
+            .find(|candidate| {
+                fs::metadata(candidate).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+            })
+    }
 
-        if Path::new(command).is_file() {
-            match Command::new(Path::new(command))
-                .args(expanded_args)
-                .current_dir(self.variables.get("PWD").unwrap().clone())
-                .spawn() {
-                Ok(mut child) => {
-                    child.wait().unwrap();
-                    ()
-                } // This should be an unwrap_or_else
-                Err(_) => println!("command failed to launch: {}", command),
-            };
-            return;
-        }
+    // Starts an external program, with the file descriptors the shell has at the moment,
+    // giving its pid or the status to use when it couldn't be started
+    pub fn spawn(&mut self, arguments: &[String], values: &[(String, String)]) -> Result<i32, i32> {
+        let command = &arguments[0];
+        let program = match self.find_command(command) {
+            Some(program) => program,
+            None => {
+                self.report(&format!("command not found: {}", command));
+                return Err(127);
+            }
+        };
 
-        let path = self.variables.get("PATH").unwrap().clone();
+        let mut args: Vec<OsString> = arguments[1..].iter().map(OsString::from).collect();
+        if command == "ls" || command == "grep" {
+            args.push(OsString::from("--color=auto"));
+        }
 
-        for entries in path.into_string()
-            .unwrap()
-            .split(':')
-            .map(|dir| fs::read_dir(Path::new(dir)))
-            .filter_map(|e| e.ok())
-        {
-            // loop over the iterator of every directory in PATH that's possible to read
-            for dir_entry in entries
-                .filter_map(|e| e.ok()) // Only entries that are ok
-                .filter(|e| &e.file_name() == command)
-            {
-                // Check if entry filename matches
-                match Command::new(dir_entry.path())
-                    .args(expanded_args)
-                    .current_dir(self.variables.get("PWD").unwrap().clone())
-                    .spawn() {
-                    Ok(mut child) => {
-                        child.wait().unwrap();
-                        ()
-                    } // This should be an unwrap_or_else
-                    Err(_) => println!("command failed to launch: {}", command),
-                };
-                return;
+        let mut process = Command::new(&program);
+        process
+            .args(args)
+            .envs(values.iter().map(|(name, value)| (name, value)))
+            .current_dir(self.variable("PWD").unwrap_or_default());
+        // Only async-signal-safe calls happen between the fork and the exec
+        unsafe {
+            process.pre_exec(|| {
+                exec::default_interrupts();
+                Ok(())
+            });
+        }
+        match process.spawn() {
+            Ok(child) => Ok(child.id() as i32),
+            Err(_) => {
+                self.report(&format!("command failed to launch: {}", program.to_string_lossy()));
+                Err(126)
             }
         }
-        println!("command not found: {}", command);
+    }
+
+    // Reports background jobs that have finished and forgets about them
+    pub fn reap_jobs(&mut self) {
+        self.helpers.retain(|&pid| unsafe { libc::waitpid(pid, std::ptr::null_mut(), libc::WNOHANG) } == 0);
+        let mut number = 0;
+        self.jobs.retain(|job| {
+            number += 1;
+            let mut status = 0;
+            match unsafe { libc::waitpid(job.pid, &mut status, libc::WNOHANG) } {
+                0 => true,
+                -1 => false,
+                _ => {
+                    let code = exec::exit_status(status);
+                    let state = if code == 0 {
+                        "done".to_owned()
+                    } else {
+                        format!("exit {}", code)
+                    };
+                    let _ = writeln!(io::stdout(), "[{}]  {:<8} {}", number, state, job.command);
+                    false
+                }
+            }
+        });
     }
 }
@@ -0,0 +1,95 @@
+extern crate std;
+
+// SHA-1, which git uses to name objects
+
+pub struct Sha1 {
+    state: [u32; 5],
+    buffer: Vec<u8>,
+    length: u64,
+}
+
+impl Sha1 {
+    pub fn new() -> Sha1 {
+        Sha1 {
+            state: [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476, 0xC3D2_E1F0],
+            buffer: Vec::with_capacity(64),
+            length: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+        if !self.buffer.is_empty() {
+            let take = (64 - self.buffer.len()).min(data.len());
+            self.buffer.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.buffer.len() < 64 {
+                return;
+            }
+            let block = std::mem::take(&mut self.buffer);
+            self.block(&block);
+        }
+        let mut blocks = data.chunks_exact(64);
+        for block in blocks.by_ref() {
+            self.block(block);
+        }
+        self.buffer.extend_from_slice(blocks.remainder());
+    }
+
+    pub fn finish(mut self) -> [u8; 20] {
+        let bits = self.length.wrapping_mul(8);
+        let mut padding = vec![0x80u8];
+        while (self.buffer.len() + padding.len()) % 64 != 56 {
+            padding.push(0);
+        }
+        padding.extend_from_slice(&bits.to_be_bytes());
+        let length = self.length;
+        self.update(&padding);
+        self.length = length;
+
+        let mut digest = [0u8; 20];
+        for (i, word) in self.state.iter().enumerate() {
+            digest[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn block(&mut self, block: &[u8]) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = self.state;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let temp = a.rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e].iter()) {
+            *state = state.wrapping_add(*value);
+        }
+    }
+}
+
+impl Default for Sha1 {
+    fn default() -> Self {
+        Sha1::new()
+    }
+}
@@ -1,317 +1,767 @@
 extern crate std;
-extern crate termcolor;
 extern crate termios;
+extern crate libc;
 
-use std::iter;
 use std::str;
 use std::fs;
 use std::io;
-use std::io::{Write, Read};
 use std::io::Error;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
 use self::termios::{Termios, TCSANOW, tcsetattr};
-use self::termcolor::{Color, ColorChoice, ColorSpec, WriteColor};
 use circular_buffer::CircularBuffer;
+use parser;
+use history;
+use history::{HistoryEntry, HistoryFile};
 use envars::Envars;
+use exec::Flow;
+use parser::Command;
+use highlight::Highlighter;
+use git::GitStatus;
+use keymap;
+use keymap::{Keymap, Widget};
+use completion::Completion;
+use options::Options;
+use arrays::Array;
+use render;
+use run_command::Builtin;
+use render::{Layout, Row, Screen};
+
+const SUGGESTION_STYLE: &str = "\x1b[35m";
 
 pub struct ShellState {
     pub variables: Envars,
-    pub history: CircularBuffer<String>,
+    pub history: CircularBuffer<HistoryEntry>,
+    pub history_file: HistoryFile,
+    // Set when fc reruns commands, which go in the history instead of the fc command
+    pub history_rerun: bool,
+    pub jobs: Vec<Job>,
+    // Exit status and running time of the last command, for the prompt
+    pub last_status: i32,
+    pub last_duration: Duration,
+    pub last_background: Option<i32>,
+    pub aliases: HashMap<String, String>,
+    pub functions: HashMap<String, Rc<Command>>,
+    // Indexed and associative arrays, which are kept out of the environment
+    pub arrays: HashMap<String, Array>,
+    // $1 onwards, of the script or function being run
+    pub arguments: Vec<String>,
+    pub flow: Option<Flow>,
+    // File of the script being run and the line of the command in it, for errors
+    pub script: Option<String>,
+    pub line: usize,
+    // Line editor key bindings and argument completions, set up with bind and complete
+    pub keymap: Keymap,
+    pub completions: HashMap<String, Completion>,
+    // Login shells run the logout scripts when they exit
+    pub login: bool,
+    pub interactive: bool,
+    pub options: Options,
+    // $0, the name of the shell or of the script it runs
+    pub name: String,
+    // How many if, while and && conditions are being tested, where -e lets commands fail
+    pub conditions: usize,
+    // Set when an expansion fails, so the command it was for doesn't run
+    pub expansion_failed: bool,
+    // The status of the last $(...) in the command being expanded, which is the status of a
+    // command that only assigns
+    pub substitution_status: Option<i32>,
+    // How many function calls are under way, which FUNCNEST limits
+    pub function_depth: usize,
+    // The processes of <(...) and >(...) in the command being run, and the shell's ends of
+    // their pipes
+    pub substitutions: Vec<(libc::pid_t, i32)>,
+    // Those of commands that were left running, to wait for later
+    pub helpers: Vec<libc::pid_t>,
+    // Run from inside another program, with builtins of its own
+    pub embedded: bool,
+    pub custom_builtins: HashMap<String, Builtin>,
+}
+
+impl ShellState {
+    pub fn new(variables: Envars) -> ShellState {
+        ShellState {
+            variables,
+            history: CircularBuffer::new(10000),
+            history_file: Default::default(),
+            history_rerun: false,
+            jobs: Vec::new(),
+            last_status: 0,
+            last_duration: Duration::from_secs(0),
+            last_background: None,
+            aliases: HashMap::new(),
+            functions: HashMap::new(),
+            arrays: HashMap::new(),
+            arguments: Vec::new(),
+            flow: None,
+            script: None,
+            line: 0,
+            keymap: keymap::default_keymap(),
+            completions: HashMap::new(),
+            login: false,
+            interactive: false,
+            options: Options::default(),
+            name: "rsh".to_owned(),
+            conditions: 0,
+            expansion_failed: false,
+            substitution_status: None,
+            function_depth: 0,
+            substitutions: Vec::new(),
+            helpers: Vec::new(),
+            embedded: false,
+            custom_builtins: HashMap::new(),
+        }
+    }
+}
+
+// A command started in the background with &
+pub struct Job {
+    pub command: String,
+    pub pid: i32,
 }
 
 pub struct PromptState {
     pub input_buffer: String,
+    // Text that tab would append to the input buffer
     pub suggestion: String,
+    // Byte offset of the cursor into input_buffer
     pub cursor_position: usize,
-    pub total_len: usize,
+    // Last line of the rendered prompt, which is redrawn with the input, and the lines of
+    // the prompt printed above it
+    pub prompt_line: String,
+    pub prompt_above: String,
+    // Rendered RPROMPT and PS2
+    pub rprompt: String,
+    pub continuation: String,
+    // Line shown below the input, like the incremental search query
+    pub status: String,
+    pub highlighter: Highlighter,
+    // The repository as the prompt showed it, for every template it rendered
+    pub git: Option<GitStatus>,
+    screen: Screen,
 }
 
 impl PromptState {
-    pub fn clear_suggestion(&self) {
-        print!("{}\r", " ".repeat(self.total_len));
-        print!("{}", self.input_buffer);
+    pub fn new() -> Self {
+        PromptState {
+            input_buffer: String::new(),
+            suggestion: String::new(),
+            cursor_position: 0,
+            prompt_line: String::new(),
+            prompt_above: String::new(),
+            rprompt: String::new(),
+            continuation: String::new(),
+            status: String::new(),
+            highlighter: Highlighter::default(),
+            git: None,
+            screen: Screen::default(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.input_buffer.clear();
+        self.suggestion.clear();
+        self.cursor_position = 0;
+    }
+
+    // Row and column (in chars) of the cursor within the input buffer
+    fn cursor_coords(&self) -> (usize, usize) {
+        let before = &self.input_buffer[..self.cursor_position];
+        let row = before.matches('\n').count();
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        (row, before[line_start..].chars().count())
+    }
+
+    // Byte offsets of the start and end of the line the cursor is on
+    fn line_bounds(&self) -> (usize, usize) {
+        let start = self.input_buffer[..self.cursor_position]
+            .rfind('\n')
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let end = self.input_buffer[self.cursor_position..]
+            .find('\n')
+            .map(|i| self.cursor_position + i)
+            .unwrap_or(self.input_buffer.len());
+        (start, end)
+    }
+
+    pub fn is_multiline(&self) -> bool {
+        self.input_buffer.contains('\n')
+    }
+
+    pub fn on_first_line(&self) -> bool {
+        !self.input_buffer[..self.cursor_position].contains('\n')
+    }
+
+    pub fn on_last_line(&self) -> bool {
+        !self.input_buffer[self.cursor_position..].contains('\n')
+    }
+
+    pub fn insert(&mut self, c: char) {
+        self.input_buffer.insert(self.cursor_position, c);
+        self.cursor_position += c.len_utf8();
+    }
+
+    pub fn move_left(&mut self) {
+        if let Some(c) = self.input_buffer[..self.cursor_position].chars().next_back() {
+            self.cursor_position -= c.len_utf8();
+        }
+    }
+
+    pub fn move_right(&mut self) {
+        if let Some(c) = self.input_buffer[self.cursor_position..].chars().next() {
+            self.cursor_position += c.len_utf8();
+        }
+    }
+
+    // Moves to the same column on the previous line, or to its end if it is shorter
+    pub fn move_up(&mut self) {
+        let (start, _) = self.line_bounds();
+        if start == 0 {
+            return;
+        }
+        let column = self.cursor_coords().1;
+        let prev_start = self.input_buffer[..start - 1]
+            .rfind('\n')
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        self.cursor_position = column_offset(&self.input_buffer[prev_start..start - 1], column) +
+            prev_start;
+    }
+
+    pub fn move_down(&mut self) {
+        let (_, end) = self.line_bounds();
+        if end == self.input_buffer.len() {
+            return;
+        }
+        let column = self.cursor_coords().1;
+        let next_start = end + 1;
+        let next_end = self.input_buffer[next_start..]
+            .find('\n')
+            .map(|i| next_start + i)
+            .unwrap_or(self.input_buffer.len());
+        self.cursor_position = column_offset(&self.input_buffer[next_start..next_end], column) +
+            next_start;
+    }
+
+    pub fn move_line_start(&mut self) {
+        self.cursor_position = self.line_bounds().0;
+    }
+
+    pub fn move_line_end(&mut self) {
+        self.cursor_position = self.line_bounds().1;
+    }
+
+    pub fn delete_back(&mut self) {
+        if self.cursor_position > 0 {
+            self.move_left();
+            self.input_buffer.remove(self.cursor_position);
+        }
+    }
+
+    pub fn delete_forward(&mut self) {
+        if self.cursor_position < self.input_buffer.len() {
+            self.input_buffer.remove(self.cursor_position);
+        }
+    }
+
+    // Deletes from the cursor to the end of its line
+    pub fn kill_line(&mut self) {
+        let (_, end) = self.line_bounds();
+        self.input_buffer.drain(self.cursor_position..end);
+    }
+
+    // Deletes from the start of the line to the cursor
+    pub fn backward_kill_line(&mut self) {
+        let (start, _) = self.line_bounds();
+        self.input_buffer.drain(start..self.cursor_position);
+        self.cursor_position = start;
+    }
+
+    // Deletes the word before the cursor along with the whitespace after it
+    pub fn backward_kill_word(&mut self) {
+        let before = &self.input_buffer[..self.cursor_position];
+        let start = before.trim_end().rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0);
+        self.input_buffer.drain(start..self.cursor_position);
+        self.cursor_position = start;
+    }
+
+    pub fn replace(&mut self, contents: &str) {
+        self.input_buffer.clear();
+        self.input_buffer.push_str(contents);
+        self.cursor_position = self.input_buffer.len();
+        self.suggestion.clear();
+    }
+
+    // Lays out the last line of the prompt, the input with PS2 before each continuation line,
+    // the suggestion, RPROMPT and the status line
+    fn layout(&self, columns: usize) -> (Vec<Row>, (usize, usize)) {
+        let mut layout = Layout::new(columns);
+        let spans = self.highlighter.highlight(&self.input_buffer);
+        let mut first_line_rows = None;
+        let mut offset = 0;
+        for (i, line) in self.input_buffer.split('\n').enumerate() {
+            if i > 0 {
+                layout.end_line();
+                first_line_rows.get_or_insert(layout.rows.len());
+                layout.new_row();
+                layout.push(render::cells(&self.continuation, ""));
+            } else {
+                layout.push(render::cells(&self.prompt_line, ""));
+            }
+            let end = offset + line.len();
+            if self.status.is_empty() && offset <= self.cursor_position && self.cursor_position <= end {
+                self.push_input(&mut layout, &spans, offset, self.cursor_position);
+                layout.mark_cursor();
+                self.push_input(&mut layout, &spans, self.cursor_position, end);
+            } else {
+                self.push_input(&mut layout, &spans, offset, end);
+            }
+            offset = end + 1;
+        }
+        if !self.suggestion.is_empty() && self.cursor_position == self.input_buffer.len() {
+            layout.push(render::cells(&self.suggestion, SUGGESTION_STYLE));
+        }
+        layout.end_line();
+
+        // The right prompt only goes next to a first line that fits on one row
+        if first_line_rows.unwrap_or(layout.rows.len()) == 1 {
+            layout.right_align(0, render::cells(&self.rprompt, ""));
+        }
+        if !self.status.is_empty() {
+            layout.new_row();
+            layout.push(render::cells(&self.status, ""));
+            layout.mark_cursor();
+        }
+        layout.finish()
+    }
+
+    // Adds input_buffer[start..end] to the layout in the colors of the highlighted spans
+    fn push_input(
+        &self,
+        layout: &mut Layout,
+        spans: &[(usize, usize, &str)],
+        start: usize,
+        end: usize,
+    ) {
+        for &(span_start, span_end, style) in spans {
+            let (from, to) = (span_start.max(start), span_end.min(end));
+            if from < to {
+                layout.push(render::cells(&self.input_buffer[from..to], style));
+            }
+        }
+    }
+
+    // Brings the terminal up to date with the input, rewriting only what changed
+    fn render(&mut self) {
+        let columns = render::terminal_width();
+        let (rows, cursor) = self.layout(columns);
+        render::draw(&self.screen.draw(rows, cursor, columns));
+    }
+
+    pub fn redraw(&mut self) {
+        self.status.clear();
+        self.render();
+    }
+
+    // Draws everything again from the top of the input, after the terminal has been resized
+    // and may have rewrapped what was on it
+    pub fn resize(&mut self) {
+        render::draw(&self.screen.erase(0));
+        self.render();
+    }
+
+    // Replaces the whole prompt, including the rows above the input, with a short one
+    pub fn collapse(&mut self, transient: String) {
+        let above = render::text_rows(&self.prompt_above, render::terminal_width());
+        render::draw(&self.screen.erase(above));
+        self.prompt_line = transient;
+        self.prompt_above.clear();
+        self.rprompt.clear();
+    }
+
+    // Clears the terminal so the prompt can be drawn again at the top
+    pub fn clear_screen(&mut self) {
+        render::draw("\x1b[H\x1b[2J");
+        self.screen.reset();
+    }
+
+    // Moves the terminal cursor below the last line of the input
+    pub fn finish(&mut self) {
+        self.suggestion.clear();
+        self.cursor_position = self.input_buffer.len();
+        self.redraw();
+        render::draw(&self.screen.leave());
+    }
+
+    // Shows a line of text below the input with the terminal cursor at its end, until the
+    // next redraw
+    pub fn show_status(&mut self, status: &str) {
+        self.status = status.to_owned();
+        self.render();
     }
 }
 
-fn getchar_raw() -> Result<u8, Error> {
+impl Default for PromptState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Byte offset of the given char column within a line, clamped to the end of the line
+fn column_offset(line: &str, column: usize) -> usize {
+    line.char_indices()
+        .nth(column)
+        .map(|(i, _)| i)
+        .unwrap_or(line.len())
+}
+
+// Reads one byte with the terminal in raw mode. With `resizable` set, a resize of the
+// terminal window ends the wait early and gives None so the caller can redraw.
+fn read_raw(resizable: bool) -> Result<Option<u8>, Error> {
     let stdin = 0;
     let old_term = Termios::from_fd(stdin)?;
     let mut term = Termios::from_fd(stdin)?;
     termios::cfmakeraw(&mut term);
     tcsetattr(stdin, TCSANOW, &term)?;
 
-    let mut charbuf = [0; 1];
-    io::stdin().read_exact(&mut charbuf)?;
+    let mut charbuf = [0u8; 1];
+    let result = loop {
+        if resizable && render::take_resize() {
+            break Ok(None);
+        }
+        match unsafe { libc::read(stdin, charbuf.as_mut_ptr() as *mut libc::c_void, 1) } {
+            1 => break Ok(Some(charbuf[0])),
+            0 => break Err(Error::from(io::ErrorKind::UnexpectedEof)),
+            _ => {
+                let error = Error::last_os_error();
+                if error.kind() != io::ErrorKind::Interrupted {
+                    break Err(error);
+                }
+            }
+        }
+    };
     tcsetattr(stdin, TCSANOW, &old_term)?;
+    result
+}
 
-    Ok(charbuf[0])
+fn getchar_raw() -> Result<u8, Error> {
+    loop {
+        if let Some(c) = read_raw(false)? {
+            return Ok(c);
+        }
+    }
 }
 
-impl ShellState {
-    pub fn prompt(&self) {
-        #![allow(unused)]
-        let mut stdout = termcolor::StandardStream::stdout(ColorChoice::Auto);
-        let mut spec = termcolor::ColorSpec::new();
-        let mut buf = String::new();
-        let prompt = self.variables.get("PROMPT").unwrap();
-        let mut in_braces = false;
-        print!("\r");
-        for c in prompt.to_string_lossy().chars() {
-            match c {
-                '{' => {
-                    in_braces = true;
+// Reads the rest of a key whose first byte has already been read: the escape sequence of a
+// special key, or the rest of a UTF-8 character
+fn read_key(first: u8) -> Result<Vec<u8>, Error> {
+    let mut key = vec![first];
+    if first == 27 {
+        let next = getchar_raw()?;
+        key.push(next);
+        if next == b'[' || next == b'O' {
+            // Parameters and intermediate bytes until the final byte
+            loop {
+                let c = getchar_raw()?;
+                key.push(c);
+                if (0x40..=0x7e).contains(&c) {
+                    break;
                 }
-                '}' => {
-                    if buf.starts_with('$') {
-                        let (_, key) = buf.split_at(1);
-                        if let Some(val) = self.variables.get(key) {
-                            write!(&mut stdout, "{}", val.to_string_lossy());
+            }
+        }
+    } else if first >= 0xC0 {
+        let extra = if first >= 0xF0 {
+            3
+        } else if first >= 0xE0 {
+            2
+        } else {
+            1
+        };
+        for _ in 0..extra {
+            key.push(getchar_raw()?);
+        }
+    }
+    Ok(key)
+}
+
+// The character typed by a key that isn't a control key or an escape sequence
+fn typed_char(key: &[u8]) -> Option<char> {
+    let c = str::from_utf8(key).ok()?.chars().next()?;
+    if c.is_control() { None } else { Some(c) }
+}
+
+impl ShellState {
+    // Reads a line of input with the line editor. Fails if the terminal can't be put in raw
+    // mode or has gone away.
+    pub fn prompt_read(&mut self, input_buffer: &mut String) -> Result<(), Error> {
+        render::watch_resize();
+        let mut state = PromptState::new();
+        self.prompt(&mut state);
+        // Entry shown while moving through the history with up and down, and the line that was
+        // being edited before that, which also filters the entries by prefix
+        let mut history_index: Option<usize> = None;
+        let mut saved_line = String::new();
+        // Key that ended an incremental search and still needs to be handled
+        let mut pending: Option<Vec<u8>> = None;
+
+        loop {
+            let key = match pending.take() {
+                Some(key) => key,
+                None => {
+                    match read_raw(true)? {
+                        Some(c) => read_key(c)?,
+                        None => {
+                            state.resize();
+                            continue;
                         }
-                    } else {
-                        match buf.as_ref() {
-                            "BOLD" => {
-                                spec.set_bold(true);
-                                stdout.set_color(&spec);
-                            }
-                            "WHITE" => {
-                                spec.set_fg(Some(Color::White));
-                                stdout.set_color(&spec);
-                            }
-                            "RED" => {
-                                spec.set_fg(Some(Color::Red));
-                                stdout.set_color(&spec);
-                            }
-                            "GREEN" => {
-                                spec.set_fg(Some(Color::Green));
-                                stdout.set_color(&spec);
-                            }
-                            "CYAN" => {
-                                spec.set_fg(Some(Color::Cyan));
-                                stdout.set_color(&spec);
-                            }
-                            _ => {}
-                        };
                     }
-                    in_braces = false;
-                    buf.clear();
-
                 }
-                '\n' => {
-                    write!(&mut stdout, "\n\r");
-                }
-                _ => {
-                    if in_braces {
-                        buf.push(c);
-                    } else {
-                        write!(&mut stdout, "{}", c);
+            };
+            // Keys that aren't bound type themselves, unless they are control keys
+            let widget = match self.keymap.get(&key) {
+                Some(widget) => widget.clone(),
+                None => {
+                    match typed_char(&key) {
+                        Some(c) => Widget::Insert(c.to_string()),
+                        None => continue,
                     }
                 }
+            };
+            // Anything but up and down leaves the history
+            let navigation = history_index.take();
+            if widget != Widget::AcceptSuggestion {
+                state.suggestion.clear();
             }
-        }
-        stdout.reset();
-        io::stdout().flush().unwrap();
-    }
 
-    pub fn prompt_read(&mut self, input_buffer: &mut String) {
-        self.prompt();
-        input_buffer.clear();
-        let mut suggestion = String::new();
-        let mut cursor_position = 0;
-        let mut total_len = 0;
-
-        loop {
-            let c = getchar_raw().unwrap();
-
-            match c {
-                // ctrl+c clears suggestion and opens a new line
-                3 => {
-                    print!("\r╰ ➤ {}", " ".repeat(total_len));
-                    print!("\r╰ ➤ {}", input_buffer);
-                    input_buffer.clear();
-                    self.prompt();
+            match widget {
+                // Abandons the current input and opens a new prompt
+                Widget::Interrupt => {
+                    self.transient_prompt(&mut state);
+                    state.finish();
+                    state.clear();
+                    self.prompt(&mut state);
                 }
-                // ctrl+d should close the shell, only if the input buffer is empty
-                4 => {
-                    // TODO: Refactor this into an exit function? We do have at least one other way to exit
-                    if input_buffer.is_empty() {
-                        print!("\n\r");
-                        io::stdout().flush().unwrap();
-                        self.save_history();
-                        std::process::exit(0);
+                // Closes the shell, only if the input buffer is empty
+                Widget::DeleteCharOrExit => {
+                    if state.input_buffer.is_empty() {
+                        render::draw("\n\r");
+                        self.exit(0);
                     }
+                    state.delete_forward();
                 }
-                // tab inserts the current autocomplete suggestion
-                9 => {
-                    if !suggestion.is_empty() {
-                        if let Some(ind) = input_buffer.as_str().rfind(' ') {
-                            input_buffer.truncate(ind + 1);
-                        }
-                        input_buffer.push_str(suggestion.as_str());
-                        print!("\r╰ ➤ {}", input_buffer);
-                        cursor_position = input_buffer.len();
-                        total_len = input_buffer.len();
+                Widget::AcceptSuggestion => {
+                    if !state.suggestion.is_empty() {
+                        let suggestion = state.suggestion.clone();
+                        state.input_buffer.push_str(&suggestion);
+                        state.cursor_position = state.input_buffer.len();
+                        state.suggestion.clear();
                     }
                 }
-                // return/enter should append the command to history and return to the caller
-                13 => {
-                    // Clear any active suggestion
-                    print!("\r╰ ➤ {}", " ".repeat(total_len));
-                    print!("\r╰ ➤ {}", input_buffer);
-                    print!("\n\r");
-                    // Append to history if not a duplicate of the last entry
-                    if self.history.tail().unwrap_or(&"".to_owned()) != input_buffer {
-                        self.history.push(input_buffer.to_owned());
+                // Runs the command, unless the parser wants more lines
+                Widget::AcceptLine => {
+                    if parser::is_complete(&state.input_buffer) {
+                        self.transient_prompt(&mut state);
+                        state.finish();
+                        input_buffer.clear();
+                        input_buffer.push_str(&state.input_buffer);
+                        return Ok(());
                     }
-                    return;
+                    state.insert('\n');
                 }
-                // Escape character indicates an arrow key
-                27 => {
-                    getchar_raw().unwrap();
-                    let c = getchar_raw().unwrap();
-                    match c {
-                        // Up and down should move through the history but instantly be reset by any other command
-                        // Up access last entry
-                        65 => {
-                            if input_buffer.is_empty() {
-                                suggestion.clear();
-                                input_buffer.clear();
-                                input_buffer.push_str(
-                                    self.history.head().unwrap_or(&"".to_owned()),
-                                );
-                                print!("\r╰ ➤ {}", " ".repeat(total_len));
-                                print!("\r╰ ➤ {}", input_buffer);
-                                cursor_position = input_buffer.len();
-                                total_len = input_buffer.len();
-                            }
-                        }
-                        // Down
-                        //66 => { },
-                        // Right
-                        67 => {
-                            if cursor_position < input_buffer.len() {
-                                cursor_position += 1;
-                                print!("\r╰ ➤ ");
-                                for c in input_buffer.chars().chain(iter::once(' ')).take(
-                                    cursor_position,
-                                )
-                                {
-                                    print!("{}", c);
-                                }
-                            }
-                        }
-                        // Left
-                        68 => {
-                            if cursor_position > 0 {
-                                cursor_position -= 1;
-                                print!("\r╰ ➤ ");
-                                for c in input_buffer.chars().take(cursor_position) {
-                                    print!("{}", c);
-                                }
-                            }
+                // Moves through a multi-line buffer, then to older history entries
+                Widget::UpLineOrHistory => {
+                    history_index = navigation;
+                    if !state.on_first_line() {
+                        state.move_up();
+                    } else {
+                        if navigation.is_none() {
+                            saved_line = state.input_buffer.clone();
                         }
-                        _ => {}
+                        history_index = self.history_step(&mut state, navigation, &saved_line, true)
+                            .or(navigation);
                     }
                 }
-                // del, which is the same char as a tilde. // TODO: figure out how to detect modifier keys
-                126 => {
-                    if !input_buffer.is_empty() && cursor_position < input_buffer.len() {
-
-                        // Purge any currently printed suggestion
-                        if !suggestion.is_empty() {
-                            print!("\r╰ ➤ {}", " ".repeat(suggestion.len()));
+                // Goes back towards the line that was being edited
+                Widget::DownLineOrHistory => {
+                    history_index = navigation;
+                    if !state.on_last_line() {
+                        state.move_down();
+                    } else if navigation.is_some() {
+                        history_index =
+                            self.history_step(&mut state, navigation, &saved_line, false);
+                        if history_index.is_none() {
+                            state.replace(&saved_line);
                         }
-
-                        input_buffer.remove(cursor_position);
-                        print!("\r╰ ➤ {} \r╰ ➤ ", input_buffer);
-                        for c in input_buffer.chars().take(cursor_position) {
-                            print!("{}", c);
-                        }
-                        total_len = input_buffer.len();
                     }
                 }
-                // backspace removes one character from the buffer
-                127 => {
-                    if cursor_position > 0 {
-                        // And input_buffer is not empty, but that should be enforced by the other rules on the cursor
-
-                        // Purge any currently printed suggestion
-                        if !suggestion.is_empty() {
-                            print!("\r╰ ➤ {}", " ".repeat(total_len));
-                        }
-
-                        input_buffer.remove(cursor_position - 1);
-                        cursor_position -= 1;
-                        print!("\r╰ ➤ {} \r╰ ➤ ", input_buffer);
-                        for c in input_buffer.chars().take(cursor_position) {
-                            print!("{}", c);
-                        }
-                        total_len = input_buffer.len();
+                Widget::ForwardChar => state.move_right(),
+                Widget::BackwardChar => state.move_left(),
+                Widget::BeginningOfLine => state.move_line_start(),
+                Widget::EndOfLine => state.move_line_end(),
+                Widget::DeleteChar => state.delete_forward(),
+                Widget::BackwardDeleteChar => state.delete_back(),
+                Widget::KillLine => state.kill_line(),
+                Widget::BackwardKillLine => state.backward_kill_line(),
+                Widget::BackwardKillWord => state.backward_kill_word(),
+                Widget::ClearScreen => {
+                    state.clear_screen();
+                    self.prompt(&mut state);
+                }
+                Widget::SearchBackward | Widget::SearchForward => {
+                    pending = self.search_mode(&mut state, widget == Widget::SearchBackward)?;
+                }
+                Widget::Insert(text) => {
+                    for c in text.chars() {
+                        state.insert(c);
+                    }
+                    // Only suggest completions while typing at the end of the input
+                    if state.cursor_position == state.input_buffer.len() {
+                        self.suggest(&mut state);
                     }
                 }
+            }
+            state.redraw();
+        }
+    }
 
-                // Everything else is a printable symbol and gets added to the input buffer
-                _ => {
-                    input_buffer.push(c as char);
-
-                    // Clear currently entered text
-                    print!("\r╰ ➤ {}", " ".repeat(total_len));
-                    suggestion.clear();
-
-                    // Try to find a suggestion from the contents of the current working directory
-
-                    // Split off the last word in the input buffer
-                    // This unwrap is safe because rsplitn always yields at least one element
-                    let last_word = input_buffer.as_str().rsplitn(2, ' ').next().unwrap();
-                    if !last_word.is_empty() && !last_word.starts_with('-') {
-                        match self.find_match_directory(last_word) {
-                            Some(dirmatch) => {
-                                suggestion.push_str(dirmatch.as_str());
-                                let mut stdout =
-                                    termcolor::StandardStream::stdout(termcolor::ColorChoice::Auto);
-                                stdout
-                                    .set_color(ColorSpec::new().set_fg(Some(Color::Magenta)))
-                                    .unwrap();
-                                let print_this: String =
-                                    suggestion.chars().skip(last_word.len()).collect();
-                                write!(
-                                    &mut stdout,
-                                    "\r╰ ➤ {}{}",
-                                    " ".repeat(input_buffer.len()),
-                                    print_this
-                                ).unwrap();
-                                stdout.reset().unwrap();
-                                total_len = (input_buffer.len() - last_word.len()) +
-                                    suggestion.len();
-                            }
-                            None => {
-                                if let Some(histmatch) = self.find_match_history(input_buffer) {
-                                    suggestion.push_str(&histmatch);
-                                    let mut stdout = termcolor::StandardStream::stdout(
-                                        termcolor::ColorChoice::Auto,
-                                    );
-                                    stdout
-                                        .set_color(ColorSpec::new().set_fg(Some(Color::Magenta)))
-                                        .unwrap();
-                                    write!(&mut stdout, "\r╰ ➤ {}", suggestion).unwrap();
-                                    stdout.reset().unwrap();
-                                    total_len = suggestion.len();
-                                }
-                            }
-                        }
+    // Loads the next older or newer history entry starting with `prefix` into the input
+    fn history_step(
+        &self,
+        state: &mut PromptState,
+        current: Option<usize>,
+        prefix: &str,
+        older: bool,
+    ) -> Option<usize> {
+        let start = match current {
+            Some(index) if older => index.checked_sub(1),
+            Some(index) => Some(index + 1),
+            None if older => self.history.len().checked_sub(1),
+            None => None,
+        };
+        let index = start.and_then(|start| {
+            self.search_history(start, older, &state.input_buffer, |e| e.starts_with(prefix))
+        })?;
+        state.replace(self.history.get(index).map(|e| e.command.as_str()).unwrap_or(""));
+        Some(index)
+    }
+
+    // Incremental history search. Returns the key that ended the search so the caller can
+    // handle it, or None if the search was cancelled and the input restored.
+    fn search_mode(
+        &self,
+        state: &mut PromptState,
+        mut reverse: bool,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let original = state.input_buffer.clone();
+        let mut query = String::new();
+        let mut found: Option<usize> = None;
+        let mut failing = false;
+        state.suggestion.clear();
+
+        loop {
+            state.show_status(&format!(
+                "{}{}-i-search: {}_",
+                if failing { "failing " } else { "" },
+                if reverse { "bck" } else { "fwd" },
+                query
+            ));
+
+            let key = read_key(getchar_raw()?)?;
+            let repeat = match self.keymap.get(&key) {
+                Some(&Widget::SearchBackward) => Some(true),
+                Some(&Widget::SearchForward) => Some(false),
+                _ => None,
+            };
+            let start = match key.as_slice() {
+                // Repeating the search moves on to the next match in CircularBuffer order
+                _ if repeat.is_some() => {
+                    reverse = repeat == Some(true);
+                    if reverse {
+                        found.unwrap_or_else(|| self.history.len()).checked_sub(1)
+                    } else {
+                        Some(found.map_or(0, |i| i + 1))
                     }
-                    if suggestion.is_empty() {
-                        total_len += 1;
+                }
+                // ctrl+c and ctrl+g cancel the search
+                b"\x03" | b"\x07" => {
+                    state.replace(&original);
+                    return Ok(None);
+                }
+                b"\x7f" | b"\x08" => {
+                    query.pop();
+                    found = None;
+                    if query.is_empty() {
+                        state.replace(&original);
+                        failing = false;
+                        continue;
+                    }
+                    if reverse {
+                        self.history.len().checked_sub(1)
+                    } else {
+                        Some(0)
                     }
-                    print!("\r╰ ➤ ");
-                    print!("{}", input_buffer);
-                    cursor_position += 1;
                 }
+                key if typed_char(key).is_some() => {
+                    query.extend(typed_char(key));
+                    // The current match may still match the longer query
+                    Some(found.unwrap_or(if reverse {
+                        self.history.len().saturating_sub(1)
+                    } else {
+                        0
+                    }))
+                }
+                _ => return Ok(Some(key)),
+            };
+
+            let skip = if repeat.is_some() { state.input_buffer.clone() } else { String::new() };
+            match start.and_then(|start| {
+                self.search_history(start, reverse, &skip, |e| history::fuzzy_match(&query, e))
+            }) {
+                Some(index) => {
+                    state.replace(
+                        self.history.get(index).map(|e| e.command.as_str()).unwrap_or(""),
+                    );
+                    found = Some(index);
+                    failing = false;
+                }
+                None => failing = true,
+            }
+        }
+    }
+
+    // Try to find a suggestion from the command's completion, then the contents of the current
+    // working directory, then history
+    fn suggest(&self, state: &mut PromptState) {
+        // Split off the last word in the input buffer
+        // This unwrap is safe because rsplit always yields at least one element
+        let last_word = state
+            .input_buffer
+            .rsplit(|c: char| c.is_whitespace())
+            .next()
+            .unwrap();
+        let completed = self.completion_for(&state.input_buffer)
+            .and_then(|completion| self.complete_word(completion, last_word));
+        if let Some(word) = completed {
+            state.suggestion = word[last_word.len()..].to_owned();
+            return;
+        }
+        if last_word.is_empty() || last_word.starts_with('-') {
+            return;
+        }
+        if let Some(dirmatch) = self.find_match_directory(last_word) {
+            state.suggestion = dirmatch[last_word.len()..].to_owned();
+        } else if let Some(histmatch) = self.find_match_history(&state.input_buffer) {
+            // Multi-line entries would not fit on the current row
+            if !histmatch[state.input_buffer.len()..].contains('\n') {
+                state.suggestion = histmatch[state.input_buffer.len()..].to_owned();
             }
-            io::stdout().flush().unwrap(); // Always flush after getting input
         }
     }
 
     fn find_match_directory(&self, pattern: &str) -> Option<String> {
-        if let Ok(entries) = fs::read_dir(&self.variables.get("PWD").unwrap()) {
+        if let Ok(entries) = fs::read_dir(self.variable("PWD").unwrap_or_default()) {
             for entry in entries.filter_map(|e| e.ok()) {
                 if let Ok(str_name) = entry.file_name().into_string() {
                     if str_name.as_str().starts_with(pattern) {
@@ -325,8 +775,8 @@ impl ShellState {
 
     fn find_match_history(&self, pattern: &str) -> Option<String> {
         for entry in self.history.iter_rev() {
-            if entry.starts_with(pattern) && entry != pattern {
-                return Some(entry.to_owned());
+            if entry.command.starts_with(pattern) && entry.command != pattern {
+                return Some(entry.command.to_owned());
             }
         }
         None
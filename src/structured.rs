@@ -0,0 +1,386 @@
+extern crate std;
+
+use std::cmp::Ordering;
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use conditional::regex_groups;
+use exec;
+use error;
+use error::ShellError;
+use history::format_time;
+use json;
+use json::Value;
+use render::visible_width;
+use state::ShellState;
+
+// Builtins that pass tables along a pipeline instead of text, once setopt structured turns
+// them on. Between commands a table is JSON, and on the terminal it is drawn with borders.
+pub const STRUCTURED: &[&str] = &["ls", "where", "sort-by", "select", "to", "from"];
+
+const WHERE_OPERATORS: &str = "-eq -ne -lt -le -gt -ge == != =~";
+
+impl ShellState {
+    pub fn is_structured(&self, name: &str) -> bool {
+        self.options.structured && STRUCTURED.contains(&name)
+    }
+
+    pub fn run_structured(&mut self, name: &str, args: &[String]) -> error::Result<i32> {
+        let result = match name {
+            "ls" => self.list_files(args),
+            "where" => where_rows(args),
+            "sort-by" => sort_by(args),
+            "select" => select(args),
+            "to" => to(args),
+            _ => from(args),
+        };
+        match result {
+            Ok(Some(value)) => {
+                output(&value)?;
+                Ok(0)
+            }
+            Ok(None) => Ok(0),
+            Err(message) => {
+                self.report(&format!("{}: {}", name, message));
+                Ok(2)
+            }
+        }
+    }
+
+    // ls [-a] [path ...], a row for each file with its name, type, size and when it was
+    // modified
+    fn list_files(&self, args: &[String]) -> Result<Option<Value>, String> {
+        let all = args.iter().any(|a| a == "-a");
+        let mut paths: Vec<&str> = args.iter().filter(|a| *a != "-a").map(|a| a.as_str()).collect();
+        if paths.is_empty() {
+            paths.push(".");
+        }
+        let mut rows = Vec::new();
+        for path in paths {
+            let full = self.resolve_path(path);
+            let metadata = fs::symlink_metadata(&full).map_err(|e| format!("{}: {}", path, e))?;
+            if !metadata.is_dir() {
+                rows.push(file_row(path.to_owned(), &metadata));
+                continue;
+            }
+            let mut entries: Vec<_> = fs::read_dir(&full)
+                .map_err(|e| format!("{}: {}", path, e))?
+                .filter_map(|e| e.ok())
+                .collect();
+            entries.sort_by_key(|e| e.file_name());
+            for entry in entries {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if name.starts_with('.') && !all {
+                    continue;
+                }
+                let name = if path == "." {
+                    name
+                } else {
+                    Path::new(path).join(name).to_string_lossy().into_owned()
+                };
+                if let Ok(metadata) = entry.path().symlink_metadata() {
+                    rows.push(file_row(name, &metadata));
+                }
+            }
+        }
+        Ok(Some(Value::Array(rows)))
+    }
+}
+
+fn file_row(name: String, metadata: &fs::Metadata) -> Value {
+    let kind = if metadata.file_type().is_symlink() {
+        "symlink"
+    } else if metadata.is_dir() {
+        "dir"
+    } else {
+        "file"
+    };
+    Value::Object(vec![
+        ("name".to_owned(), Value::String(name)),
+        ("type".to_owned(), Value::String(kind.to_owned())),
+        ("size".to_owned(), Value::Number(metadata.len() as f64)),
+        (
+            "modified".to_owned(),
+            Value::String(format_time(metadata.mtime().max(0) as u64, "%Y-%m-%d %H:%M:%S")),
+        ),
+    ])
+}
+
+// The table coming down the pipeline. A single record counts as a table of one row.
+fn input_rows() -> Result<Vec<Value>, String> {
+    match input()? {
+        Value::Array(rows) => Ok(rows),
+        row => Ok(vec![row]),
+    }
+}
+
+fn input() -> Result<Value, String> {
+    if unsafe { libc::isatty(0) } == 1 {
+        return Err("needs input from a pipe".to_owned());
+    }
+    let mut text = String::new();
+    io::stdin().read_to_string(&mut text).map_err(|e| e.to_string())?;
+    if text.trim().is_empty() {
+        return Ok(Value::Array(Vec::new()));
+    }
+    json::parse(&text).map_err(|e| format!("input isn't structured: {}", e))
+}
+
+// Draws a table on the terminal, and passes it on as JSON anywhere else
+fn output(value: &Value) -> io::Result<()> {
+    if unsafe { libc::isatty(1) } == 1 {
+        write!(exec::output(), "{}", render(value))
+    } else {
+        writeln!(exec::output(), "{}", value)
+    }
+}
+
+// where column operator value, keeping the rows where the comparison holds. The operators are
+// -eq -ne -lt -le -gt -ge as in test, == and != for -eq and -ne, and =~ for a regular expression.
+// < <= > >= work too when quoted, since bare they are redirections. Numbers compare as numbers.
+fn where_rows(args: &[String]) -> Result<Option<Value>, String> {
+    let (column, operator, operand) = match *args {
+        [ref column, ref operator, ref operand] => (column, operator.as_str(), operand),
+        // The shell took a bare > or < and the value after it for a redirection
+        [_] => {
+            return Err(format!(
+                "usage: where column operator value: quote > and <, or use -gt and -lt, since \
+                 bare ones redirect (operators: {})",
+                WHERE_OPERATORS
+            ))
+        }
+        _ => {
+            return Err(format!("usage: where column operator value, with {}", WHERE_OPERATORS))
+        }
+    };
+    let operator = match operator {
+        "-eq" | "==" => "==",
+        "-ne" | "!=" => "!=",
+        "-lt" | "<" => "<",
+        "-le" | "<=" => "<=",
+        "-gt" | ">" => ">",
+        "-ge" | ">=" => ">=",
+        "=~" => "=~",
+        _ => return Err(format!("{}: unknown operator, use one of {}", operator, WHERE_OPERATORS)),
+    };
+    let mut kept = Vec::new();
+    for row in input_rows()? {
+        let keep = match row.get(column) {
+            Some(cell) if operator == "=~" => regex_groups(&cell.text(), operand)?.is_some(),
+            Some(cell) => {
+                let ordering = compare(cell, &operand_value(operand));
+                match operator {
+                    "==" => ordering == Ordering::Equal,
+                    "!=" => ordering != Ordering::Equal,
+                    "<" => ordering == Ordering::Less,
+                    "<=" => ordering != Ordering::Greater,
+                    ">" => ordering == Ordering::Greater,
+                    _ => ordering != Ordering::Less,
+                }
+            }
+            None => false,
+        };
+        if keep {
+            kept.push(row);
+        }
+    }
+    Ok(Some(Value::Array(kept)))
+}
+
+// What the value in a where command stands for: a number if it looks like one
+fn operand_value(operand: &str) -> Value {
+    match operand.parse() {
+        Ok(number) => Value::Number(number),
+        Err(_) => Value::String(operand.to_owned()),
+    }
+}
+
+// Numbers by value and everything else by its text, with missing values first
+fn compare(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (&Value::Number(a), &Value::Number(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+        (&Value::Null, &Value::Null) => Ordering::Equal,
+        (&Value::Null, _) => Ordering::Less,
+        (_, &Value::Null) => Ordering::Greater,
+        _ => a.text().cmp(&b.text()),
+    }
+}
+
+// sort-by [-r] column ..., sorting by the first column, then the next where those are equal
+fn sort_by(args: &[String]) -> Result<Option<Value>, String> {
+    let reverse = args.iter().any(|a| a == "-r" || a == "--reverse");
+    let columns: Vec<&String> = args.iter().filter(|a| !a.starts_with('-')).collect();
+    if columns.is_empty() {
+        return Err("usage: sort-by [-r] column ...".to_owned());
+    }
+    let mut rows = input_rows()?;
+    rows.sort_by(|a, b| {
+        let null = Value::Null;
+        let ordering = columns.iter()
+            .map(|c| compare(a.get(c).unwrap_or(&null), b.get(c).unwrap_or(&null)))
+            .find(|o| *o != Ordering::Equal)
+            .unwrap_or(Ordering::Equal);
+        if reverse { ordering.reverse() } else { ordering }
+    });
+    Ok(Some(Value::Array(rows)))
+}
+
+// select column ..., keeping only those columns, in that order
+fn select(args: &[String]) -> Result<Option<Value>, String> {
+    if args.is_empty() {
+        return Err("usage: select column ...".to_owned());
+    }
+    let rows = input_rows()?
+        .into_iter()
+        .map(|row| {
+            let fields = args.iter()
+                .map(|c| (c.clone(), row.get(c).cloned().unwrap_or(Value::Null)))
+                .collect();
+            Value::Object(fields)
+        })
+        .collect();
+    Ok(Some(Value::Array(rows)))
+}
+
+// to json prints what comes in as indented JSON text, which is never drawn as a table
+fn to(args: &[String]) -> Result<Option<Value>, String> {
+    match args.first().map(|a| a.as_str()) {
+        Some("json") => {
+            let text = input()?.pretty();
+            writeln!(exec::output(), "{}", text).map_err(|e| ShellError::Output(e).to_string())?;
+            Ok(None)
+        }
+        Some(format) => Err(format!("{}: unknown format", format)),
+        None => Err("usage: to json".to_owned()),
+    }
+}
+
+// from json reads JSON text into a table
+fn from(args: &[String]) -> Result<Option<Value>, String> {
+    match args.first().map(|a| a.as_str()) {
+        Some("json") => input().map(Some),
+        Some(format) => Err(format!("{}: unknown format", format)),
+        None => Err("usage: from json".to_owned()),
+    }
+}
+
+// Draws a value for the terminal: a list as a table with a numbered row for each item and a
+// column for each key, a record as a table of its keys and values, and anything else as its
+// text
+pub fn render(value: &Value) -> String {
+    match *value {
+        Value::Array(ref rows) if rows.is_empty() => String::new(),
+        Value::Array(ref rows) => list_table(rows),
+        Value::Object(ref fields) => record_table(fields),
+        _ => format!("{}\n", value.text()),
+    }
+}
+
+fn list_table(rows: &[Value]) -> String {
+    let mut columns: Vec<String> = Vec::new();
+    for row in rows {
+        if let Value::Object(ref fields) = *row {
+            for (key, _) in fields {
+                if !columns.contains(key) {
+                    columns.push(key.clone());
+                }
+            }
+        }
+    }
+    // Items that aren't records go in a column of their own
+    if columns.is_empty() || rows.iter().any(|r| !matches!(*r, Value::Object(_))) {
+        columns.insert(0, "value".to_owned());
+    }
+    let cells: Vec<Vec<(String, bool)>> = rows.iter()
+        .enumerate()
+        .map(|(i, row)| {
+            let mut cells = vec![(i.to_string(), true)];
+            for column in &columns {
+                let value = match *row {
+                    Value::Object(_) => row.get(column).unwrap_or(&Value::Null),
+                    _ if column == "value" => row,
+                    _ => &Value::Null,
+                };
+                cells.push((cell(value), matches!(*value, Value::Number(_))));
+            }
+            cells
+        })
+        .collect();
+    let mut header = vec!["#".to_owned()];
+    header.extend(columns);
+    let widths: Vec<usize> = header.iter()
+        .enumerate()
+        .map(|(i, name)| {
+            cells.iter()
+                .map(|row| visible_width(&row[i].0))
+                .chain(Some(visible_width(name)))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    let mut lines = vec![border('╭', '┬', '╮', &widths)];
+    let names: Vec<String> = header.iter()
+        .zip(&widths)
+        .map(|(name, &width)| heading(&pad(name, width, false)))
+        .collect();
+    lines.push(format!("│ {} │", names.join(" │ ")));
+    lines.push(border('├', '┼', '┤', &widths));
+    for row in &cells {
+        let row: Vec<String> = row.iter()
+            .zip(&widths)
+            .map(|(&(ref text, number), &width)| pad(text, width, number))
+            .collect();
+        lines.push(format!("│ {} │", row.join(" │ ")));
+    }
+    lines.push(border('╰', '┴', '╯', &widths));
+    lines.join("\n") + "\n"
+}
+
+// A record has its keys down the side
+fn record_table(fields: &[(String, Value)]) -> String {
+    let widths = [
+        fields.iter().map(|f| visible_width(&f.0)).max().unwrap_or(0),
+        fields.iter().map(|f| visible_width(&cell(&f.1))).max().unwrap_or(0),
+    ];
+    let mut lines = vec![border('╭', '┬', '╮', &widths)];
+    for (key, value) in fields {
+        lines.push(format!(
+            "│ {} │ {} │",
+            heading(&pad(key, widths[0], false)),
+            pad(&cell(value), widths[1], matches!(*value, Value::Number(_)))
+        ));
+    }
+    lines.push(border('╰', '┴', '╯', &widths));
+    lines.join("\n") + "\n"
+}
+
+fn heading(text: &str) -> String {
+    format!("\x1b[1;32m{}\x1b[0m", text)
+}
+
+fn border(left: char, middle: char, right: char, widths: &[usize]) -> String {
+    let pieces: Vec<String> = widths.iter().map(|&w| "─".repeat(w + 2)).collect();
+    format!("{}{}{}", left, pieces.join(&middle.to_string()), right)
+}
+
+fn pad(text: &str, width: usize, right: bool) -> String {
+    let padding = " ".repeat(width.saturating_sub(visible_width(text)));
+    if right {
+        padding + text
+    } else {
+        format!("{}{}", text, padding)
+    }
+}
+
+// How a value looks in a table cell, where lists and records are only summed up
+fn cell(value: &Value) -> String {
+    match *value {
+        Value::Null => String::new(),
+        Value::Array(ref values) => format!("[list {} items]", values.len()),
+        Value::Object(ref fields) => format!("{{record {} fields}}", fields.len()),
+        _ => value.text().replace('\n', " "),
+    }
+}
@@ -0,0 +1,81 @@
+extern crate std;
+
+use error;
+use parser;
+use state::ShellState;
+
+impl ShellState {
+    // export name[=value] ... Every variable already goes to the commands the shell runs, so
+    // this only has to assign.
+    pub fn export(&mut self, args: &[String]) -> error::Result<i32> {
+        let mut status = 0;
+        for arg in args {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg.as_str(), None),
+            };
+            if !parser::is_name(name) {
+                self.report(&format!("export: `{}': not a valid identifier", arg));
+                status = 1;
+                continue;
+            }
+            // The environment only has strings in it
+            if self.arrays.contains_key(name) {
+                self.report(&format!("export: {}: arrays can't be exported", name));
+                status = 1;
+                continue;
+            }
+            if let Some(value) = value {
+                self.variables.insert(name, value);
+            } else if self.variables.get(name).is_none() {
+                self.variables.insert(name, "");
+            }
+        }
+        Ok(status)
+    }
+
+    // unset [-v] [-f] name ... A name[subscript] removes one element of an array.
+    pub fn unset(&mut self, args: &[String]) -> error::Result<i32> {
+        let mut functions = false;
+        let mut status = 0;
+        for arg in args {
+            match arg.as_str() {
+                "-f" => functions = true,
+                "-v" => functions = false,
+                name if functions => {
+                    self.functions.remove(name);
+                }
+                name => {
+                    let element = name.strip_suffix(']').and_then(|n| n.split_once('['));
+                    if let Some((name, subscript)) = element {
+                        if let Err(error) = self.unset_element(name, subscript) {
+                            self.report(&format!("unset: {}", error));
+                            status = 1;
+                        }
+                    } else {
+                        self.arrays.remove(name);
+                        self.variables.remove(name);
+                    }
+                }
+            }
+        }
+        Ok(status)
+    }
+
+    // shift [n] drops the first n arguments
+    pub fn shift(&mut self, args: &[String]) -> error::Result<i32> {
+        let count = match args.first().map(|n| n.parse::<usize>()) {
+            None => 1,
+            Some(Ok(count)) => count,
+            Some(Err(_)) => {
+                self.report(&format!("shift: {}: numeric argument required", args[0]));
+                return Ok(2);
+            }
+        };
+        if count > self.arguments.len() {
+            return Ok(1);
+        }
+        self.arguments.drain(..count);
+        Ok(0)
+    }
+}
@@ -0,0 +1,104 @@
+use std::process::Command;
+
+// Runs a script with rsh -c, and gives back its status and what it printed
+fn rsh(script: &str) -> (i32, String, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_rust-shell"))
+        .args(["--norc", "-c", script])
+        .output()
+        .expect("rsh runs");
+    (
+        output.status.code().unwrap_or(-1),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+#[test]
+fn indexing() {
+    let setup = "a=(one two three four); ";
+    assert_eq!(
+        rsh(&format!("{}echo ${{a[0]}} ${{a[2]}} $a", setup)).1,
+        "one three one\n"
+    );
+    assert_eq!(
+        rsh(&format!("{}echo ${{a[-1]}} ${{a[-4]}}", setup)).1,
+        "four one\n"
+    );
+    assert_eq!(rsh(&format!("{}i=1; echo ${{a[$i]}}", setup)).1, "two\n");
+    assert_eq!(rsh("x=5; echo ${x[0]} \"[${x[1]}]\"").1, "5 []\n");
+}
+
+#[test]
+fn missing_elements_are_unset() {
+    assert_eq!(rsh("a=(1); echo \"[${a[5]}]\"").1, "[]\n");
+    assert_eq!(rsh("a=(x y z); echo \"[${a[-5]}]\"").1, "[]\n");
+    assert_eq!(
+        rsh("a=(x y z); echo \"[${a[7]#x}]\" \"[${a[7]%x}]\"").1,
+        "[] []\n"
+    );
+    assert_eq!(
+        rsh("a=(x); echo ${a[3]-default} ${a[3]:+set}").1,
+        "default\n"
+    );
+    let (status, stdout, stderr) = rsh("set -u; a=(1); echo ${a[5]}; echo after");
+    assert_eq!((status, stdout.as_str()), (1, ""));
+    assert_eq!(stderr, "rsh: a[5]: unbound variable\n");
+}
+
+#[test]
+fn slices_and_lengths() {
+    let setup = "a=(one two three four); ";
+    assert_eq!(
+        rsh(&format!("{}echo \"${{a[@]:1:2}}\"", setup)).1,
+        "two three\n"
+    );
+    assert_eq!(rsh(&format!("{}echo \"${{a[@]: -1}}\"", setup)).1, "four\n");
+    assert_eq!(rsh(&format!("{}echo ${{a[1]:1:2}}", setup)).1, "wo\n");
+    assert_eq!(
+        rsh(&format!("{}echo ${{#a[@]}} ${{#a[*]}} ${{#a[2]}}", setup)).1,
+        "4 4 5\n"
+    );
+    assert_eq!(rsh("a=(); echo ${#a[@]}").1, "0\n");
+}
+
+#[test]
+fn appending() {
+    assert_eq!(
+        rsh("a=(one); a+=(two three); echo ${#a[@]} ${a[2]}").1,
+        "3 three\n"
+    );
+    assert_eq!(rsh("a=(one); a[0]+=X; echo ${a[0]}").1, "oneX\n");
+    assert_eq!(rsh("s=abc; s+=def; echo $s").1, "abcdef\n");
+    assert_eq!(
+        rsh("a=(one); a[5]=six; a+=(seven); echo ${!a[@]}").1,
+        "0 5 6\n"
+    );
+}
+
+#[test]
+fn unset_elements() {
+    assert_eq!(
+        rsh("a=(x y z); unset 'a[1]'; echo ${a[@]} ${!a[@]}").1,
+        "x z 0 2\n"
+    );
+    assert_eq!(
+        rsh("declare -A m; m[k]=v; unset 'm[k]'; echo ${#m[@]}").1,
+        "0\n"
+    );
+}
+
+#[test]
+fn declarations() {
+    assert_eq!(
+        rsh("a=(one 'two words'); a[4]=five; declare -p a").1,
+        "declare -a a=([0]=\"one\" [1]=\"two words\" [4]=\"five\")\n"
+    );
+    assert_eq!(
+        rsh("declare -A m; m[k]=v; m[j]='a b'; declare -p m").1,
+        "declare -A m=([\"j\"]=\"a b\" [\"k\"]=\"v\")\n"
+    );
+    assert_eq!(rsh("declare -a e; declare -p e").1, "declare -a e=()\n");
+    let (status, _, stderr) = rsh("declare -p missing");
+    assert_eq!(status, 1);
+    assert!(stderr.contains("missing: not found"), "{}", stderr);
+}
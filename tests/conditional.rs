@@ -0,0 +1,122 @@
+use std::env;
+use std::fs;
+use std::process::Command;
+
+// Runs a script with rsh -c, and gives back its status and what it printed
+fn rsh(script: &str) -> (i32, String, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_rust-shell"))
+        .args(["--norc", "-c", script])
+        .output()
+        .expect("rsh runs");
+    (
+        output.status.code().unwrap_or(-1),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+// The status of [[ expression ]]
+fn status(expression: &str) -> i32 {
+    rsh(&format!("[[ {} ]]", expression)).0
+}
+
+#[test]
+fn strings_and_patterns() {
+    assert_eq!(status("abc == a*"), 0);
+    assert_eq!(status("abc = a?c"), 0);
+    assert_eq!(status("abc == \"a*\""), 1);
+    assert_eq!(status("abc != b*"), 0);
+    assert_eq!(status("a < b && b > a"), 0);
+    assert_eq!(status("-z '' && -n x"), 0);
+    assert_eq!(status("$unset_variable"), 1);
+    assert_eq!(rsh("x='a b'; [[ $x == 'a b' ]]").0, 0);
+}
+
+#[test]
+fn numbers() {
+    assert_eq!(
+        status("10 -gt 9 && 3 -eq 3 && 3 -ne 4 && 3 -le 3 && 3 -ge 3"),
+        0
+    );
+    assert_eq!(status("10 -lt 9"), 1);
+    assert_eq!(status("-1 -lt 0"), 0);
+    let (status, _, stderr) = rsh("[[ a -eq 1 ]]");
+    assert_eq!(status, 2);
+    assert_eq!(stderr, "rsh: [[: a: integer expression expected\n");
+}
+
+#[test]
+fn regular_expressions_set_bash_rematch() {
+    let script = "[[ foo123bar =~ ([a-z]+)([0-9]+) ]]; echo $? ${BASH_REMATCH[0]} \
+                  ${BASH_REMATCH[1]} ${BASH_REMATCH[2]}";
+    assert_eq!(rsh(script).1, "0 foo123 foo 123\n");
+    assert_eq!(
+        rsh("[[ abc =~ x ]]; echo $? ${#BASH_REMATCH[@]}").1,
+        "1 0\n"
+    );
+    assert_eq!(rsh("[[ a.c =~ ^a\\.c$ ]] && [[ abc =~ ^a.c$ ]]").0, 0);
+}
+
+#[test]
+fn files() {
+    let dir = env::temp_dir().join(format!("rsh-conditional-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let script = format!(
+        "cd {}; touch -d 2000-01-01 old; touch new; mkdir -p sub
+         [[ -f old && ! -f sub && -d sub && ! -d old && -e new && ! -e none ]]; echo $?
+         [[ new -nt old && old -ot new && ! old -nt new ]]; echo $?
+         [[ -s old ]]; echo $?",
+        dir.display()
+    );
+    let (_, stdout, stderr) = rsh(&script);
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(stderr, "");
+    assert_eq!(stdout, "0\n0\n1\n");
+}
+
+#[test]
+fn precedence() {
+    // ! binds tightest and && tighter than ||
+    assert_eq!(status("! 1 -eq 1 && 1 -eq 2 || 1 -eq 1"), 0);
+    assert_eq!(status("1 -eq 2 || 1 -eq 1 && 1 -eq 2"), 1);
+    assert_eq!(status("! ( 1 -eq 2 || 1 -eq 1 )"), 1);
+    assert_eq!(status("( 1 -eq 2 || 1 -eq 1 ) && ! 1 -eq 2"), 0);
+    // What && and || don't need isn't expanded
+    assert_eq!(rsh("[[ a == b && $(echo x >&2) ]]").2, "");
+    assert_eq!(rsh("[[ a == a || $(echo x >&2) ]]").2, "");
+}
+
+#[test]
+fn syntax_errors() {
+    let error = |script: &str| {
+        let (status, _, stderr) = rsh(script);
+        (status, stderr)
+    };
+    assert_eq!(
+        error("[[ ]]"),
+        (2, "rsh: syntax error: unexpected `]]'\n".to_owned())
+    );
+    assert_eq!(
+        error("[[ a"),
+        (
+            2,
+            "rsh: syntax error: unexpected end of file in cond\n".to_owned()
+        )
+    );
+    assert_eq!(
+        error("[[ a ; ]]"),
+        (2, "rsh: syntax error: unexpected `;'\n".to_owned())
+    );
+    assert_eq!(
+        error("[[ ( a == a ]]"),
+        (2, "rsh: [[: expected `)'\n".to_owned())
+    );
+    assert_eq!(
+        error("[[ a == ]]"),
+        (2, "rsh: [[: argument expected\n".to_owned())
+    );
+    assert_eq!(
+        error("[[ 1 -lt 2 -a 1 ]]"),
+        (2, "rsh: [[: unexpected `-a'\n".to_owned())
+    );
+}
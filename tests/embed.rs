@@ -0,0 +1,84 @@
+extern crate rust_shell;
+
+use rust_shell::Shell;
+use std::cell::RefCell;
+use std::env;
+use std::io;
+use std::io::Write;
+use std::rc::Rc;
+
+// A writer the test can look into after the shell is done with it
+#[derive(Clone, Default)]
+struct Shared(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Shared {
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
+
+// Every run takes over the file descriptors of the process, so they all go in one test rather
+// than in tests that run at the same time
+#[test]
+fn runs() {
+    let mut shell = Shell::new();
+
+    let output = shell.run("echo out; echo err >&2; false");
+    assert_eq!(output.status, 1);
+    assert_eq!(output.stdout, "out\n");
+    assert_eq!(output.stderr, "err\n");
+    assert!(!output.exited);
+
+    let output = shell.run("exit 3; echo not reached");
+    assert_eq!(
+        (output.status, output.stdout.as_str(), output.exited),
+        (3, "", true)
+    );
+
+    // Variables and functions last from one run to the next
+    shell.run("greeting=hi; greet() { echo $greeting $1; }");
+    assert_eq!(shell.variable("greeting").as_deref(), Some("hi"));
+    shell.set_variable("greeting", "hello");
+    assert_eq!(shell.run("greet you").stdout, "hello you\n");
+
+    shell.stdin(io::Cursor::new("b\na\n"));
+    let output = shell.run("sort; echo done");
+    assert_eq!(output.stdout, "a\nb\ndone\n");
+    assert_eq!(shell.run("cat").stdout, "");
+
+    shell.builtin("count", |_, args| {
+        // println! would go to the test harness, which captures it
+        writeln!(io::stdout(), "{} arguments", args.len()).unwrap();
+        args.len() as i32
+    });
+    let output = shell.run("count a b c | tr a-z A-Z; count");
+    assert_eq!(output.stdout, "3 ARGUMENTS\n0 arguments\n");
+    assert_eq!(output.status, 0);
+
+    let (stdout, stderr) = (Shared::default(), Shared::default());
+    shell.stdout(stdout.clone()).stderr(stderr.clone());
+    let output = shell.run("echo one; echo two >&2");
+    assert_eq!(
+        (stdout.text(), stderr.text()),
+        ("one\n".to_owned(), "two\n".to_owned())
+    );
+    assert_eq!(output.stdout, "one\n");
+
+    // cd only moves the shell, not the program it is in
+    let cwd = env::current_dir().unwrap();
+    let root = shell.run("cd /; pwd");
+    assert_eq!(root.stdout, "/\n");
+    assert_eq!(env::current_dir().unwrap(), cwd);
+    assert_eq!(shell.run("pwd").stdout, "/\n");
+}
@@ -0,0 +1,137 @@
+use std::process::Command;
+
+// Runs a script with rsh -c, and gives back its status and what it printed
+fn rsh(script: &str) -> (i32, String, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_rust-shell"))
+        .args(["--norc", "-c", script])
+        .output()
+        .expect("rsh runs");
+    (
+        output.status.code().unwrap_or(-1),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+// What `= expression` prints
+fn value(expression: &str) -> String {
+    let (status, output, error) = rsh(&format!("= {}", expression));
+    assert_eq!((status, error.as_str()), (0, ""), "{}", expression);
+    output.trim_end_matches('\n').to_owned()
+}
+
+// The error `= expression` reports
+fn error(expression: &str) -> String {
+    let (status, output, error) = rsh(&format!("= {}", expression));
+    assert_eq!((status, output.as_str()), (1, ""), "{}", expression);
+    error.trim_end_matches('\n').to_owned()
+}
+
+#[test]
+fn precedence() {
+    assert_eq!(value("1 + 2 * 3"), "7");
+    assert_eq!(value("(1 + 2) * 3"), "9");
+    assert_eq!(value("2 ** 3 ** 2"), "512");
+    assert_eq!(value("-2 ** 2"), "-4");
+    assert_eq!(value("10 - 4 - 3"), "3");
+    assert_eq!(value("7 // 2 + 7 % 2"), "4");
+    assert_eq!(value("1 < 2 and not 3 > 4"), "true");
+    assert_eq!(value("0 or 2 and 3"), "3");
+    assert_eq!(value("'yes' if 1 + 1 == 2 else 'no'"), "yes");
+}
+
+#[test]
+fn types() {
+    assert_eq!(value("10 / 4"), "2.5");
+    assert_eq!(value("-7 % 3"), "2");
+    assert_eq!(value("'ab' + 'cd'"), "abcd");
+    assert_eq!(value("'ab' * 3"), "ababab");
+    assert_eq!(value("[1, 2] + [3] * 2"), "[1,2,3,3]");
+    assert_eq!(value("{'a': 1} + {'b': 2}"), r#"{"a":1,"b":2}"#);
+    assert_eq!(value("'hello'[1:3].upper()"), "EL");
+    assert_eq!(value("[1, 2, 3][-1]"), "3");
+    assert_eq!(value("'b' in ['a', 'b']"), "true");
+    assert_eq!(value("type(1.5) + type('') + type([])"), "numberstringlist");
+    assert_eq!(value("int('42') + len('abc')"), "45");
+    assert_eq!(value("sum(range(5))"), "10");
+    assert_eq!(value("range(0, 1, 0.25)"), "[0,0.25,0.5,0.75]");
+    assert_eq!(rsh("x=5\n= int($x) * 2").1, "10\n");
+    assert_eq!(rsh("= null").1, "");
+}
+
+#[test]
+fn errors() {
+    assert_eq!(error("1 / 0"), "rsh: =: division by zero");
+    assert_eq!(error("1 +"), "rsh: =: expected a value at the end");
+    assert_eq!(error("(1"), "rsh: =: expected `)' at the end");
+    assert_eq!(
+        error("'a' - 1"),
+        "rsh: =: can't use - on a string and a number"
+    );
+    assert_eq!(
+        error("1 < 'a'"),
+        "rsh: =: can't compare a number with a string"
+    );
+    assert_eq!(error("nope(1)"), "rsh: =: nope: unknown function");
+    assert_eq!(error("[1][5]"), "rsh: =: 5 is out of range");
+    assert_eq!(error("'abc"), "rsh: =: unterminated string");
+    assert_eq!(rsh("= 1 / 0\necho $?").1, "1\n");
+}
+
+#[test]
+fn numbers_out_of_range() {
+    assert_eq!(error("1e400"), "rsh: =: 1e400: number too large");
+    assert_eq!(error("2 ** 10000"), "rsh: =: integer overflow");
+    assert_eq!(error("1.5 ** 10000"), "rsh: =: number too large");
+    assert_eq!(error("9007199254740991 + 1"), "rsh: =: integer overflow");
+    assert_eq!(
+        error("9223372036854775807"),
+        "rsh: =: 9223372036854775807: number too large"
+    );
+    assert_eq!(error("(-8) ** 0.5"), "rsh: =: result is not a number");
+    assert_eq!(error("int('1e400')"), "rsh: =: number too large");
+    assert_eq!(value("9007199254740990 + 1"), "9007199254740991");
+    assert_eq!(value("1e20 + 1"), "100000000000000000000");
+}
+
+#[test]
+fn sizes_are_limited() {
+    assert_eq!(
+        error("'x' * 100000000000"),
+        "rsh: =: string too long, over 1048576"
+    );
+    assert_eq!(error("[0] * 1e12"), "rsh: =: list too long, over 1048576");
+    assert_eq!(
+        error("range(1000000000)"),
+        "rsh: =: range() too long, over 1048576"
+    );
+    assert_eq!(
+        error("range(0, 1, 1e-300)"),
+        "rsh: =: range() too long, over 1048576"
+    );
+    assert_eq!(
+        error("'a'.replace('a', 'b' * 1048576)"),
+        "rsh: =: string too long, over 1048576"
+    );
+    assert_eq!(value("len('x' * 1048576)"), "1048576");
+    assert_eq!(value("'' * 1e300"), "");
+}
+
+#[test]
+fn nesting_is_limited() {
+    let nested = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+    assert_eq!(value(&nested(100)), "1");
+    assert_eq!(error(&nested(5000)), "rsh: =: expression nested too deeply");
+    assert_eq!(
+        error(&"[".repeat(5000)),
+        "rsh: =: expression nested too deeply"
+    );
+    assert_eq!(
+        error(&format!("{}1", "- ".repeat(20000))),
+        "rsh: =: expression nested too deeply"
+    );
+    assert_eq!(
+        error(&format!("{}1", "not ".repeat(20000))),
+        "rsh: =: expression nested too deeply"
+    );
+}
@@ -0,0 +1,136 @@
+extern crate rust_shell;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use rust_shell::git::git_status;
+use rust_shell::inflate::zlib_decompress;
+use rust_shell::sha1::Sha1;
+
+fn fixture(path: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(path)
+}
+
+fn sha1_hex(data: &[u8]) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(data);
+    sha1.finish().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// The examples from FIPS 180-2
+#[test]
+fn sha1_known_answers() {
+    assert_eq!(sha1_hex(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+    assert_eq!(sha1_hex(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    assert_eq!(
+        sha1_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+        "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+    );
+    assert_eq!(sha1_hex(&[b'a'; 1_000_000]), "34aa973cd4c4daa4f61eeb2bdbad27316534016f");
+}
+
+#[test]
+fn sha1_in_pieces() {
+    let mut sha1 = Sha1::new();
+    for chunk in [b'a'; 1_000_000].chunks(999) {
+        sha1.update(chunk);
+    }
+    let hex: String = sha1.finish().iter().map(|b| format!("{:02x}", b)).collect();
+    assert_eq!(hex, "34aa973cd4c4daa4f61eeb2bdbad27316534016f");
+}
+
+// The fixtures are the same text compressed by zlib into each kind of deflate block
+#[test]
+fn inflate_block_types() {
+    let text = fs::read(fixture("inflate/text")).unwrap();
+    for name in &["stored", "fixed", "dynamic"] {
+        let compressed = fs::read(fixture(&format!("inflate/{}.zz", name))).unwrap();
+        assert_eq!(zlib_decompress(&compressed, text.len()).as_ref(), Some(&text), "{}", name);
+    }
+}
+
+#[test]
+fn inflate_rejects_bad_input() {
+    let compressed = fs::read(fixture("inflate/dynamic.zz")).unwrap();
+    assert_eq!(zlib_decompress(&compressed, 100), None);
+    assert_eq!(zlib_decompress(&compressed[..compressed.len() / 2], 1 << 20), None);
+    assert_eq!(zlib_decompress(b"\x78", 1 << 20), None);
+    assert_eq!(zlib_decompress(b"", 1 << 20), None);
+}
+
+fn copy_dir(from: &Path, to: &Path) {
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        if entry.file_type().unwrap().is_dir() {
+            copy_dir(&entry.path(), &to.join(entry.file_name()));
+        } else {
+            fs::copy(entry.path(), to.join(entry.file_name())).unwrap();
+        }
+    }
+}
+
+// The fixture repositories hold everything in one pack with all their refs packed. main is one
+// commit ahead of and one behind origin/main, and its tree is stored as an offset delta in one
+// and as a delta against a named object in the other. This checks out main beside one of them.
+fn repository(kind: &str, test: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("rsh-{}-{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    copy_dir(&fixture(&format!("git/{}", kind)), &dir.join(".git"));
+    for i in 0..50 {
+        let mut contents = format!("file {:02}\nsecond line of {:02}\n", i, i);
+        if i == 10 {
+            contents.push_str("upstream change\n");
+        }
+        fs::write(dir.join(format!("f{:02}.txt", i)), contents).unwrap();
+    }
+    dir
+}
+
+#[test]
+fn status_from_offset_deltas() {
+    let dir = repository("ofs", "offset-deltas");
+    let status = git_status(&dir, Duration::from_secs(10)).unwrap();
+    assert_eq!(status.summary(), "main ↑1↓1");
+    assert!(status.complete);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn status_from_ref_deltas() {
+    let dir = repository("ref", "ref-deltas");
+    let status = git_status(&dir, Duration::from_secs(10)).unwrap();
+    assert_eq!(status.summary(), "main ↑1↓1");
+    assert!(status.complete);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// In same-time every commit has the same date, and main and origin/main each have one commit on
+// top of five shared ones. Walking by date alone, the shared commits would be counted as ahead.
+#[test]
+fn status_with_equal_commit_dates() {
+    let dir = repository("same-time", "same-time");
+    let status = git_status(&dir, Duration::from_secs(10)).unwrap();
+    assert_eq!(status.summary(), "main ↑1↓1");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn status_of_changed_work_tree() {
+    let dir = repository("ofs", "changed-work-tree");
+    fs::write(dir.join("f05.txt"), "changed\n").unwrap();
+    fs::write(dir.join("new.txt"), "new\n").unwrap();
+    let status = git_status(&dir, Duration::from_secs(10)).unwrap();
+    assert!(status.dirty && status.untracked && !status.staged);
+    assert_eq!(status.summary(), "main ↑1↓1 !?");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn status_without_time_is_incomplete() {
+    let dir = repository("ofs", "no-time");
+    let status = git_status(&dir, Duration::from_secs(0));
+    assert!(status.is_none_or(|s| !s.complete));
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,75 @@
+use std::env;
+use std::fs;
+use std::process::Command;
+
+// Runs a script with rsh -c, and gives back its status and what it printed
+fn rsh(script: &str) -> (i32, String, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_rust-shell"))
+        .args(["--norc", "-c", script])
+        .output()
+        .expect("rsh runs");
+    (
+        output.status.code().unwrap_or(-1),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+#[test]
+fn subshell_changes_do_not_last() {
+    assert_eq!(rsh("x=1; (x=2; echo $x); echo $x").1, "2\n1\n");
+    assert_eq!(rsh("cd /; (cd /tmp; pwd); pwd").1, "/tmp\n/\n");
+    assert_eq!(rsh("(set -u); echo $unset_variable ok").1, "ok\n");
+}
+
+#[test]
+fn subshell_status_and_exit() {
+    assert_eq!(rsh("(exit 3); echo $?").1, "3\n");
+    assert_eq!(rsh("(exit 4)").0, 4);
+    assert_eq!(rsh("(true; false) || echo failed").1, "failed\n");
+}
+
+#[test]
+fn group_runs_in_this_shell() {
+    assert_eq!(rsh("x=1; { x=2; cd /; }; echo $x; pwd").1, "2\n/\n");
+    assert_eq!(rsh("{ false; }; echo $?").1, "1\n");
+    assert_eq!(rsh("{ echo a; echo b; }").1, "a\nb\n");
+}
+
+#[test]
+fn groups_and_subshells_in_pipelines() {
+    assert_eq!(rsh("{ echo a; echo b; } | tr a-z A-Z").1, "A\nB\n");
+    assert_eq!(rsh("(echo x; echo y) | tr a-z A-Z").1, "X\nY\n");
+    assert_eq!(rsh("echo in | { cat; echo done; }").1, "in\ndone\n");
+}
+
+#[test]
+fn redirections_apply_to_the_whole_group() {
+    let dir = env::temp_dir().join(format!("rsh-groups-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("out");
+    let script = format!(
+        "{{ echo a; echo b; }} > {0}; (echo c; echo d) >> {0}; cat {0}",
+        file.display()
+    );
+    let (status, stdout, _) = rsh(&script);
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(status, 0);
+    assert_eq!(stdout, "a\nb\nc\nd\n");
+    assert_eq!(rsh("(echo out; echo err >&2) 2>&1 >/dev/null").1, "err\n");
+}
+
+#[test]
+fn functions_can_have_either_body() {
+    assert_eq!(rsh("f() { echo in f; }; f").1, "in f\n");
+    assert_eq!(rsh("f() ( cd /; x=2 ); x=1; cd /tmp; f; echo $x; pwd").1, "1\n/tmp\n");
+}
+
+#[test]
+fn unclosed_groups_are_syntax_errors() {
+    let (status, _, stderr) = rsh("{ echo a");
+    assert_eq!(status, 2);
+    assert!(stderr.contains("syntax error"));
+    assert_eq!(rsh("( echo a").0, 2);
+    assert_eq!(rsh("echo a )").0, 2);
+}
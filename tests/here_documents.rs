@@ -0,0 +1,62 @@
+use std::process::Command;
+
+// Runs a script with rsh -c, and gives back its status and what it printed
+fn rsh(script: &str) -> (i32, String, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_rust-shell"))
+        .args(["--norc", "-c", script])
+        .output()
+        .expect("rsh runs");
+    (
+        output.status.code().unwrap_or(-1),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+#[test]
+fn bodies_are_expanded() {
+    let script = "x=world\ncat <<EOF\nhello $x\n\t$(echo sub) ${x#w}\nEOF\necho after";
+    assert_eq!(rsh(script).1, "hello world\n\tsub orld\nafter\n");
+    assert_eq!(rsh("x=1\ncat <<EOF\n\\$x \\\\ \\`\nEOF").1, "$x \\ `\n");
+}
+
+#[test]
+fn dash_strips_leading_tabs() {
+    let script = "x=world\ncat <<-EOF\n\tstripped $x\n\t\ttwo\n  spaces\n\tEOF";
+    assert_eq!(rsh(script).1, "stripped world\ntwo\n  spaces\n");
+}
+
+#[test]
+fn quoted_delimiters_turn_off_expansion() {
+    assert_eq!(
+        rsh("x=1\ncat <<\"EOF\"\n$x \\$ $(echo no)\nEOF").1,
+        "$x \\$ $(echo no)\n"
+    );
+    assert_eq!(rsh("x=1\ncat <<'E'F\n$x\nEF").1, "$x\n");
+    assert_eq!(rsh("x=1\ncat <<E\\F\n$x\nEF").1, "$x\n");
+    assert_eq!(rsh("x=1\ncat <<-'EOF'\n\t$x\n\tEOF").1, "$x\n");
+}
+
+#[test]
+fn commands_around_here_documents() {
+    assert_eq!(rsh("cat <<EOF; echo after\none\nEOF").1, "one\nafter\n");
+    // The last of several here-documents is the input
+    assert_eq!(rsh("cat <<A <<B\na\nA\nb\nB").1, "b\n");
+    assert_eq!(rsh("cat <<A | tr a-z A-Z\nlower\nA").1, "LOWER\n");
+    assert_eq!(
+        rsh("f() { cat <<EOF\nin $1\nEOF\n}; f fn; f again").1,
+        "in fn\nin again\n"
+    );
+    assert_eq!(rsh("for i in 1 2; do cat <<EOF\n$i\nEOF\ndone").1, "1\n2\n");
+    assert_eq!(rsh("cat <<EOF\nEOF").1, "");
+}
+
+#[test]
+fn unterminated_here_documents_are_syntax_errors() {
+    let (status, stdout, stderr) = rsh("echo before\ncat <<EOF\nbody");
+    assert_eq!((status, stdout.as_str()), (2, "before\n"));
+    assert_eq!(
+        stderr,
+        "rsh: syntax error: unexpected end of file in heredoc\n"
+    );
+}
@@ -0,0 +1,80 @@
+extern crate rust_shell;
+
+use rust_shell::envars::Envars;
+use rust_shell::history::{escape_command, parse_history, HistoryEntry};
+use rust_shell::state::ShellState;
+
+const COMMANDS: &[&str] = &[
+    "echo plain",
+    "echo a\\",
+    "echo a\\\\",
+    "\\",
+    "if true\nthen echo yes\nfi",
+    "echo one \\\ntwo",
+    "first\\\nsecond\\",
+    "ends with a newline\n",
+];
+
+fn commands(contents: &str) -> Vec<String> {
+    parse_history(contents)
+        .into_iter()
+        .map(|e| e.command)
+        .collect()
+}
+
+#[test]
+fn plain_commands_round_trip() {
+    let mut contents = String::new();
+    for command in COMMANDS {
+        contents.push_str(&escape_command(command));
+        contents.push('\n');
+    }
+    assert_eq!(commands(&contents), COMMANDS);
+}
+
+#[test]
+fn plain_multi_line_commands_are_joined() {
+    assert_eq!(
+        commands("for i in 1 2\\\ndo echo $i\\\ndone\nls\n"),
+        ["for i in 1 2\ndo echo $i\ndone", "ls"]
+    );
+    assert_eq!(commands("echo a\\\\\nls\n"), ["echo a\\", "ls"]);
+}
+
+#[test]
+fn extended_entries_round_trip() {
+    let entries: Vec<HistoryEntry> = COMMANDS
+        .iter()
+        .enumerate()
+        .map(|(i, command)| HistoryEntry {
+            command: command.to_string(),
+            timestamp: 1700000000 + i as u64,
+            cwd: format!("/tmp/a;b\\c{}", i),
+            status: Some(i as i32),
+            duration: if i % 2 == 0 {
+                Some(i as u64 * 10)
+            } else {
+                None
+            },
+        })
+        .collect();
+    let contents: String = entries.iter().map(|e| e.to_line()).collect();
+    assert_eq!(parse_history(&contents), entries);
+}
+
+#[test]
+fn trailing_backslash_does_not_join_the_next_entry() {
+    let first = HistoryEntry::new("echo a\\", "/");
+    let second = HistoryEntry::new("ls", "/");
+    let contents = first.to_line() + &second.to_line();
+    assert_eq!(parse_history(&contents), [first, second]);
+}
+
+#[test]
+fn failed_substitution_shows_the_pattern() {
+    let mut shell = ShellState::new(Envars::load());
+    shell.history.push(HistoryEntry::new("echo alpha", "/"));
+    let error = shell.expand_history("!!:s/beta/x/").err().unwrap();
+    assert_eq!(error, ":s/beta/: substitution failed");
+    assert_eq!(shell.expand_history("!!:s/alpha/x/").unwrap().line, "echo x");
+}
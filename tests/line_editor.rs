@@ -0,0 +1,128 @@
+extern crate libc;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::os::unix::io::FromRawFd;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const UP: &str = "\x1b[A";
+const DOWN: &str = "\x1b[B";
+
+// Commands in the history, which print the word without the quotes in it, so that what they
+// print never shows up on the command line itself
+const HISTORY: &str = "printf '%s\\n' alp''ha\necho be''ta\nprintf '%s\\n' gam''ma\n";
+
+// Types `keys` one after another into an interactive shell with HISTORY as its history, and
+// gives back everything the terminal showed
+fn type_keys(name: &str, keys: &[&str]) -> String {
+    let (mut master, mut slave) = (0, 0);
+    let opened = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        )
+    };
+    assert_eq!(opened, 0);
+    let history = env::temp_dir().join(format!("rsh-editor-{}-{}", name, std::process::id()));
+    fs::write(&history, HISTORY).unwrap();
+    let mut command = Command::new(env!("CARGO_BIN_EXE_rust-shell"));
+    command
+        .arg("--norc")
+        .env("HISTFILE", &history)
+        .env("TERM", "dumb")
+        .stdin(unsafe { Stdio::from_raw_fd(libc::dup(slave)) })
+        .stdout(unsafe { Stdio::from_raw_fd(libc::dup(slave)) })
+        .stderr(unsafe { Stdio::from_raw_fd(slave) });
+    unsafe {
+        command.pre_exec(|| {
+            libc::setsid();
+            libc::ioctl(0, libc::TIOCSCTTY, 0);
+            Ok(())
+        });
+    }
+    let mut child = command.spawn().expect("rsh runs");
+    drop(command);
+    let mut terminal = unsafe { File::from_raw_fd(master) };
+    thread::sleep(Duration::from_millis(200));
+    for key in keys.iter().chain(&["exit\r"]) {
+        terminal.write_all(key.as_bytes()).unwrap();
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    let start = Instant::now();
+    let mut seen = Vec::new();
+    let mut buffer = [0; 4096];
+    while start.elapsed() < Duration::from_secs(5) {
+        let mut poll = libc::pollfd {
+            fd: master,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        if unsafe { libc::poll(&mut poll, 1, 100) } <= 0 {
+            continue;
+        }
+        match terminal.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => seen.extend_from_slice(&buffer[..n]),
+        }
+    }
+    let _ = child.kill();
+    child.wait().unwrap();
+    fs::remove_file(&history).unwrap();
+    String::from_utf8_lossy(&seen).into_owned()
+}
+
+// Whether a command printed `word` on a line of its own
+fn printed(shown: &str, word: &str) -> bool {
+    shown.contains(&format!("\n{}\r\n", word))
+}
+
+#[test]
+fn up_and_down_move_through_the_history() {
+    let shown = type_keys("updown", &[UP, UP, "\r"]);
+    assert!(printed(&shown, "beta"), "{}", shown);
+    // Up stops at the oldest entry, and down comes back from it
+    let shown = type_keys("oldest", &[UP, UP, UP, UP, DOWN, "\r"]);
+    assert!(printed(&shown, "beta"), "{}", shown);
+    // Down past the newest entry gives back the line being edited
+    let shown = type_keys("edited", &["echo sa''ved", UP, UP, DOWN, DOWN, "\r"]);
+    assert!(printed(&shown, "saved"), "{}", shown);
+}
+
+#[test]
+fn the_line_being_edited_filters_the_history() {
+    let shown = type_keys("prefix", &["printf", UP, UP, "\r"]);
+    assert!(printed(&shown, "alpha"), "{}", shown);
+    let shown = type_keys("echo", &["echo", UP, UP, "\r"]);
+    assert!(printed(&shown, "beta"), "{}", shown);
+}
+
+#[test]
+fn reverse_search_finds_older_matches() {
+    let shown = type_keys("search", &["\x12", "be", "\r"]);
+    assert!(printed(&shown, "beta"), "{}", shown);
+    assert!(shown.contains("bck-i-search: "), "{}", shown);
+    // Searching again moves on to the next older match
+    let shown = type_keys("again", &["\x12", "printf", "\x12", "\r"]);
+    assert!(printed(&shown, "alpha"), "{}", shown);
+    // A search that fails says so
+    let shown = type_keys("failing", &["\x12", "nothing", "\x07", "\r"]);
+    assert!(shown.contains("failing bck-i-search: "), "{}", shown);
+}
+
+#[test]
+fn cancelled_search_restores_the_line() {
+    let shown = type_keys("cancel", &["echo can''celled", "\x12", "be", "\x07", "\r"]);
+    assert!(printed(&shown, "cancelled"), "{}", shown);
+    assert!(!printed(&shown, "beta"), "{}", shown);
+    // Other keys end the search and edit the match
+    let shown = type_keys("edit", &["\x12", "be", "\x1b[D", "x", "\r"]);
+    assert!(printed(&shown, "betxa"), "{}", shown);
+}
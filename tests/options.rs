@@ -0,0 +1,46 @@
+use std::process::Command;
+
+// Runs a script with rsh -c, and gives back its status and what it printed
+fn rsh(script: &str) -> (i32, String, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_rust-shell"))
+        .args(["--norc", "-c", script])
+        .output()
+        .expect("rsh runs");
+    (
+        output.status.code().unwrap_or(-1),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+#[test]
+fn errexit_stops_at_a_failure_that_is_not_tested() {
+    let (status, stdout, _) =
+        rsh("set -e; false || true; if false; then :; fi; echo ok; false; echo no");
+    assert_eq!((status, stdout.as_str()), (1, "ok\n"));
+}
+
+#[test]
+fn assignments_give_the_status_of_their_last_substitution() {
+    assert_eq!(rsh("x=$(false); echo $?").1, "1\n");
+    assert_eq!(rsh("x=$(exit 3) y=$(true); echo $?").1, "0\n");
+    assert_eq!(rsh("x=$(exit 3) y=$(exit 4); echo $?").1, "4\n");
+    assert_eq!(rsh("false; x=1; echo $?").1, "0\n");
+    assert_eq!(rsh("false; x=$?; echo $? $x").1, "0 1\n");
+    assert_eq!(rsh("x=$(false) true; echo $?").1, "0\n");
+
+    let (status, stdout, _) = rsh("set -e; x=$(true); echo ok; x=$(false); echo no");
+    assert_eq!((status, stdout.as_str()), (1, "ok\n"));
+}
+
+#[test]
+fn nounset_and_pipefail() {
+    let (status, stdout, stderr) = rsh("set -u; echo $nope; echo no");
+    assert_eq!((status, stdout.as_str()), (1, ""));
+    assert_eq!(stderr, "rsh: nope: unbound variable\n");
+    assert_eq!(
+        rsh("set -o pipefail; false | true; echo $?; set +o pipefail; false | true; echo $?").1,
+        "1\n0\n"
+    );
+    assert_eq!(rsh("set -eu; echo $-").1, "eu\n");
+}
@@ -0,0 +1,63 @@
+use std::env;
+use std::fs;
+use std::process::Command;
+
+// Runs a script with rsh -c, and gives back its status and what it printed
+fn rsh(script: &str) -> (i32, String, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_rust-shell"))
+        .args(["--norc", "-c", script])
+        .output()
+        .expect("rsh runs");
+    (
+        output.status.code().unwrap_or(-1),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+#[test]
+fn input_substitutions_are_files_to_read() {
+    let (status, stdout, stderr) = rsh("diff <(printf 'a\\nb\\n') <(printf 'a\\nc\\n'); echo $?");
+    assert_eq!(stderr, "");
+    assert_eq!(status, 0);
+    assert_eq!(stdout, "2c2\n< b\n---\n> c\n1\n");
+    assert_eq!(rsh("cat <(echo one) <(echo two)").1, "one\ntwo\n");
+    assert_eq!(rsh("wc -l < <(printf '1\\n2\\n')").1.trim(), "2");
+    assert!(rsh("echo <(true)").1.starts_with("/dev/fd/"));
+}
+
+#[test]
+fn output_substitutions_are_files_to_write() {
+    let path = env::temp_dir().join(format!("rsh-substitution-{}", std::process::id()));
+    let script = format!(
+        "echo x | tee >(sed s/x/y/ > {}) > /dev/null",
+        path.display()
+    );
+    assert_eq!(rsh(&script).2, "");
+    // The substitution finishes on its own time
+    let mut contents = String::new();
+    for _ in 0..50 {
+        contents = fs::read_to_string(&path).unwrap_or_default();
+        if !contents.is_empty() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    let _ = fs::remove_file(&path);
+    assert_eq!(contents, "y\n");
+}
+
+#[test]
+fn substitutions_in_functions_and_command_substitutions() {
+    assert_eq!(
+        rsh("f() { cat $1; }; f <(echo in function)").1,
+        "in function\n"
+    );
+    assert_eq!(rsh("x=$(cat <(echo inside)); echo $x").1, "inside\n");
+    assert_eq!(rsh("for i in 1 2; do cat <(echo $i); done").1, "1\n2\n");
+}
+
+#[test]
+fn quoted_substitutions_are_plain_text() {
+    assert_eq!(rsh("echo '<(not)' \"<(this)\"").1, "<(not) <(this)\n");
+}
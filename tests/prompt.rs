@@ -0,0 +1,129 @@
+extern crate rust_shell;
+
+use rust_shell::envars::Envars;
+use rust_shell::git::GitStatus;
+use rust_shell::state::ShellState;
+
+// A shell with colors on and the given variables set
+fn shell(variables: &[(&str, &str)]) -> ShellState {
+    let mut shell = ShellState::new(Envars::load());
+    shell.variables.remove("NO_COLOR");
+    shell.variables.insert("TERM", "xterm-256color");
+    for &(name, value) in variables {
+        shell.variables.insert(name, value);
+    }
+    shell
+}
+
+fn render(template: &str) -> String {
+    shell(&[]).render_prompt(template, None)
+}
+
+// The text of a prompt, without colors
+fn plain(template: &str) -> String {
+    shell(&[("TERM", "dumb")]).render_prompt(template, None)
+}
+
+fn repository() -> GitStatus {
+    GitStatus {
+        branch: "main".to_owned(),
+        ahead: 2,
+        behind: 0,
+        staged: true,
+        dirty: false,
+        untracked: true,
+        state: None,
+        complete: true,
+    }
+}
+
+#[test]
+fn text_and_escapes() {
+    assert_eq!(plain("plain $ "), "plain $ ");
+    assert_eq!(plain("\\{BOLD\\} \\\\"), "{BOLD} \\");
+    // Braces that don't make a tag stay as they are
+    assert_eq!(plain("{ {unclosed"), "{ {unclosed");
+    assert_eq!(plain("{NOT_A_TAG}x"), "x");
+}
+
+#[test]
+fn styles_and_colors() {
+    assert_eq!(render("{BOLD}a"), "\x1b[0m\x1b[1ma\x1b[0m");
+    assert_eq!(render("{RED}a"), "\x1b[0m\x1b[31ma\x1b[0m");
+    assert_eq!(render("{BRIGHT_RED}a"), "\x1b[0m\x1b[38;5;9ma\x1b[0m");
+    assert_eq!(render("{FG:208}a"), "\x1b[0m\x1b[38;5;208ma\x1b[0m");
+    assert_eq!(
+        render("{FG:#ff8000}a"),
+        "\x1b[0m\x1b[38;2;255;128;0ma\x1b[0m"
+    );
+    assert_eq!(render("{BG_BLUE}a"), "\x1b[0m\x1b[44ma\x1b[0m");
+    assert_eq!(render("{RED}a{RESET}b"), "\x1b[0m\x1b[31ma\x1b[0mb\x1b[0m");
+    // A bad color is no style at all
+    assert_eq!(render("{FG:#12}a"), "a\x1b[0m");
+}
+
+#[test]
+fn colors_can_be_turned_off() {
+    assert_eq!(plain("{BOLD}{RED}a"), "a");
+    let mut colorless = shell(&[]);
+    colorless.variables.insert("NO_COLOR", "1");
+    assert_eq!(colorless.render_prompt("{BOLD}{RED}a", None), "a");
+    assert_eq!(colorless.render_style("{BOLD}"), "");
+    assert_eq!(
+        shell(&[]).render_style("{BOLD}text{RED}"),
+        "\x1b[0m\x1b[1m\x1b[31m"
+    );
+}
+
+#[test]
+fn variables_and_segments() {
+    let mut shell = shell(&[
+        ("TERM", "dumb"),
+        ("HOME", "/home/me"),
+        ("PWD", "/home/me/src/rsh"),
+        ("WHO", "you"),
+    ]);
+    let render = |shell: &ShellState, template: &str| shell.render_prompt(template, None);
+    assert_eq!(render(&shell, "{$WHO}@{$UNSET}"), "you@");
+    assert_eq!(render(&shell, "{CWD}"), "~/src/rsh");
+    assert_eq!(render(&shell, "{CWD:1}"), "…/rsh");
+    assert_eq!(render(&shell, "{DIR}"), "rsh");
+    assert_eq!(render(&shell, "[{STATUS}]"), "[]");
+    shell.last_status = 3;
+    assert_eq!(render(&shell, "[{STATUS}]"), "[3]");
+    assert_eq!(render(&shell, "{DATE:%Y}").len(), 4);
+}
+
+#[test]
+fn conditionals() {
+    let mut shell = shell(&[("TERM", "dumb"), ("SET", "x")]);
+    let render = |shell: &ShellState, template: &str| shell.render_prompt(template, None);
+    assert_eq!(render(&shell, "{?$SET:set {$SET}}{!$SET:unset}"), "set x");
+    assert_eq!(render(&shell, "{?$UNSET:set}{!$UNSET:unset}"), "unset");
+    assert_eq!(render(&shell, "{?STATUS:failed {STATUS}}ok"), "ok");
+    shell.last_status = 1;
+    assert_eq!(
+        render(&shell, "{?STATUS:failed {STATUS} }ok"),
+        "failed 1 ok"
+    );
+    // Conditionals nest, and \} is a brace inside one
+    assert_eq!(render(&shell, "{?$SET:a{?STATUS:b\\}}c}"), "ab}c");
+}
+
+#[test]
+fn repository_segments() {
+    let shell = shell(&[("TERM", "dumb")]);
+    let git = repository();
+    let render = |template: &str, git: Option<&GitStatus>| shell.render_prompt(template, git);
+    assert_eq!(render("{GIT}", Some(&git)), "main ↑2 +?");
+    assert_eq!(
+        render("{GIT_BRANCH}:{GIT_AHEAD}:{GIT_BEHIND}", Some(&git)),
+        "main:2:"
+    );
+    assert_eq!(
+        render("{GIT_STAGED}{GIT_DIRTY}{GIT_UNTRACKED}", Some(&git)),
+        "+?"
+    );
+    assert_eq!(render("{?GIT:({GIT_BRANCH}) }$", Some(&git)), "(main) $");
+    assert_eq!(render("{?GIT:({GIT_BRANCH}) }$", None), "$");
+}
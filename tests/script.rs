@@ -0,0 +1,146 @@
+use std::env;
+use std::fs;
+use std::process::Command;
+
+fn rsh(args: &[&str]) -> (i32, String, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_rust-shell"))
+        .arg("--norc")
+        .args(args)
+        .output()
+        .expect("rsh runs");
+    (
+        output.status.code().unwrap_or(-1),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+fn script(name: &str, contents: &str) -> String {
+    let path = env::temp_dir().join(format!("rsh-{}-{}.sh", name, std::process::id()));
+    fs::write(&path, contents).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+#[test]
+fn commands_before_an_unterminated_quote_run() {
+    let path = script("quote", "echo first\necho \"unterminated\n");
+    let (status, stdout, stderr) = rsh(&[&path]);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(status, 2);
+    assert_eq!(stdout, "first\n");
+    assert!(stderr.contains(":2: syntax error"), "{}", stderr);
+}
+
+#[test]
+fn definitions_before_a_syntax_error_are_kept() {
+    let path = script(
+        "rc",
+        "alias hi='echo hi'\ngreet() { echo hello $1; }\nexport SEEN=yes\n\
+         if true; then\n  echo in if\nfi\n\necho $(date\n",
+    );
+    let (status, stdout, stderr) =
+        rsh(&["-c", &format!("source {}\nhi; greet you; echo $SEEN", path)]);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(status, 0);
+    assert_eq!(stdout, "in if\nhi\nhello you\nyes\n");
+    assert!(stderr.contains(":8: syntax error"), "{}", stderr);
+}
+
+#[test]
+fn a_command_running_into_the_error_does_not_run() {
+    let path = script(
+        "compound",
+        "echo before\nif true; then\n  echo 'never\nfi\n",
+    );
+    let (status, stdout, _) = rsh(&[&path]);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(status, 2);
+    assert_eq!(stdout, "before\n");
+    assert_eq!(rsh(&["-c", "echo a; echo \"b"]).1, "");
+    assert_eq!(rsh(&["-c", "echo a\ncat <<EOF\nbody"]).1, "a\n");
+}
+
+#[test]
+fn endless_recursion_stops_at_funcnest() {
+    let (status, stdout, stderr) = rsh(&["-c", "f() { f; }; f; echo status $?"]);
+    assert_eq!(status, 0);
+    assert_eq!(stdout, "status 1\n");
+    assert_eq!(stderr, "rsh: f: maximum function nesting level exceeded (1000)\n");
+
+    let (_, stdout, stderr) = rsh(&["-c", "FUNCNEST=3; f() { echo $#; f x $@; }; f"]);
+    assert_eq!(stdout, "0\n1\n2\n");
+    assert_eq!(stderr, "rsh: f: maximum function nesting level exceeded (3)\n");
+}
+
+// Function bodies that take much more stack for each call stop before there is none left
+#[test]
+fn deep_recursion_stops_before_the_stack_runs_out() {
+    let (status, stdout, stderr) = rsh(&[
+        "-c",
+        "g() { if true; then while true; do { case x in x) [[ -n x ]] && for i in 1; do \
+         g || true; done ;; esac; }; break; done; fi; }; g; echo done",
+    ]);
+    assert_eq!(status, 0);
+    assert_eq!(stdout, "done\n");
+    assert!(stderr.contains("maximum function nesting level exceeded"), "{}", stderr);
+}
+
+// Blocks and conditions nested far too deeply are errors rather than a stack overflow
+#[test]
+fn deep_nesting_stops_before_the_stack_runs_out() {
+    let nested = |depth: usize, open: &str, inside: &str, close: &str| {
+        format!("{}{}{}", open.repeat(depth), inside, close.repeat(depth))
+    };
+    assert_eq!(rsh(&["-c", &nested(100, "{ ", "echo a", "; }")]).1, "a\n");
+    let (status, stdout, stderr) = rsh(&["-c", &nested(5000, "{ ", "echo a", "; }")]);
+    assert_eq!((status, stdout.as_str()), (2, ""));
+    assert_eq!(stderr, "rsh: syntax error: commands nested too deeply\n");
+    assert_eq!(rsh(&["-c", &nested(5000, "( ", "echo a", " )")]).0, 2);
+    assert_eq!(rsh(&["-c", &nested(5000, "if true; then ", "echo a", "; fi")]).0, 2);
+
+    let script = format!("[[ {} ]]; echo $?", nested(5000, "( ", "a", " )"));
+    let (status, stdout, stderr) = rsh(&["-c", &script]);
+    assert_eq!((status, stdout.as_str()), (0, "2\n"));
+    assert_eq!(stderr, "rsh: [[: expression nested too deeply\n");
+    let script = format!("[[ {} ]]; echo $?", nested(5000, "! ", "a", ""));
+    assert_eq!(rsh(&["-c", &script]).1, "2\n");
+}
+
+#[test]
+fn builtin_and_redirection_errors_are_reported() {
+    let (status, stdout, stderr) = rsh(&[
+        "-c",
+        "source /nonexistent; echo $?; shift x; echo $?; unalias none; echo $?; \
+         echo > /nonexistent/file; echo $?; cat <&99; echo $?; [ a = a; echo $?",
+    ]);
+    assert_eq!(status, 0);
+    assert_eq!(stdout, "1\n2\n1\n1\n1\n2\n");
+    assert_eq!(
+        stderr,
+        "rsh: source: /nonexistent: No such file or directory\n\
+         rsh: shift: x: numeric argument required\n\
+         rsh: unalias: none: not found\n\
+         rsh: /nonexistent/file: No such file or directory\n\
+         rsh: 99: bad file descriptor\n\
+         rsh: [: missing `]'\n"
+    );
+}
+
+// The environment can't hold NUL bytes, which programs the shell runs don't see
+#[test]
+fn values_with_nul_bytes_do_not_abort() {
+    let (status, stdout, stderr) = rsh(&[
+        "-c",
+        "x=$(printf 'a\\0b'); for i in \"$(printf 'a\\0b')\"; do :; done; \
+         export \"A=$(printf '\\0')\" B=\"$(printf 'p\\0q')\"; printenv B; echo done",
+    ]);
+    assert_eq!((status, stderr.as_str()), (0, ""));
+    assert_eq!(stdout, "pq\ndone\n");
+}
+
+#[test]
+fn a_missing_script_is_reported() {
+    let (status, stdout, stderr) = rsh(&["/nonexistent/script.sh"]);
+    assert_eq!((status, stdout.as_str()), (127, ""));
+    assert_eq!(stderr, "rsh: /nonexistent/script.sh: No such file or directory\n");
+}
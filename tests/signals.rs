@@ -0,0 +1,89 @@
+extern crate libc;
+
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::io::FromRawFd;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Everything the terminal shows until the shell closes it, or `limit` has passed
+fn read_until_closed(terminal: &mut File, limit: Duration) -> String {
+    let start = Instant::now();
+    let mut seen = Vec::new();
+    let mut buffer = [0; 4096];
+    while start.elapsed() < limit {
+        let mut poll = libc::pollfd {
+            fd: std::os::unix::io::AsRawFd::as_raw_fd(terminal),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        if unsafe { libc::poll(&mut poll, 1, 100) } <= 0 {
+            continue;
+        }
+        match terminal.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => seen.extend_from_slice(&buffer[..n]),
+        }
+    }
+    String::from_utf8_lossy(&seen).into_owned()
+}
+
+// ^C and ^\ stop the command in the foreground, while the shell carries on
+#[test]
+fn interrupts_stop_the_command_not_the_shell() {
+    let (mut master, mut slave) = (0, 0);
+    let opened = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        )
+    };
+    assert_eq!(opened, 0);
+    let history = env::temp_dir().join(format!("rsh-signals-{}", std::process::id()));
+    let mut command = Command::new(env!("CARGO_BIN_EXE_rust-shell"));
+    command
+        .arg("--norc")
+        .env("HISTFILE", &history)
+        .env("TERM", "dumb")
+        .stdin(unsafe { Stdio::from_raw_fd(libc::dup(slave)) })
+        .stdout(unsafe { Stdio::from_raw_fd(libc::dup(slave)) })
+        .stderr(unsafe { Stdio::from_raw_fd(slave) });
+    unsafe {
+        command.pre_exec(|| {
+            libc::setsid();
+            libc::ioctl(0, libc::TIOCSCTTY, 0);
+            Ok(())
+        });
+    }
+    let start = Instant::now();
+    let mut child = command.spawn().expect("rsh runs");
+    // Leaving only the shell with the terminal open, so that it closes when the shell exits
+    drop(command);
+    let mut terminal = unsafe { File::from_raw_fd(master) };
+
+    // A program the shell runs, and a copy of the shell in a pipeline
+    let commands = [
+        ("sleep 5\r", "\x03"),
+        ("while true; do :; done | cat\r", "\x1c"),
+    ];
+    for &(line, key) in &commands {
+        terminal.write_all(line.as_bytes()).unwrap();
+        thread::sleep(Duration::from_millis(500));
+        terminal.write_all(key.as_bytes()).unwrap();
+        thread::sleep(Duration::from_millis(200));
+    }
+    terminal.write_all(b"echo status $?\rexit\r").unwrap();
+    let shown = read_until_closed(&mut terminal, Duration::from_secs(5));
+    let status = child.wait().unwrap();
+    let _ = std::fs::remove_file(&history);
+
+    assert!(start.elapsed() < Duration::from_secs(5), "{}", shown);
+    assert_eq!(status.code(), Some(0), "{}", shown);
+    assert!(shown.contains("status 131"), "{}", shown);
+}
@@ -0,0 +1,76 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn rsh(dir: &PathBuf, command: &str) -> (i32, String, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_rust-shell"))
+        .current_dir(dir)
+        .args(["--norc", "-c", &format!("setopt structured; {}", command)])
+        .output()
+        .expect("rsh runs");
+    (
+        output.status.code().unwrap_or(-1),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+fn files(test: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("rsh-{}-{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("big"), vec![b'x'; 6000]).unwrap();
+    fs::write(dir.join("small"), "small\n").unwrap();
+    dir
+}
+
+#[test]
+fn where_word_operators() {
+    let dir = files("where-words");
+    let cases = [
+        ("size -gt 5000", "big"),
+        ("size -ge 6000", "big"),
+        ("size -lt 5000", "small"),
+        ("size -le 6", "small"),
+        ("size -eq 6", "small"),
+        ("size == 6", "small"),
+        ("size -ne 6", "big"),
+        ("size != 6", "big"),
+        ("name =~ ^s", "small"),
+        ("size '>' 5000", "big"),
+    ];
+    for &(condition, name) in &cases {
+        let (status, stdout, stderr) =
+            rsh(&dir, &format!("ls | where {} | select name", condition));
+        assert_eq!(status, 0, "{}: {}", condition, stderr);
+        assert_eq!(
+            stdout,
+            format!("[{{\"name\":\"{}\"}}]\n", name),
+            "{}",
+            condition
+        );
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// A bare > is a redirection, which leaves where too few arguments
+#[test]
+fn where_with_a_bare_comparison_is_a_usage_error() {
+    let dir = files("where-bare");
+    let (status, stdout, stderr) = rsh(&dir, "ls | where size > 5000");
+    assert_eq!(status, 2);
+    assert_eq!(stdout, "");
+    assert!(
+        stderr.contains("usage: where column operator value: quote > and <, or use -gt and -lt"),
+        "{}",
+        stderr
+    );
+    let (status, _, stderr) = rsh(&dir, "ls | where size 5000");
+    assert_eq!(status, 2);
+    assert!(stderr.contains("usage: where column operator value, with -eq"), "{}", stderr);
+    let (status, _, stderr) = rsh(&dir, "ls | where size -bigger 5000");
+    assert_eq!(status, 2);
+    assert!(stderr.contains("-bigger: unknown operator"), "{}", stderr);
+    fs::remove_dir_all(&dir).unwrap();
+}